@@ -4,17 +4,12 @@ import('../wasm/').then(async ({ RustWebGLEngine }) => {
 	const rightButton = document.getElementById('right-button')
 
 	try {
-		const engine = await new RustWebGLEngine(canvas)
+		const engine = await new RustWebGLEngine(canvas, { defaultScene: true })
 
 		leftButton.addEventListener('click', () => engine.rotateCameraLeft())
 		rightButton.addEventListener('click', () => engine.rotateCameraRight())
 
-		const render = () => {
-			engine.render()
-			window.requestAnimationFrame(render)
-		}
-
-		window.requestAnimationFrame(render)
+		engine.start()
 	} catch (e) {
 		console.log(e)
 	}
@@ -22,11 +17,15 @@ import('../wasm/').then(async ({ RustWebGLEngine }) => {
 
 // TODO: Either move this out or just do it all in Rust now
 // that I understand it a little better
-export const loadTextureImage = (gl, texture, sourceUrl) => {
-	let image = new Image()
-	image.src = sourceUrl
-	image.addEventListener('load', () => {
-		gl.bindTexture(gl.TEXTURE_2D, texture)
-		gl.texImage2D(gl.TEXTURE_2D, 0, gl.RGBA, gl.RGBA, gl.UNSIGNED_BYTE, image)
+// Resolves once the texture has the image
+export const loadTextureImage = (gl, texture, sourceUrl) =>
+	new Promise((resolve, reject) => {
+		let image = new Image()
+		image.src = sourceUrl
+		image.addEventListener('load', () => {
+			gl.bindTexture(gl.TEXTURE_2D, texture)
+			gl.texImage2D(gl.TEXTURE_2D, 0, gl.RGBA, gl.RGBA, gl.UNSIGNED_BYTE, image)
+			resolve()
+		})
+		image.addEventListener('error', reject)
 	})
-}
@@ -0,0 +1,584 @@
+// glTF animation clips and their playback. Clips pose a copy of the rest
+// pose, several playing at once (while cross-fading) are blended by weight.
+// Plain Rust with no WebGL.
+
+use crate::skin::Skeleton;
+use nalgebra_glm as na;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Interpolation {
+    Linear,
+    Step,
+    // Each key has an in-tangent, the value and an out-tangent, in that order
+    CubicSpline,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Property {
+    Translation,
+    Rotation,
+    Scale,
+    Weights,
+}
+
+// Keyframes for one property of one node
+pub struct Channel {
+    pub node: usize,
+    pub property: Property,
+    pub interpolation: Interpolation,
+    // In seconds, increasing
+    pub times: Vec<f32>,
+    // Flat, XYZW for rotations and one float per morph target for weights
+    pub values: Vec<f32>,
+}
+
+impl Channel {
+    pub fn sample(&self, time: f32) -> Vec<f32> {
+        let keys = self.times.len();
+        let cubic = self.interpolation == Interpolation::CubicSpline;
+        let size = self.values.len() / keys / if cubic { 3 } else { 1 };
+        // Part of key k: 0 for the in-tangent, 1 the value and 2 the out-tangent
+        let part = |k: usize, part: usize| -> &[f32] {
+            let start = if cubic {
+                (k * 3 + part) * size
+            } else {
+                k * size
+            };
+            &self.values[start..start + size]
+        };
+        let value = |k| part(k, 1);
+
+        if time <= self.times[0] {
+            return value(0).to_vec();
+        }
+        if time >= self.times[keys - 1] {
+            return value(keys - 1).to_vec();
+        }
+        let k = self.times.partition_point(|&t| t <= time) - 1;
+        let dt = self.times[k + 1] - self.times[k];
+        let s = (time - self.times[k]) / dt;
+
+        match self.interpolation {
+            Interpolation::Step => value(k).to_vec(),
+            Interpolation::Linear if self.property == Property::Rotation => {
+                let q = slerp(&quat(value(k)), &quat(value(k + 1)), s);
+                q.coords.as_slice().to_vec()
+            }
+            Interpolation::Linear => value(k)
+                .iter()
+                .zip(value(k + 1))
+                .map(|(a, b)| a + (b - a) * s)
+                .collect(),
+            Interpolation::CubicSpline => {
+                let (s2, s3) = (s * s, s * s * s);
+                let values: Vec<f32> = (0..size)
+                    .map(|i| {
+                        (2. * s3 - 3. * s2 + 1.) * value(k)[i]
+                            + (s3 - 2. * s2 + s) * dt * part(k, 2)[i]
+                            + (-2. * s3 + 3. * s2) * value(k + 1)[i]
+                            + (s3 - s2) * dt * part(k + 1, 0)[i]
+                    })
+                    .collect();
+                if self.property == Property::Rotation {
+                    na::quat_normalize(&quat(&values))
+                        .coords
+                        .as_slice()
+                        .to_vec()
+                } else {
+                    values
+                }
+            }
+        }
+    }
+
+    fn apply(&self, time: f32, pose: &mut Skeleton) {
+        let value = self.sample(time);
+        let node = &mut pose.nodes[self.node];
+        match self.property {
+            Property::Translation => node.translation = na::make_vec3(&value),
+            Property::Rotation => node.rotation = quat(&value),
+            Property::Scale => node.scale = na::make_vec3(&value),
+            Property::Weights => node.weights = value,
+        }
+    }
+}
+
+pub struct Clip {
+    // Unnamed clips go by their index
+    pub name: String,
+    pub channels: Vec<Channel>,
+    // Time of the last key of any channel
+    pub duration: f32,
+}
+
+impl Clip {
+    pub fn new(name: String, channels: Vec<Channel>) -> Clip {
+        let duration = channels
+            .iter()
+            .filter_map(|channel| channel.times.last().copied())
+            .fold(0., f32::max);
+        Clip {
+            name,
+            channels,
+            duration,
+        }
+    }
+
+    pub fn from_gltf(
+        animation: &gltf::Animation,
+        buffers: &[gltf::buffer::Data],
+    ) -> Result<Clip, String> {
+        use gltf::animation::util::{MorphTargetWeights, ReadOutputs};
+
+        let name = animation
+            .name()
+            .map_or_else(|| animation.index().to_string(), String::from);
+        let mut channels = Vec::new();
+        for channel in animation.channels() {
+            let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+            let times: Vec<f32> = reader
+                .read_inputs()
+                .ok_or_else(|| format!("Animation {} has a channel with no times", name))?
+                .collect();
+            let (property, values) = match reader
+                .read_outputs()
+                .ok_or_else(|| format!("Animation {} has a channel with no values", name))?
+            {
+                ReadOutputs::Translations(iter) => {
+                    (Property::Translation, iter.flatten().collect())
+                }
+                ReadOutputs::Rotations(iter) => {
+                    (Property::Rotation, iter.into_f32().flatten().collect())
+                }
+                ReadOutputs::Scales(iter) => (Property::Scale, iter.flatten().collect()),
+                // Not into_f32, which divides u8 by 32767 in gltf 0.15
+                ReadOutputs::MorphTargetWeights(MorphTargetWeights::U8(iter)) => {
+                    (Property::Weights, iter.map(|x| x as f32 / 255.).collect())
+                }
+                ReadOutputs::MorphTargetWeights(iter) => {
+                    (Property::Weights, iter.into_f32().collect())
+                }
+            };
+            if times.is_empty() {
+                continue;
+            }
+            channels.push(Channel {
+                node: channel.target().node().index(),
+                property,
+                interpolation: match channel.sampler().interpolation() {
+                    gltf::animation::Interpolation::Linear => Interpolation::Linear,
+                    gltf::animation::Interpolation::Step => Interpolation::Step,
+                    gltf::animation::Interpolation::CubicSpline => Interpolation::CubicSpline,
+                },
+                times,
+                values,
+            });
+        }
+        Ok(Clip::new(name, channels))
+    }
+
+    // Sets whatever the clip animates, leaving the rest of the pose alone
+    pub fn apply(&self, time: f32, pose: &mut Skeleton) {
+        for channel in &self.channels {
+            channel.apply(time, pose);
+        }
+    }
+}
+
+// The node hierarchy a glTF mesh came with and the clips that animate it
+pub struct Rig {
+    pub rest_pose: Skeleton,
+    // The node the mesh hangs from
+    pub mesh_node: usize,
+    pub clips: Vec<Clip>,
+}
+
+impl Rig {
+    pub fn from_gltf(
+        document: &gltf::Document,
+        mesh_node: &gltf::Node,
+        buffers: &[gltf::buffer::Data],
+    ) -> Result<Rig, String> {
+        // A weight per morph target, zero unless the file says otherwise
+        let mut rest_pose = Skeleton::from_gltf(document);
+        let targets = mesh_node
+            .mesh()
+            .and_then(|mesh| mesh.primitives().next())
+            .map_or(0, |primitive| primitive.morph_targets().len());
+        rest_pose.nodes[mesh_node.index()]
+            .weights
+            .resize(targets, 0.);
+
+        Ok(Rig {
+            rest_pose,
+            mesh_node: mesh_node.index(),
+            clips: document
+                .animations()
+                .map(|animation| Clip::from_gltf(&animation, buffers))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    pub fn clip(&self, name: &str) -> Option<&Clip> {
+        self.clips.iter().find(|clip| clip.name == name)
+    }
+}
+
+// A clip being played, with how much it counts in the blend
+struct Layer {
+    clip: String,
+    time: f32,
+    looping: bool,
+    weight: f32,
+    // Where the weight is fading to and how fast, per second
+    target_weight: f32,
+    fade_rate: f32,
+}
+
+impl Layer {
+    // Wrapped or held at the end
+    fn clip_time(&self, duration: f32) -> f32 {
+        if duration <= 0. {
+            0.
+        } else if self.looping {
+            self.time.rem_euclid(duration)
+        } else {
+            self.time.min(duration)
+        }
+    }
+}
+
+// Playback state for one object. Clips are looked up by name when posing, so
+// they can be played before the model has loaded.
+pub struct Animator {
+    // Oldest first, the last one is the clip that was played last
+    layers: Vec<Layer>,
+    pub paused: bool,
+    pub speed: f32,
+}
+
+impl Default for Animator {
+    fn default() -> Animator {
+        Animator {
+            layers: Vec::new(),
+            paused: false,
+            speed: 1.,
+        }
+    }
+}
+
+impl Animator {
+    // Fades from whatever is playing to the clip over fade seconds, or cuts
+    // to it with no fade
+    pub fn play(&mut self, clip: &str, looping: bool, fade: f32) {
+        if fade <= 0. {
+            self.layers.clear();
+        }
+        let fade_rate = if fade > 0. { 1. / fade } else { 0. };
+        for layer in &mut self.layers {
+            layer.target_weight = 0.;
+            layer.fade_rate = fade_rate;
+        }
+        self.layers.push(Layer {
+            clip: clip.to_string(),
+            time: 0.,
+            looping,
+            weight: if fade > 0. { 0. } else { 1. },
+            target_weight: 1.,
+            fade_rate,
+        });
+    }
+
+    // Back to the rest pose
+    pub fn stop(&mut self) {
+        self.layers.clear();
+    }
+
+    pub fn update(&mut self, delta: f32) {
+        if self.paused {
+            return;
+        }
+        for layer in &mut self.layers {
+            layer.time += delta * self.speed;
+            let step = layer.fade_rate * delta;
+            layer.weight = if layer.weight < layer.target_weight {
+                (layer.weight + step).min(layer.target_weight)
+            } else {
+                (layer.weight - step).max(layer.target_weight)
+            };
+        }
+        self.layers
+            .retain(|layer| layer.weight > 0. || layer.target_weight > 0.);
+    }
+
+    // Whether the pose changes with time. Clips that don't loop hold their
+    // last frame once they're done.
+    pub fn is_moving(&self, rig: &Rig) -> bool {
+        !self.paused
+            && self.layers.iter().any(|layer| {
+                layer.looping
+                    || layer.weight != layer.target_weight
+                    || rig
+                        .clip(&layer.clip)
+                        .is_some_and(|clip| layer.time < clip.duration)
+            })
+    }
+
+    // None when nothing is playing. Whatever weight the layers leave, like
+    // while fading in from nothing, goes to the rest pose.
+    pub fn pose(&self, rig: &Rig) -> Option<Skeleton> {
+        let layers: Vec<(&Layer, &Clip)> = self
+            .layers
+            .iter()
+            .filter(|layer| layer.weight > 0.)
+            .filter_map(|layer| Some((layer, rig.clip(&layer.clip)?)))
+            .collect();
+        if layers.is_empty() {
+            return None;
+        }
+
+        let mut pose = rig.rest_pose.clone();
+        let mut total_weight =
+            (1. - layers.iter().map(|(layer, _)| layer.weight).sum::<f32>()).max(0.);
+        for (layer, clip) in layers {
+            let mut sampled = rig.rest_pose.clone();
+            clip.apply(layer.clip_time(clip.duration), &mut sampled);
+            // Each layer's share of everything blended so far
+            total_weight += layer.weight;
+            pose = blend(&pose, &sampled, layer.weight / total_weight);
+        }
+        Some(pose)
+    }
+}
+
+fn blend(a: &Skeleton, b: &Skeleton, t: f32) -> Skeleton {
+    let mut pose = a.clone();
+    for (node, other) in pose.nodes.iter_mut().zip(&b.nodes) {
+        node.translation = na::lerp(&node.translation, &other.translation, t);
+        node.rotation = slerp(&node.rotation, &other.rotation, t);
+        node.scale = na::lerp(&node.scale, &other.scale, t);
+        for (weight, other) in node.weights.iter_mut().zip(&other.weights) {
+            *weight += (other - *weight) * t;
+        }
+    }
+    pose
+}
+
+// XYZW like glTF
+fn quat(v: &[f32]) -> na::Quat {
+    na::quat(v[0], v[1], v[2], v[3])
+}
+
+// The short way around, and normalized lerp when they're nearly the same
+pub(crate) fn slerp(a: &na::Quat, b: &na::Quat, t: f32) -> na::Quat {
+    let b = if a.coords.dot(&b.coords) < 0. { -b } else { *b };
+    let dot = a.coords.dot(&b.coords).min(1.);
+    if dot > 0.9995 {
+        return na::quat_normalize(&na::quat_lerp(a, &b, t));
+    }
+    let angle = dot.acos();
+    (a * ((1. - t) * angle).sin() + b * (t * angle).sin()) / angle.sin()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::skin::Node;
+
+    fn channel(property: Property, interpolation: Interpolation, values: Vec<f32>) -> Channel {
+        Channel {
+            node: 0,
+            property,
+            interpolation,
+            times: vec![1., 2.],
+            values,
+        }
+    }
+
+    fn assert_close(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-5, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    fn rig(clips: Vec<Clip>) -> Rig {
+        Rig {
+            rest_pose: Skeleton {
+                nodes: vec![Node {
+                    parent: None,
+                    translation: na::vec3(0., 0., 0.),
+                    rotation: na::quat_identity(),
+                    scale: na::vec3(1., 1., 1.),
+                    weights: Vec::new(),
+                }],
+            },
+            mesh_node: 0,
+            clips,
+        }
+    }
+
+    // Moves node 0 along X from 0 to 1 in a second
+    fn slide(name: &str, y: f32) -> Clip {
+        let mut channel = channel(
+            Property::Translation,
+            Interpolation::Linear,
+            vec![0., y, 0., 1., y, 0.],
+        );
+        channel.times = vec![0., 1.];
+        Clip::new(name.to_string(), vec![channel])
+    }
+
+    #[test]
+    fn samples_linear_and_step() {
+        let values = vec![0., 0., 0., 2., 4., 6.];
+        let linear = channel(Property::Translation, Interpolation::Linear, values.clone());
+        assert_close(&linear.sample(1.5), &[1., 2., 3.]);
+        // Held before the first key and after the last
+        assert_close(&linear.sample(0.), &[0., 0., 0.]);
+        assert_close(&linear.sample(3.), &[2., 4., 6.]);
+
+        let step = channel(Property::Translation, Interpolation::Step, values);
+        assert_close(&step.sample(1.9), &[0., 0., 0.]);
+        assert_close(&step.sample(2.), &[2., 4., 6.]);
+    }
+
+    #[test]
+    fn slerps_rotations() {
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        let rotation = channel(
+            Property::Rotation,
+            Interpolation::Linear,
+            vec![0., 0., 0., 1., 0., 0., 1., 0.],
+        );
+        // Halfway through a half turn about Z is a quarter turn
+        assert_close(&rotation.sample(1.5), &[0., 0., half, half]);
+    }
+
+    #[test]
+    fn samples_cubic_splines() {
+        // Zero tangents ease in and out, so halfway is still halfway
+        let mut spline = channel(
+            Property::Weights,
+            Interpolation::CubicSpline,
+            vec![0., 0., 0., 0., 1., 0.],
+        );
+        assert_close(&spline.sample(1.5), &[0.5]);
+        assert!(spline.sample(1.25)[0] < 0.25);
+
+        // Out-tangent of 1 per second from the first key, with a straight
+        // line's tangents it's linear
+        spline.values = vec![0., 0., 1., 1., 1., 0.];
+        assert_close(&spline.sample(1.25), &[0.25]);
+    }
+
+    #[test]
+    fn reads_gltf_clips() {
+        let mut buffer = Vec::new();
+        for x in [0f32, 1., 0., 0., 0., 2., 0., 0.].iter() {
+            buffer.extend_from_slice(&x.to_le_bytes());
+        }
+        let document = gltf::Gltf::from_slice(
+            br#"{
+                "asset": { "version": "2.0" },
+                "nodes": [{}],
+                "buffers": [{ "byteLength": 32 }],
+                "bufferViews": [
+                    { "buffer": 0, "byteLength": 8 },
+                    { "buffer": 0, "byteOffset": 8, "byteLength": 24 }
+                ],
+                "accessors": [
+                    { "bufferView": 0, "componentType": 5126, "count": 2, "type": "SCALAR",
+                      "min": [0], "max": [1] },
+                    { "bufferView": 1, "componentType": 5126, "count": 2, "type": "VEC3" }
+                ],
+                "animations": [{
+                    "samplers": [{ "input": 0, "output": 1, "interpolation": "STEP" }],
+                    "channels": [{ "sampler": 0, "target": { "node": 0, "path": "translation" } }]
+                }]
+            }"#,
+        )
+        .unwrap()
+        .document;
+
+        let clip = Clip::from_gltf(
+            &document.animations().next().unwrap(),
+            &[gltf::buffer::Data(buffer)],
+        )
+        .unwrap();
+        assert_eq!(clip.name, "0");
+        assert_eq!(clip.duration, 1.);
+        assert_eq!(clip.channels[0].interpolation, Interpolation::Step);
+        assert_close(&clip.channels[0].sample(1.), &[2., 0., 0.]);
+    }
+
+    #[test]
+    fn loops_or_holds() {
+        let rig = rig(vec![slide("slide", 0.)]);
+        let mut animator = Animator::default();
+        animator.play("slide", true, 0.);
+        animator.update(1.25);
+        let x = animator.pose(&rig).unwrap().nodes[0].translation.x;
+        assert!((x - 0.25).abs() < 1e-5);
+
+        assert!(animator.is_moving(&rig));
+
+        animator.play("slide", false, 0.);
+        animator.update(0.5);
+        assert!(animator.is_moving(&rig));
+        animator.update(0.75);
+        let x = animator.pose(&rig).unwrap().nodes[0].translation.x;
+        assert!((x - 1.).abs() < 1e-5);
+        assert!(!animator.is_moving(&rig));
+
+        animator.stop();
+        assert!(animator.pose(&rig).is_none());
+    }
+
+    #[test]
+    fn pauses_and_scales_time() {
+        let rig = rig(vec![slide("slide", 0.)]);
+        let mut animator = Animator::default();
+        animator.play("slide", false, 0.);
+        animator.speed = 0.5;
+        animator.update(0.5);
+        animator.paused = true;
+        animator.update(0.5);
+        let x = animator.pose(&rig).unwrap().nodes[0].translation.x;
+        assert!((x - 0.25).abs() < 1e-5);
+    }
+
+    #[test]
+    fn cross_fades() {
+        let rig = rig(vec![slide("low", 0.), slide("high", 1.)]);
+        let mut animator = Animator::default();
+        animator.play("low", true, 0.);
+        animator.play("high", true, 1.);
+        animator.update(0.5);
+        let y = animator.pose(&rig).unwrap().nodes[0].translation.y;
+        assert!((y - 0.5).abs() < 1e-5);
+
+        // The old clip is dropped once it's faded out
+        animator.update(0.5);
+        assert_eq!(animator.layers.len(), 1);
+        let y = animator.pose(&rig).unwrap().nodes[0].translation.y;
+        assert!((y - 1.).abs() < 1e-5);
+    }
+
+    #[test]
+    fn fades_in_from_rest() {
+        let rig = rig(vec![slide("high", 1.)]);
+        let mut animator = Animator::default();
+        animator.play("high", true, 1.);
+        animator.update(0.25);
+        let y = animator.pose(&rig).unwrap().nodes[0].translation.y;
+        assert!((y - 0.25).abs() < 1e-5);
+
+        // Clips that aren't in the rig leave their weight to the rest pose too
+        animator.update(0.75);
+        animator.play("missing", true, 1.);
+        animator.update(0.5);
+        let y = animator.pose(&rig).unwrap().nodes[0].translation.y;
+        assert!((y - 0.5).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,250 @@
+use nalgebra_glm as na;
+
+#[derive(Clone, Copy)]
+pub struct Aabb {
+    pub min: na::Vec3,
+    pub max: na::Vec3,
+}
+
+impl Aabb {
+    pub fn center(&self) -> na::Vec3 {
+        (self.min + self.max) / 2.
+    }
+
+    pub fn extents(&self) -> na::Vec3 {
+        (self.max - self.min) / 2.
+    }
+
+    // Smallest box containing the transformed box (Arvo's method)
+    pub fn transform(&self, m: &na::Mat4) -> Aabb {
+        let center = m * na::vec4(self.center().x, self.center().y, self.center().z, 1.);
+        let linear = na::mat4_to_mat3(m).abs();
+        let extents = linear * self.extents();
+        Aabb {
+            min: center.xyz() - extents,
+            max: center.xyz() + extents,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Sphere {
+    pub center: na::Vec3,
+    pub radius: f32,
+}
+
+impl Sphere {
+    // Bounds the transformed sphere, scaling the radius by the largest axis scale
+    pub fn transform(&self, m: &na::Mat4) -> Sphere {
+        let center = m * na::vec4(self.center.x, self.center.y, self.center.z, 1.);
+        let linear = na::mat4_to_mat3(m);
+        let scale = (0..3)
+            .map(|i| na::length(&linear.column(i).into_owned()))
+            .fold(0., f32::max);
+        Sphere {
+            center: center.xyz(),
+            radius: self.radius * scale,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Bounds {
+    pub aabb: Aabb,
+    pub sphere: Sphere,
+}
+
+impl Bounds {
+    // Box from the min/max that glTF provides for POSITION, sphere around its
+    // center just big enough to contain every position
+    pub fn new(min: na::Vec3, max: na::Vec3, positions: &[f32]) -> Bounds {
+        let aabb = Aabb { min, max };
+        let center = aabb.center();
+        let radius = positions
+            .chunks_exact(3)
+            .map(|p| na::distance(&center, &na::vec3(p[0], p[1], p[2])))
+            .fold(0., f32::max);
+        Bounds {
+            aabb,
+            sphere: Sphere { center, radius },
+        }
+    }
+
+    pub fn from_positions(positions: &[f32]) -> Bounds {
+        let (min, max) = positions.chunks_exact(3).fold(
+            (
+                na::vec3(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+                na::vec3(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+            ),
+            |(min, max), p| {
+                let p = na::vec3(p[0], p[1], p[2]);
+                (na::min2(&min, &p), na::max2(&max, &p))
+            },
+        );
+        Bounds::new(min, max, positions)
+    }
+
+    pub fn transform(&self, m: &na::Mat4) -> Bounds {
+        Bounds {
+            aabb: self.aabb.transform(m),
+            sphere: self.sphere.transform(m),
+        }
+    }
+}
+
+pub struct Frustum {
+    // Plane equations (normal in xyz, distance in w) with normals facing in
+    planes: [na::Vec4; 6],
+}
+
+impl Frustum {
+    // Extract the planes from a view projection matrix (Gribb/Hartmann)
+    pub fn new(view_projection: &na::Mat4) -> Frustum {
+        let row = |i: usize| view_projection.row(i).transpose();
+        let planes = [
+            row(3) + row(0),
+            row(3) - row(0),
+            row(3) + row(1),
+            row(3) - row(1),
+            row(3) + row(2),
+            row(3) - row(2),
+        ];
+        Frustum {
+            planes: planes.map(|plane| plane / na::length(&plane.xyz())),
+        }
+    }
+
+    // Conservative: may report bounds as visible when they're just outside a
+    // corner of the frustum, but never reports visible bounds as outside
+    pub fn intersects(&self, bounds: &Bounds) -> bool {
+        self.intersects_sphere(&bounds.sphere) && self.intersects_aabb(&bounds.aabb)
+    }
+
+    pub fn intersects_sphere(&self, sphere: &Sphere) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| signed_distance(plane, &sphere.center) >= -sphere.radius)
+    }
+
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        let center = aabb.center();
+        let extents = aabb.extents();
+        self.planes.iter().all(|plane| {
+            let radius = na::dot(&plane.xyz().abs(), &extents);
+            signed_distance(plane, &center) >= -radius
+        })
+    }
+}
+
+fn signed_distance(plane: &na::Vec4, point: &na::Vec3) -> f32 {
+    na::dot(&plane.xyz(), point) + plane.w
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: &na::Vec4, b: &na::Vec4) {
+        assert!(na::distance(a, b) < 1e-3, "{:?} != {:?}", a, b);
+    }
+
+    fn aabb(center: na::Vec3, half_size: f32) -> Aabb {
+        let extents = na::vec3(half_size, half_size, half_size);
+        Aabb {
+            min: center - extents,
+            max: center + extents,
+        }
+    }
+
+    // 90 degrees each way, looking down -Z from the origin, 1 to 100 away
+    fn frustum() -> Frustum {
+        Frustum::new(&na::perspective(1., std::f32::consts::FRAC_PI_2, 1., 100.))
+    }
+
+    #[test]
+    fn extracts_planes() {
+        let s = std::f32::consts::FRAC_1_SQRT_2;
+        let planes = frustum().planes;
+        // Left, right, bottom and top through the camera, at 45 degrees
+        assert_close(&planes[0], &na::vec4(s, 0., -s, 0.));
+        assert_close(&planes[1], &na::vec4(-s, 0., -s, 0.));
+        assert_close(&planes[2], &na::vec4(0., s, -s, 0.));
+        assert_close(&planes[3], &na::vec4(0., -s, -s, 0.));
+        // Near and far
+        assert_close(&planes[4], &na::vec4(0., 0., -1., -1.));
+        assert_close(&planes[5], &na::vec4(0., 0., 1., 100.));
+
+        // Orthographic, with a view moving it
+        let view = na::translation(&na::vec3(-10., 0., 0.));
+        let planes = Frustum::new(&(na::ortho(-1., 1., -2., 2., 0., 10.) * view)).planes;
+        assert_close(&planes[0], &na::vec4(1., 0., 0., -9.));
+        assert_close(&planes[1], &na::vec4(-1., 0., 0., 11.));
+        assert_close(&planes[3], &na::vec4(0., -1., 0., 2.));
+    }
+
+    #[test]
+    fn tests_spheres() {
+        let frustum = frustum();
+        let sphere = |x, z, radius| Sphere {
+            center: na::vec3(x, 0., z),
+            radius,
+        };
+        assert!(frustum.intersects_sphere(&sphere(0., -50., 1.)));
+        // Behind, beyond the far plane and off to the side
+        assert!(!frustum.intersects_sphere(&sphere(0., 10., 1.)));
+        assert!(!frustum.intersects_sphere(&sphere(0., -110., 5.)));
+        assert!(!frustum.intersects_sphere(&sphere(-30., -10., 5.)));
+        // Straddling the near, far and left planes
+        assert!(frustum.intersects_sphere(&sphere(0., 0., 2.)));
+        assert!(frustum.intersects_sphere(&sphere(0., -105., 6.)));
+        assert!(frustum.intersects_sphere(&sphere(-12., -10., 2.)));
+    }
+
+    #[test]
+    fn tests_boxes() {
+        let frustum = frustum();
+        assert!(frustum.intersects_aabb(&aabb(na::vec3(0., 0., -50.), 1.)));
+        // Containing the whole frustum
+        assert!(frustum.intersects_aabb(&aabb(na::vec3(0., 0., 0.), 500.)));
+        assert!(!frustum.intersects_aabb(&aabb(na::vec3(0., 0., 10.), 1.)));
+        assert!(!frustum.intersects_aabb(&aabb(na::vec3(0., 30., -10.), 5.)));
+        // Straddling the top plane
+        assert!(frustum.intersects_aabb(&aabb(na::vec3(0., 12., -10.), 3.)));
+
+        let bounds = Bounds::from_positions(&[-1., -1., -1., 1., 1., 1.]);
+        let inside = na::translation(&na::vec3(0., 0., -20.));
+        let outside = na::translation(&na::vec3(0., 0., 20.));
+        assert!(frustum.intersects(&bounds.transform(&inside)));
+        assert!(!frustum.intersects(&bounds.transform(&outside)));
+    }
+
+    #[test]
+    fn transforms_bounds() {
+        let bounds = Bounds::from_positions(&[0., 0., 0., 2., 2., 2.]);
+        assert_eq!(bounds.aabb.center(), na::vec3(1., 1., 1.));
+        assert!((bounds.sphere.radius - 3_f32.sqrt()).abs() < 1e-5);
+
+        // Quarter turn about Y, scaled unevenly, then moved
+        let m = na::translation(&na::vec3(10., 0., 0.))
+            * na::rotation(std::f32::consts::FRAC_PI_2, &na::vec3(0., 1., 0.))
+            * na::scaling(&na::vec3(1., 2., 3.));
+        let transformed = bounds.transform(&m);
+        let aabb = transformed.aabb;
+        // The box's X comes from Z scaled by 3, and its Z from -X
+        let close = |a: na::Vec3, b: na::Vec3| na::distance(&a, &b) < 1e-5;
+        assert!(close(aabb.min, na::vec3(10., 0., -2.)), "{}", aabb.min);
+        assert!(close(aabb.max, na::vec3(16., 4., 0.)), "{}", aabb.max);
+        // The radius grows by the largest scale
+        let sphere = transformed.sphere;
+        assert!(close(sphere.center, na::vec3(13., 2., -1.)));
+        assert!((sphere.radius - 3. * 3_f32.sqrt()).abs() < 1e-4);
+
+        // 45 degrees about Z grows the box to hold the corners
+        let m = na::rotation(std::f32::consts::FRAC_PI_4, &na::vec3(0., 0., 1.));
+        let aabb = Bounds::from_positions(&[-1., -1., -1., 1., 1., 1.])
+            .transform(&m)
+            .aabb;
+        let s = 2_f32.sqrt();
+        assert!(close(aabb.max, na::vec3(s, s, 1.)), "{}", aabb.max);
+    }
+}
@@ -0,0 +1,164 @@
+// Where the scene is drawn from. Plain Rust, shared by the renderer and the
+// rasterizer.
+
+use nalgebra_glm as na;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Camera {
+    pub position: na::Vec3,
+    pub rotation: na::Quat,
+    // Vertical, in radians
+    pub fov: f32,
+    pub near: f32,
+    pub far: f32,
+    // The height of the view in world units, instead of a perspective
+    pub orthographic: Option<f32>,
+}
+
+impl Camera {
+    pub fn new(position: na::Vec3, direction: na::Vec3) -> Camera {
+        Camera {
+            position,
+            rotation: rotation_towards(&direction),
+            fov: 60. * (std::f32::consts::PI / 180.),
+            near: 1.,
+            far: 2000.,
+            orthographic: None,
+        }
+    }
+
+    // Turns it to face the point, with up as close to +Y as it can be
+    pub fn look_at(&mut self, point: &na::Vec3) {
+        let direction = point - self.position;
+        if direction != na::Vec3::zeros() {
+            self.rotation = rotation_towards(&direction);
+        }
+    }
+
+    // fov in degrees
+    pub fn set_perspective(&mut self, fov: f32, near: f32, far: f32) -> Result<(), String> {
+        if !(fov > 0. && fov < 180.) {
+            return Err(format!(
+                "Field of view must be 0 to 180 degrees, not {}",
+                fov
+            ));
+        }
+        check_range(near, far, true)?;
+        self.fov = fov * (std::f32::consts::PI / 180.);
+        self.near = near;
+        self.far = far;
+        self.orthographic = None;
+        Ok(())
+    }
+
+    // height in world units
+    pub fn set_orthographic(&mut self, height: f32, near: f32, far: f32) -> Result<(), String> {
+        if !(height > 0. && height.is_finite()) {
+            return Err(format!(
+                "Orthographic height must be over 0, not {}",
+                height
+            ));
+        }
+        check_range(near, far, false)?;
+        self.near = near;
+        self.far = far;
+        self.orthographic = Some(height);
+        Ok(())
+    }
+
+    pub fn view(&self) -> na::Mat4 {
+        na::inverse(&(na::translation(&self.position) * na::quat_to_mat4(&self.rotation)))
+    }
+
+    // For a view aspect wide per unit high
+    pub fn projection(&self, aspect: f32) -> na::Mat4 {
+        match self.orthographic {
+            Some(height) => {
+                let (x, y) = (height * aspect / 2., height / 2.);
+                na::ortho(-x, x, -y, y, self.near, self.far)
+            }
+            None => na::perspective(aspect, self.fov, self.near, self.far),
+        }
+    }
+}
+
+// Perspectives can't start at the camera
+fn check_range(near: f32, far: f32, perspective: bool) -> Result<(), String> {
+    let near_ok = if perspective { near > 0. } else { near >= 0. };
+    if !(near_ok && far > near && far.is_finite()) {
+        return Err(format!(
+            "Camera range {} to {} must be increasing{}",
+            near,
+            far,
+            if perspective { ", from over 0" } else { "" }
+        ));
+    }
+    Ok(())
+}
+
+// Straight up or down there's no telling which way is up, so +Z is used
+fn rotation_towards(direction: &na::Vec3) -> na::Quat {
+    let direction = na::normalize(direction);
+    let up = if na::cross(&direction, &na::vec3(0., 1., 0.)).norm() < 1e-6 {
+        na::vec3(0., 0., 1.)
+    } else {
+        na::vec3(0., 1., 0.)
+    };
+    na::quat_inverse(&na::quat_look_at(&direction, &up))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: &na::Vec3, b: &na::Vec3) {
+        assert!(na::distance(a, b) < 1e-5, "{} != {}", a, b);
+    }
+
+    // Where a world position ends up in normalized device coordinates
+    fn project(camera: &Camera, point: na::Vec3) -> na::Vec3 {
+        let clip = camera.projection(2.) * camera.view() * na::vec4(point.x, point.y, point.z, 1.);
+        na::vec3(clip.x / clip.w, clip.y / clip.w, clip.z / clip.w)
+    }
+
+    #[test]
+    fn looks_at_points() {
+        let mut camera = Camera::new(na::vec3(0., 0., 10.), na::vec3(0., 0., -1.));
+        camera.look_at(&na::vec3(10., 0., 10.));
+        let center = project(&camera, na::vec3(20., 0., 10.));
+        assert_close(&na::vec3(center.x, center.y, 0.), &na::vec3(0., 0., 0.));
+
+        // Straight down
+        camera.look_at(&na::vec3(0., -10., 10.));
+        let center = project(&camera, na::vec3(0., -5., 10.));
+        assert_close(&na::vec3(center.x, center.y, 0.), &na::vec3(0., 0., 0.));
+        assert!(camera.rotation.coords.iter().all(|x| x.is_finite()));
+    }
+
+    #[test]
+    fn checks_projections() {
+        let mut camera = Camera::new(na::vec3(0., 0., 0.), na::vec3(0., 0., -1.));
+        assert!(camera.set_orthographic(10., 0., 100.).is_ok());
+        assert_eq!(camera.orthographic, Some(10.));
+        assert!(camera.set_perspective(90., 0., 100.).is_err());
+        assert!(camera.set_perspective(180., 1., 100.).is_err());
+        assert!(camera.set_perspective(90., 10., 10.).is_err());
+        assert!(camera.set_orthographic(f32::NAN, 1., 100.).is_err());
+        assert!(camera.set_perspective(90., 1., 100.).is_ok());
+        assert_eq!(camera.orthographic, None);
+        assert!((camera.fov - std::f32::consts::FRAC_PI_2).abs() < 1e-6);
+    }
+
+    #[test]
+    fn projects_orthographically() {
+        let camera = Camera {
+            orthographic: Some(10.),
+            ..Camera::new(na::vec3(0., 0., 0.), na::vec3(0., 0., -1.))
+        };
+        // Twice as wide as high, the same size at any distance
+        for &z in &[-10., -100.] {
+            let corner = project(&camera, na::vec3(10., 5., z));
+            assert_close(&na::vec3(corner.x, corner.y, 0.), &na::vec3(1., 1., 0.));
+        }
+    }
+}
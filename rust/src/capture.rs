@@ -0,0 +1,98 @@
+// Turning pixels read back from a framebuffer into images. Plain Rust, the
+// renderer hands in the RGBA bytes.
+
+use image::png::PngEncoder;
+use image::ColorType;
+
+// The largest capture each way, before supersampling
+pub const MAX_SIZE: u32 = 8192;
+pub const MAX_SUPERSAMPLE: u32 = 4;
+
+// Checks a capture size and supersampling factor, returning the size to
+// render at
+pub fn render_size(width: u32, height: u32, supersample: u32) -> Result<(u32, u32), String> {
+    if width == 0 || height == 0 || width > MAX_SIZE || height > MAX_SIZE {
+        return Err(format!(
+            "Capture size must be 1 to {} pixels each way, not {}x{}",
+            MAX_SIZE, width, height
+        ));
+    }
+    if !(1..=MAX_SUPERSAMPLE).contains(&supersample) {
+        return Err(format!(
+            "Supersampling must be 1 to {}, not {}",
+            MAX_SUPERSAMPLE, supersample
+        ));
+    }
+    Ok((width * supersample, height * supersample))
+}
+
+// Averages each factor x factor block, for smoother edges than the
+// framebuffer gives
+pub fn downsample(pixels: &[u8], width: u32, height: u32, factor: u32) -> Vec<u8> {
+    if factor == 1 {
+        return pixels.to_vec();
+    }
+    let (width, height, factor) = (width as usize, height as usize, factor as usize);
+    let (out_width, out_height) = (width / factor, height / factor);
+    let mut out = Vec::with_capacity(out_width * out_height * 4);
+    for y in 0..out_height {
+        for x in 0..out_width {
+            let mut sum = [0u32; 4];
+            for sy in y * factor..(y + 1) * factor {
+                for sx in x * factor..(x + 1) * factor {
+                    let i = (sy * width + sx) * 4;
+                    for (c, total) in sum.iter_mut().enumerate() {
+                        *total += pixels[i + c] as u32;
+                    }
+                }
+            }
+            let samples = (factor * factor) as u32;
+            out.extend(
+                sum.iter()
+                    .map(|total| ((total + samples / 2) / samples) as u8),
+            );
+        }
+    }
+    out
+}
+
+pub fn encode_png(pixels: &[u8], width: u32, height: u32) -> Result<Vec<u8>, String> {
+    let mut png = Vec::new();
+    PngEncoder::new(&mut png)
+        .encode(pixels, width, height, ColorType::Rgba8)
+        .map_err(|e| e.to_string())?;
+    Ok(png)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_sizes() {
+        assert_eq!(render_size(256, 128, 2), Ok((512, 256)));
+        assert!(render_size(0, 128, 1).is_err());
+        assert!(render_size(MAX_SIZE + 1, 128, 1).is_err());
+        assert!(render_size(256, 128, 0).is_err());
+        assert!(render_size(256, 128, MAX_SUPERSAMPLE + 1).is_err());
+    }
+
+    #[test]
+    fn downsamples_by_averaging() {
+        // 2x2 of black, white, white, white
+        let pixels = [
+            0, 0, 0, 0, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255,
+        ];
+        assert_eq!(downsample(&pixels, 2, 2, 2), vec![191, 191, 191, 191]);
+        assert_eq!(downsample(&pixels, 2, 2, 1), pixels.to_vec());
+    }
+
+    #[test]
+    fn encodes_pngs() {
+        let pixels = [255, 0, 0, 255, 0, 255, 0, 128];
+        let png = encode_png(&pixels, 2, 1).unwrap();
+        let image = image::load_from_memory(&png).unwrap().to_rgba8();
+        assert_eq!(image.dimensions(), (2, 1));
+        assert_eq!(image.into_raw(), pixels.to_vec());
+    }
+}
@@ -0,0 +1,164 @@
+// Frame timing for everything that moves. Plain Rust, the time comes from a
+// source handed in, so tests and offline captures can drive it.
+
+// Seconds since some fixed point, like performance.now() / 1000
+pub type TimeSource = Box<dyn Fn() -> f64>;
+
+pub struct Clock {
+    source: TimeSource,
+    // The source's time at the last tick
+    last: Option<f64>,
+    pub paused: bool,
+    // 2 is double speed
+    pub time_scale: f32,
+    // Every frame advances by this many seconds, whatever the source says
+    pub fixed_step: Option<f32>,
+    // Advanced by the next tick even when paused
+    step: Option<f32>,
+    delta: f32,
+    elapsed: f64,
+    frame: u64,
+}
+
+impl Clock {
+    pub fn new(source: TimeSource) -> Clock {
+        Clock {
+            source,
+            last: None,
+            paused: false,
+            time_scale: 1.,
+            fixed_step: None,
+            step: None,
+            delta: 0.,
+            elapsed: 0.,
+            frame: 0,
+        }
+    }
+
+    // Carries on from the elapsed time so far, without a jump
+    pub fn set_source(&mut self, source: TimeSource) {
+        self.source = source;
+        self.last = None;
+    }
+
+    // Once per frame, before anything reads the time
+    pub fn tick(&mut self) {
+        let now = (self.source)();
+        // Sources that go wrong or backwards stand still
+        let real_delta = match self.last {
+            Some(last) if now.is_finite() => (now - last).max(0.) as f32,
+            _ => 0.,
+        };
+        if now.is_finite() {
+            self.last = Some(now);
+        }
+
+        self.delta = match self.step.take() {
+            Some(step) => step,
+            None if self.paused => 0.,
+            None => self.fixed_step.unwrap_or(real_delta) * self.time_scale,
+        };
+        self.elapsed += self.delta as f64;
+        self.frame += 1;
+    }
+
+    // Advances the next frame by seconds, paused or not
+    pub fn step(&mut self, seconds: f32) {
+        self.step = Some(seconds);
+    }
+
+    // Seconds since the last frame, scaled and 0 while paused
+    pub fn delta(&self) -> f32 {
+        self.delta
+    }
+
+    // Seconds of the deltas so far
+    pub fn elapsed(&self) -> f64 {
+        self.elapsed
+    }
+
+    // Frames ticked so far
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    fn manual() -> (Clock, Rc<Cell<f64>>) {
+        let time = Rc::new(Cell::new(100.));
+        let source = Rc::clone(&time);
+        (Clock::new(Box::new(move || source.get())), time)
+    }
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-6, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn follows_the_source() {
+        let (mut clock, time) = manual();
+        // Nothing to measure from on the first frame
+        clock.tick();
+        assert_eq!(clock.delta(), 0.);
+        time.set(100.5);
+        clock.tick();
+        assert_close(clock.delta() as f64, 0.5);
+        time.set(101.);
+        clock.tick();
+        assert_close(clock.elapsed(), 1.);
+        assert_eq!(clock.frame(), 3);
+    }
+
+    #[test]
+    fn pauses_and_scales() {
+        let (mut clock, time) = manual();
+        clock.tick();
+        clock.paused = true;
+        time.set(101.);
+        clock.tick();
+        assert_eq!(clock.delta(), 0.);
+        // Stepping while paused
+        clock.step(0.1);
+        time.set(102.);
+        clock.tick();
+        assert_close(clock.delta() as f64, 0.1);
+        clock.paused = false;
+        clock.time_scale = 0.5;
+        time.set(103.);
+        clock.tick();
+        assert_close(clock.delta() as f64, 0.5);
+        assert_close(clock.elapsed(), 0.6);
+    }
+
+    #[test]
+    fn fixed_steps_ignore_the_source() {
+        let (mut clock, time) = manual();
+        clock.fixed_step = Some(1. / 60.);
+        for i in 0..60 {
+            time.set(100. + i as f64 * 0.3);
+            clock.tick();
+        }
+        assert_close(clock.elapsed(), 1.);
+    }
+
+    #[test]
+    fn changing_source_does_not_jump() {
+        let (mut clock, time) = manual();
+        clock.tick();
+        time.set(101.);
+        clock.tick();
+        clock.set_source(Box::new(|| 5000.));
+        clock.tick();
+        assert_eq!(clock.delta(), 0.);
+        assert_close(clock.elapsed(), 1.);
+        // Nor do broken or backwards sources
+        clock.set_source(Box::new(|| f64::NAN));
+        clock.tick();
+        assert_eq!(clock.delta(), 0.);
+    }
+}
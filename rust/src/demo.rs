@@ -0,0 +1,82 @@
+// The default scene: eight textured blocks in a ring around the camera,
+// spinning
+
+use super::material::*;
+use super::mesh::Mesh;
+use super::model::Model;
+use super::object::Object;
+use super::renderer::Renderer;
+use super::utils::load_texture;
+use nalgebra_glm as na;
+use std::rc::Rc;
+use web_sys::WebGlRenderingContext as GL;
+
+const BLOCKS: [&str; 8] = [
+    "yellow_glazed_terracotta",
+    "nether_gold_ore",
+    "redstone_block",
+    "obsidian",
+    "blackstone",
+    "white_glazed_terracotta",
+    "lime_glazed_terracotta",
+    "red_glazed_terracotta",
+];
+
+pub struct Demo {
+    // Object ids
+    blocks: Vec<u32>,
+}
+
+impl Demo {
+    // Adds the blocks to the renderer's scene
+    pub async fn load(gl: &GL, renderer: &mut Renderer) -> Demo {
+        let cube_mesh = Rc::new(
+            Mesh::load(
+                gl,
+                renderer.attributes(),
+                &renderer.options.asset_url("cube.gltf"),
+            )
+            .await,
+        );
+        let blocks = BLOCKS
+            .iter()
+            .enumerate()
+            .map(|(pos, texture_name)| {
+                let angle = std::f32::consts::PI * 2. / 8. * (pos as f32);
+                let position = na::vec3(600. * angle.cos(), 0., -(600. * angle.sin()));
+                let model = block(gl, renderer, &cube_mesh, texture_name);
+                renderer.add_model(model, position, 100.)
+            })
+            .collect();
+        Demo { blocks }
+    }
+
+    // Once every ten seconds
+    pub fn update(&self, objects: &mut [Object], elapsed: f64) {
+        let angle = std::f32::consts::PI * 2. * (elapsed % 10. / 10.) as f32;
+        for object in objects
+            .iter_mut()
+            .filter(|object| self.blocks.contains(&object.id))
+        {
+            object.rotation = na::rotation(angle, &na::vec3(0., 1., 0.));
+        }
+    }
+}
+
+fn block(gl: &GL, renderer: &Renderer, mesh: &Rc<Mesh>, texture_name: &str) -> Model {
+    let texture = |suffix: &str| {
+        let url = format!("textures/{}{}.png", texture_name, suffix);
+        Some(load_texture(gl, &renderer.options.asset_url(&url)))
+    };
+    Model {
+        mesh: Rc::clone(mesh),
+        material: Rc::new(Material {
+            color_map: texture(""),
+            specular_map: texture("_s"),
+            normal_map: texture("_n"),
+            ..Material::untextured(MaterialFactors::default())
+        }),
+        skin: None,
+        rig: None,
+    }
+}
@@ -0,0 +1,18 @@
+use nalgebra_glm as na;
+
+// Linear fog, from no fog at near to only fog color at far (distances from the
+// camera)
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Fog {
+    pub color: na::Vec3,
+    pub near: f32,
+    pub far: f32,
+}
+
+impl Fog {
+    // Same as apply_fog in fog.glsl
+    pub fn apply(&self, color: &na::Vec3, distance: f32) -> na::Vec3 {
+        let visibility = ((self.far - distance) / (self.far - self.near)).clamp(0., 1.);
+        na::lerp(&self.color, color, visibility)
+    }
+}
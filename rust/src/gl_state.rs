@@ -0,0 +1,127 @@
+use wasm_bindgen::JsCast;
+use web_sys::WebGlRenderingContext as GL;
+use web_sys::{OesVertexArrayObject, WebGlProgram, WebGlTexture, WebGlVertexArrayObject};
+
+pub const TEXTURE_UNITS: usize = 8;
+
+// Tracks the state set through it so that redundant binds can be skipped, and
+// holds on to extension objects so they're only queried once
+//
+// Anything that changes bindings behind its back (texture uploads, mesh
+// creation) happens between frames, so reset must be called at the start of
+// every frame
+pub struct GlState {
+    vao_ext: OesVertexArrayObject,
+    program: Option<WebGlProgram>,
+    active_texture: Option<u32>,
+    textures: [Option<WebGlTexture>; TEXTURE_UNITS],
+    vertex_array: Option<WebGlVertexArrayObject>,
+    cull_face: Option<bool>,
+    blend: Option<bool>,
+    depth_mask: Option<bool>,
+}
+
+impl GlState {
+    pub fn new(gl: &GL) -> GlState {
+        // Keep destination alpha as is, so the canvas stays opaque
+        gl.blend_func_separate(GL::SRC_ALPHA, GL::ONE_MINUS_SRC_ALPHA, GL::ZERO, GL::ONE);
+
+        GlState {
+            vao_ext: gl
+                .get_extension("OES_vertex_array_object")
+                .unwrap()
+                .unwrap()
+                .unchecked_into::<OesVertexArrayObject>(),
+            program: None,
+            active_texture: None,
+            textures: Default::default(),
+            vertex_array: None,
+            cull_face: None,
+            blend: None,
+            depth_mask: None,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.program = None;
+        self.active_texture = None;
+        self.textures = Default::default();
+        self.vertex_array = None;
+        self.cull_face = None;
+        self.blend = None;
+        self.depth_mask = None;
+    }
+
+    // Before drawing into a texture, which mustn't be bound while it is
+    pub fn unbind_textures(&mut self, gl: &GL) {
+        for unit in 0..TEXTURE_UNITS {
+            gl.active_texture(GL::TEXTURE0 + unit as u32);
+            gl.bind_texture(GL::TEXTURE_2D, None);
+            self.textures[unit] = None;
+        }
+        self.active_texture = Some(TEXTURE_UNITS as u32 - 1);
+    }
+
+    pub fn use_program(&mut self, gl: &GL, program: &WebGlProgram) {
+        if self.program.as_ref() != Some(program) {
+            gl.use_program(Some(program));
+            self.program = Some(program.clone());
+        }
+    }
+
+    pub fn bind_texture(&mut self, gl: &GL, unit: u32, texture: &WebGlTexture) {
+        if self.textures[unit as usize].as_ref() != Some(texture) {
+            if self.active_texture != Some(unit) {
+                gl.active_texture(GL::TEXTURE0 + unit);
+                self.active_texture = Some(unit);
+            }
+            gl.bind_texture(GL::TEXTURE_2D, Some(texture));
+            self.textures[unit as usize] = Some(texture.clone());
+        }
+    }
+
+    // Also leaves the unit active, for uploading to the texture
+    pub fn bind_texture_for_upload(&mut self, gl: &GL, unit: u32, texture: &WebGlTexture) {
+        self.bind_texture(gl, unit, texture);
+        if self.active_texture != Some(unit) {
+            gl.active_texture(GL::TEXTURE0 + unit);
+            self.active_texture = Some(unit);
+        }
+    }
+
+    pub fn bind_vertex_array(&mut self, vertex_array: &WebGlVertexArrayObject) {
+        if self.vertex_array.as_ref() != Some(vertex_array) {
+            self.vao_ext.bind_vertex_array_oes(Some(vertex_array));
+            self.vertex_array = Some(vertex_array.clone());
+        }
+    }
+
+    pub fn set_cull_face(&mut self, gl: &GL, enabled: bool) {
+        if self.cull_face != Some(enabled) {
+            set_capability(gl, GL::CULL_FACE, enabled);
+            self.cull_face = Some(enabled);
+        }
+    }
+
+    pub fn set_blend(&mut self, gl: &GL, enabled: bool) {
+        if self.blend != Some(enabled) {
+            set_capability(gl, GL::BLEND, enabled);
+            self.blend = Some(enabled);
+        }
+    }
+
+    pub fn set_depth_mask(&mut self, gl: &GL, enabled: bool) {
+        if self.depth_mask != Some(enabled) {
+            gl.depth_mask(enabled);
+            self.depth_mask = Some(enabled);
+        }
+    }
+}
+
+fn set_capability(gl: &GL, capability: u32, enabled: bool) {
+    if enabled {
+        gl.enable(capability);
+    } else {
+        gl.disable(capability);
+    }
+}
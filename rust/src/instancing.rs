@@ -0,0 +1,79 @@
+use crate::program::Attribute;
+use std::collections::HashMap;
+use wasm_bindgen::JsCast;
+use web_sys::WebGlRenderingContext as GL;
+use web_sys::{AngleInstancedArrays, WebGlBuffer};
+
+// Floats per instance: a_world (mat4), a_world_inverse_transpose (mat3) then
+// a_material_index, into the draw's table of material factors
+pub const INSTANCE_SIZE: usize = 16 + 9 + 1;
+
+// Buffer of per-instance attributes, drawn with ANGLE_instanced_arrays so that
+// every object sharing a model is a single draw call
+pub struct InstanceBuffer {
+    ext: AngleInstancedArrays,
+    buffer: WebGlBuffer,
+    // Location, floats per column and number of columns, in buffer order
+    layout: [(u32, i32, u32); 3],
+}
+
+impl InstanceBuffer {
+    pub fn new(gl: &GL, attributes: &HashMap<String, Attribute>) -> Result<InstanceBuffer, String> {
+        let ext = gl
+            .get_extension("ANGLE_instanced_arrays")
+            .ok()
+            .flatten()
+            .ok_or("Instanced drawing (ANGLE_instanced_arrays) isn't available")?
+            .unchecked_into::<AngleInstancedArrays>();
+
+        let layout = ["a_world", "a_world_inverse_transpose", "a_material_index"].map(|name| {
+            let attribute = attributes.get(name).unwrap();
+            (attribute.index, attribute.size, attribute.columns)
+        });
+
+        Ok(InstanceBuffer {
+            ext,
+            buffer: gl
+                .create_buffer()
+                .ok_or("Couldn't create instance buffer")?,
+            layout,
+        })
+    }
+
+    // Upload the instance data and point the per-instance attributes of the
+    // currently bound VAO at it
+    pub fn bind(&self, gl: &GL, data: &[f32]) {
+        gl.bind_buffer(GL::ARRAY_BUFFER, Some(&self.buffer));
+        gl.buffer_data_with_array_buffer_view(
+            GL::ARRAY_BUFFER,
+            &js_sys::Float32Array::from(data),
+            GL::STREAM_DRAW,
+        );
+
+        // Matrix attributes take one location per column
+        let stride = (INSTANCE_SIZE * 4) as i32;
+        let mut offset = 0;
+        for &(location, size, columns) in &self.layout {
+            for column in 0..columns {
+                self.set_pointer(gl, location + column, size, stride, offset);
+                offset += size as u32 * 4;
+            }
+        }
+    }
+
+    pub fn draw_elements(&self, num_indices: i32, index_type: u32, instance_count: i32) {
+        self.ext.draw_elements_instanced_angle_with_i32(
+            GL::TRIANGLES,
+            num_indices,
+            index_type,
+            0,
+            instance_count,
+        );
+    }
+
+    fn set_pointer(&self, gl: &GL, location: u32, size: i32, stride: i32, offset: u32) {
+        gl.vertex_attrib_pointer_with_i32(location, size, GL::FLOAT, false, stride, offset as i32);
+        gl.enable_vertex_attrib_array(location);
+        self.ext.vertex_attrib_divisor_angle(location, 1);
+    }
+}
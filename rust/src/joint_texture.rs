@@ -0,0 +1,67 @@
+use crate::gl_state::GlState;
+use nalgebra_glm as na;
+use web_sys::WebGlRenderingContext as GL;
+use web_sys::WebGlTexture;
+
+// After the built-in maps. Only built-in variants sample it, and they have no
+// custom textures.
+pub const JOINT_TEXTURE_UNIT: u32 = 3;
+
+// Joint matrices for skins with too many joints for uniforms, one row of four
+// RGBA float texels (the matrix's columns) per joint, uploaded for each draw
+pub struct JointTexture {
+    texture: WebGlTexture,
+}
+
+impl JointTexture {
+    // Float textures and texture reads in vertex shaders are both optional in
+    // WebGL1
+    pub fn check_support(gl: &GL, joints: usize, max_uniform_joints: usize) -> Result<(), String> {
+        let vertex_textures = gl
+            .get_parameter(GL::MAX_VERTEX_TEXTURE_IMAGE_UNITS)
+            .ok()
+            .and_then(|value| value.as_f64())
+            .unwrap_or(0.);
+        let float_textures = gl.get_extension("OES_texture_float").ok().flatten();
+        if vertex_textures < 1. || float_textures.is_none() {
+            return Err(format!(
+                "Skin has {} joints, over {} needs float textures (OES_texture_float) \
+                 read in vertex shaders, which aren't available",
+                joints, max_uniform_joints
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn new(gl: &GL, state: &mut GlState) -> JointTexture {
+        let texture = gl.create_texture().unwrap();
+        state.bind_texture_for_upload(gl, JOINT_TEXTURE_UNIT, &texture);
+        // Float textures can't be filtered without another extension
+        gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_MIN_FILTER, GL::NEAREST as i32);
+        gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_MAG_FILTER, GL::NEAREST as i32);
+        gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_WRAP_S, GL::CLAMP_TO_EDGE as i32);
+        gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_WRAP_T, GL::CLAMP_TO_EDGE as i32);
+        JointTexture { texture }
+    }
+
+    // Leaves it bound to JOINT_TEXTURE_UNIT
+    pub fn upload(&self, gl: &GL, state: &mut GlState, joint_matrices: &[na::Mat4]) {
+        let data: Vec<f32> = joint_matrices
+            .iter()
+            .flat_map(|m| m.as_slice().to_vec())
+            .collect();
+        state.bind_texture_for_upload(gl, JOINT_TEXTURE_UNIT, &self.texture);
+        gl.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_array_buffer_view(
+            GL::TEXTURE_2D,
+            0,
+            GL::RGBA as i32,
+            4,
+            joint_matrices.len() as i32,
+            0,
+            GL::RGBA,
+            GL::FLOAT,
+            Some(&js_sys::Float32Array::from(data.as_slice())),
+        )
+        .unwrap();
+    }
+}
@@ -1,69 +1,807 @@
+pub mod animation;
+mod bounds;
+pub mod camera;
+pub mod capture;
+pub mod clock;
+mod demo;
+mod fog;
+mod gl_state;
+mod instancing;
+mod joint_texture;
+mod material;
 mod mesh;
+pub mod mesh_data;
 mod model;
+pub mod obj;
 mod object;
+mod options;
+pub mod ply;
+pub mod primitives;
+mod program;
+pub mod rasterizer;
+mod render_loop;
+mod render_queue;
+mod render_target;
 mod renderer;
+pub mod resolution;
+mod shader_library;
+pub mod skin;
+pub mod stl;
+pub mod tween;
+mod uniforms;
 mod utils;
+pub mod view;
 
-use renderer::Renderer;
+use demo::Demo;
+pub use fog::Fog;
+use material::CustomUniform;
+pub use material::{AlphaMode, MaterialFactors};
+use options::EngineOptions;
+use render_loop::RenderLoop;
+use renderer::{RenderStats, Renderer};
+use std::cell::{RefCell, RefMut};
+use std::rc::Rc;
+use uniforms::UniformValue;
 use wasm_bindgen::prelude::*;
+use wasm_bindgen::Clamped;
 use wasm_bindgen::JsCast;
-use web_sys::HtmlCanvasElement;
 use web_sys::WebGlRenderingContext as GL;
+use web_sys::{Blob, BlobPropertyBag, HtmlCanvasElement, ImageData};
 
 // web_sys::console::log_1(&format!("{}").into());
 
+#[cfg(target_arch = "wasm32")]
 #[global_allocator]
 static ALLOC: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
 #[wasm_bindgen]
 pub struct RustWebGLEngine {
     gl: GL,
-    renderer: Renderer,
+    // Shared with the render loop, and never borrowed while calling into JS
+    renderer: Rc<RefCell<Renderer>>,
+    hooks: Rc<FrameHooks>,
+    render_loop: RenderLoop,
+}
+
+// Called with the frame's delta in seconds
+#[derive(Default)]
+struct FrameHooks {
+    before: RefCell<Option<js_sys::Function>>,
+    after: RefCell<Option<js_sys::Function>>,
 }
 
 #[wasm_bindgen]
 impl RustWebGLEngine {
+    // options is optional, see the README for what it can have
+    #[allow(deprecated)]
     #[wasm_bindgen(constructor)]
-    pub async fn new(canvas: JsValue) -> Result<RustWebGLEngine, JsValue> {
+    pub async fn new(canvas: JsValue, options: JsValue) -> Result<RustWebGLEngine, JsValue> {
         #[cfg(debug_assertions)]
         console_error_panic_hook::set_once();
 
+        let options = EngineOptions::from_js(&options)?;
         let canvas = canvas.dyn_into::<HtmlCanvasElement>()?;
-        let gl = canvas.get_context("webgl")?.unwrap().dyn_into::<GL>()?;
+        let gl = canvas
+            .get_context_with_context_options("webgl", &options.context_attributes())?
+            .ok_or("WebGL isn't available")?
+            .dyn_into::<GL>()?;
+
+        let default_scene = options.default_scene;
+        let mut renderer = Renderer::new(&gl, options)?;
+        if default_scene {
+            renderer.demo = Some(Demo::load(&gl, &mut renderer).await);
+        }
+        let renderer = Rc::new(RefCell::new(renderer));
+        let hooks = Rc::new(FrameHooks::default());
+
+        let render_loop = {
+            let (gl, renderer, hooks) = (gl.clone(), Rc::clone(&renderer), Rc::clone(&hooks));
+            RenderLoop::new(move || {
+                let delta = renderer.borrow_mut().tick();
+                if renderer.borrow().needs_render(&gl) {
+                    draw_frame(&gl, &renderer, &hooks, delta);
+                }
+            })
+        };
+
+        Ok(RustWebGLEngine {
+            gl,
+            renderer,
+            hooks,
+            render_loop,
+        })
+    }
+
+    // Renders every animation frame from now on, or only the frames that
+    // would look different with render on demand (the default)
+    pub fn start(&self) {
+        self.render_loop.start();
+    }
+
+    pub fn stop(&self) {
+        self.render_loop.stop();
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn running(&self) -> bool {
+        self.render_loop.is_running()
+    }
+
+    // Without the loop running, renders one frame now
+    pub fn render(&self) {
+        let delta = self.renderer.borrow_mut().tick();
+        draw_frame(&self.gl, &self.renderer, &self.hooks, delta);
+    }
+
+    // The engine knows about changes made through it, this is for anything
+    // else that should show, like changes to the canvas's surroundings
+    #[wasm_bindgen(js_name = requestRender)]
+    pub fn request_render(&self) {
+        self.renderer().request_render();
+    }
+
+    // With it off every frame is rendered
+    #[wasm_bindgen(js_name = setRenderOnDemand)]
+    pub fn set_render_on_demand(&self, on_demand: bool) {
+        self.renderer().render_on_demand = on_demand;
+    }
+
+    // The canvas is drawn at its size on the page times the device pixel
+    // ratio, up to this ratio (2 by default)
+    #[wasm_bindgen(js_name = setMaxPixelRatio)]
+    pub fn set_max_pixel_ratio(&self, ratio: f32) {
+        self.renderer().resolution.max_pixel_ratio = ratio;
+    }
+
+    // Lowers the resolution, down to min_scale of it (0.5 by default), while
+    // frames take longer than target_frame_time seconds, and raises it again
+    // once they don't. Off with null.
+    #[wasm_bindgen(js_name = setDynamicResolution)]
+    pub fn set_dynamic_resolution(&self, target_frame_time: Option<f32>, min_scale: Option<f32>) {
+        let mut renderer = self.renderer();
+        renderer.resolution.set_target_frame_time(target_frame_time);
+        if let Some(min_scale) = min_scale {
+            renderer.resolution.min_scale = min_scale;
+        }
+    }
+
+    // What dynamic resolution has scaled the canvas's resolution by, 1 when
+    // it's off
+    #[wasm_bindgen(getter, js_name = resolutionScale)]
+    pub fn resolution_scale(&self) -> f32 {
+        self.renderer.borrow().resolution.scale()
+    }
+
+    // Called with the frame's delta in seconds before each frame that's
+    // rendered, or not at all with null. The engine can be changed from it,
+    // and those changes show in the frame.
+    #[wasm_bindgen(js_name = onBeforeFrame)]
+    pub fn on_before_frame(&self, hook: Option<js_sys::Function>) {
+        *self.hooks.before.borrow_mut() = hook;
+    }
+
+    // Like onBeforeFrame, after the frame is rendered
+    #[wasm_bindgen(js_name = onAfterFrame)]
+    pub fn on_after_frame(&self, hook: Option<js_sys::Function>) {
+        *self.hooks.after.borrow_mut() = hook;
+    }
+
+    // Counts from the last rendered frame
+    #[wasm_bindgen(getter)]
+    pub fn stats(&self) -> RenderStats {
+        self.renderer.borrow().stats()
+    }
+
+    // The scene as it is now, drawn offscreen at width x height whatever the
+    // canvas's size, as RGBA bytes with the top row first. Supersampling (1
+    // to 4, 1 by default) draws it that many times bigger and scales it down
+    // for smoother edges.
+    #[wasm_bindgen(js_name = capturePixels)]
+    pub fn capture_pixels(
+        &self,
+        width: u32,
+        height: u32,
+        supersample: Option<u32>,
+    ) -> Result<js_sys::Uint8Array, JsValue> {
+        let pixels = self.capture(width, height, supersample)?;
+        Ok(js_sys::Uint8Array::from(pixels.as_slice()))
+    }
+
+    // Like capturePixels, for drawing onto a 2D canvas
+    #[wasm_bindgen(js_name = captureImageData)]
+    pub fn capture_image_data(
+        &self,
+        width: u32,
+        height: u32,
+        supersample: Option<u32>,
+    ) -> Result<ImageData, JsValue> {
+        let pixels = self.capture(width, height, supersample)?;
+        ImageData::new_with_u8_clamped_array_and_sh(Clamped(&pixels), width, height)
+    }
+
+    // Like capturePixels, as a PNG to download or upload
+    #[wasm_bindgen(js_name = capturePng)]
+    pub fn capture_png(
+        &self,
+        width: u32,
+        height: u32,
+        supersample: Option<u32>,
+    ) -> Result<Blob, JsValue> {
+        let pixels = self.capture(width, height, supersample)?;
+        let png = capture::encode_png(&pixels, width, height)?;
+        let parts = js_sys::Array::of1(&js_sys::Uint8Array::from(png.as_slice()));
+        let properties = BlobPropertyBag::new();
+        properties.set_type("image/png");
+        Blob::new_with_u8_array_sequence_and_options(&parts, &properties)
+    }
+
+    // Loads in the background, the models show up in a later frame. Like the
+    // other loaders it returns the id to control the objects with.
+    #[wasm_bindgen(js_name = loadObj)]
+    pub fn load_obj(&self, url: String, x: f32, y: f32, z: f32, scale: f32) -> u32 {
+        self.renderer()
+            .load_obj(&self.gl, url, nalgebra_glm::vec3(x, y, z), scale)
+    }
+
+    // Drawn untextured, with smooth (welded) or flat normals
+    #[wasm_bindgen(js_name = loadStl)]
+    pub fn load_stl(&self, url: String, smooth: bool, x: f32, y: f32, z: f32, scale: f32) -> u32 {
+        self.renderer().load_stl(
+            &self.gl,
+            url,
+            shading(smooth),
+            nalgebra_glm::vec3(x, y, z),
+            scale,
+        )
+    }
+
+    // Drawn with the vertex colors, normals are generated if the file has none
+    #[wasm_bindgen(js_name = loadPly)]
+    pub fn load_ply(&self, url: String, smooth: bool, x: f32, y: f32, z: f32, scale: f32) -> u32 {
+        self.renderer().load_ply(
+            &self.gl,
+            url,
+            shading(smooth),
+            nalgebra_glm::vec3(x, y, z),
+            scale,
+        )
+    }
+
+    // A .gltf or .glb with one mesh, a model per primitive, drawn in its rest
+    // pose until an animation is played
+    #[wasm_bindgen(js_name = loadGltf)]
+    pub fn load_gltf(&self, url: String, x: f32, y: f32, z: f32, scale: f32) -> u32 {
+        self.renderer()
+            .load_gltf(&self.gl, url, nalgebra_glm::vec3(x, y, z), scale)
+    }
 
-        let renderer = Renderer::new(&gl).await?;
+    // Cross-fades from whatever the object is playing over fade seconds, or
+    // cuts with a fade of 0. Clips go by name, or index if they have none.
+    #[wasm_bindgen(js_name = playAnimation)]
+    pub fn play_animation(
+        &self,
+        object: u32,
+        clip: &str,
+        looping: bool,
+        fade: f32,
+    ) -> Result<(), JsValue> {
+        self.renderer()
+            .play_animation(object, clip, looping, fade)
+            .map_err(JsValue::from)
+    }
 
-        Ok(RustWebGLEngine { gl, renderer })
+    // Back to the rest pose
+    #[wasm_bindgen(js_name = stopAnimation)]
+    pub fn stop_animation(&self, object: u32) -> Result<(), JsValue> {
+        self.renderer().animator(object)?.stop();
+        Ok(())
     }
 
-    // If I make this async, it MUST take "self" NOT "&self"
-    pub fn render(&mut self) {
-        let canvas = self
-            .gl
-            .canvas()
-            .unwrap()
-            .dyn_into::<HtmlCanvasElement>()
-            .unwrap();
-        canvas.set_width(canvas.client_width() as u32);
-        canvas.set_height(canvas.client_height() as u32);
-        self.gl
-            .viewport(0, 0, canvas.width() as i32, canvas.height() as i32);
+    #[wasm_bindgen(js_name = pauseAnimation)]
+    pub fn pause_animation(&self, object: u32) -> Result<(), JsValue> {
+        self.renderer().animator(object)?.paused = true;
+        Ok(())
+    }
 
-        self.gl.enable(GL::CULL_FACE);
-        self.gl.enable(GL::DEPTH_TEST);
-        self.gl.clear_color(0.8, 0.8, 0.8, 1.);
-        self.gl.clear(GL::COLOR_BUFFER_BIT | GL::DEPTH_BUFFER_BIT);
+    #[wasm_bindgen(js_name = resumeAnimation)]
+    pub fn resume_animation(&self, object: u32) -> Result<(), JsValue> {
+        self.renderer().animator(object)?.paused = false;
+        Ok(())
+    }
+
+    // Overrides the file's default and any animation of the weight until
+    // cleared
+    #[wasm_bindgen(js_name = setMorphWeight)]
+    pub fn set_morph_weight(&self, object: u32, target: usize, weight: f32) -> Result<(), JsValue> {
+        self.renderer()
+            .set_morph_weight(object, target, weight)
+            .map_err(JsValue::from)
+    }
+
+    #[wasm_bindgen(js_name = clearMorphWeights)]
+    pub fn clear_morph_weights(&self, object: u32) -> Result<(), JsValue> {
+        self.renderer()
+            .clear_morph_weights(object)
+            .map_err(JsValue::from)
+    }
+
+    // 1 is normal speed, fades aren't affected
+    #[wasm_bindgen(js_name = setAnimationSpeed)]
+    pub fn set_animation_speed(&self, object: u32, speed: f32) -> Result<(), JsValue> {
+        self.renderer().animator(object)?.speed = speed;
+        Ok(())
+    }
 
-        self.renderer.render(&self.gl);
+    // Linear fog from near to far, distances from the camera
+    #[wasm_bindgen(js_name = setFog)]
+    pub fn set_fog(&self, r: f32, g: f32, b: f32, near: f32, far: f32) {
+        self.renderer().fog = Some(Fog {
+            color: nalgebra_glm::vec3(r, g, b),
+            near,
+            far,
+        });
+    }
+
+    #[wasm_bindgen(js_name = clearFog)]
+    pub fn clear_fog(&self) {
+        self.renderer().fog = None;
+    }
+
+    // Throws an array of { stage, line, message } if compiling or linking
+    // fails, line being null when the log doesn't give one
+    #[wasm_bindgen(js_name = createShaderMaterial)]
+    pub fn create_shader_material(
+        &self,
+        vertex_source: &str,
+        fragment_source: &str,
+    ) -> Result<u32, JsValue> {
+        self.renderer()
+            .create_shader_material(&self.gl, vertex_source, fragment_source)
+            .map_err(|errors| {
+                errors
+                    .iter()
+                    .map(shader_error_to_js)
+                    .collect::<js_sys::Array>()
+                    .into()
+            })
+    }
+
+    // mode is "opaque", "mask" or "blend", like glTF's alphaMode. With mask,
+    // fragments with alpha under the cutoff (0.5 by default) are discarded.
+    // The object's models get their own copy of their material.
+    #[wasm_bindgen(js_name = setAlphaMode)]
+    pub fn set_alpha_mode(
+        &self,
+        object: u32,
+        mode: &str,
+        cutoff: Option<f32>,
+    ) -> Result<(), JsValue> {
+        let alpha_mode = AlphaMode::parse(mode, cutoff)?;
+        self.renderer()
+            .change_material(object, |material| material.set_alpha_mode(alpha_mode))
+            .map_err(JsValue::from)
+    }
+
+    // Draws back faces too, lit as if they faced the other way
+    #[wasm_bindgen(js_name = setDoubleSided)]
+    pub fn set_double_sided(&self, object: u32, double_sided: bool) -> Result<(), JsValue> {
+        self.renderer()
+            .change_material(object, |material| material.double_sided = double_sided)
+            .map_err(JsValue::from)
+    }
+
+    // Also for bool uniforms
+    #[wasm_bindgen(js_name = setUniformInt)]
+    pub fn set_uniform_int(&self, material: u32, name: &str, x: i32) -> Result<(), JsValue> {
+        self.set_value(material, name, UniformValue::Int(x))
+    }
+
+    #[wasm_bindgen(js_name = setUniformFloat)]
+    pub fn set_uniform_float(&self, material: u32, name: &str, x: f32) -> Result<(), JsValue> {
+        self.set_value(material, name, UniformValue::Float(x))
+    }
+
+    #[wasm_bindgen(js_name = setUniformVec2)]
+    pub fn set_uniform_vec2(
+        &self,
+        material: u32,
+        name: &str,
+        x: f32,
+        y: f32,
+    ) -> Result<(), JsValue> {
+        self.set_value(material, name, UniformValue::Vec2(nalgebra_glm::vec2(x, y)))
+    }
+
+    #[wasm_bindgen(js_name = setUniformVec3)]
+    pub fn set_uniform_vec3(
+        &self,
+        material: u32,
+        name: &str,
+        x: f32,
+        y: f32,
+        z: f32,
+    ) -> Result<(), JsValue> {
+        self.set_value(
+            material,
+            name,
+            UniformValue::Vec3(nalgebra_glm::vec3(x, y, z)),
+        )
+    }
+
+    #[wasm_bindgen(js_name = setUniformVec4)]
+    pub fn set_uniform_vec4(
+        &self,
+        material: u32,
+        name: &str,
+        x: f32,
+        y: f32,
+        z: f32,
+        w: f32,
+    ) -> Result<(), JsValue> {
+        self.set_value(
+            material,
+            name,
+            UniformValue::Vec4(nalgebra_glm::vec4(x, y, z, w)),
+        )
+    }
+
+    // Column-major, like uniformMatrix3fv
+    #[wasm_bindgen(js_name = setUniformMat3)]
+    pub fn set_uniform_mat3(
+        &self,
+        material: u32,
+        name: &str,
+        values: &[f32],
+    ) -> Result<(), JsValue> {
+        if values.len() != 9 {
+            return Err("Expected 9 values".into());
+        }
+        let matrix = nalgebra_glm::Mat3::from_column_slice(values);
+        self.set_value(material, name, UniformValue::Mat3(matrix))
+    }
+
+    #[wasm_bindgen(js_name = setUniformMat4)]
+    pub fn set_uniform_mat4(
+        &self,
+        material: u32,
+        name: &str,
+        values: &[f32],
+    ) -> Result<(), JsValue> {
+        if values.len() != 16 {
+            return Err("Expected 16 values".into());
+        }
+        let matrix = nalgebra_glm::Mat4::from_column_slice(values);
+        self.set_value(material, name, UniformValue::Mat4(matrix))
+    }
+
+    // For a sampler2D uniform, loaded asynchronously like every other texture
+    #[wasm_bindgen(js_name = setUniformTexture)]
+    pub fn set_uniform_texture(&self, material: u32, name: &str, url: &str) -> Result<(), JsValue> {
+        // Released before set_uniform borrows the renderer mutably
+        let url = self.renderer.borrow().options.asset_url(url);
+        let texture = utils::load_texture(&self.gl, &url);
+        self.set_uniform(material, name, CustomUniform::Texture(texture))
+    }
+
+    // Samples what views last drew into the render target, top row at v = 0
+    #[wasm_bindgen(js_name = setUniformRenderTarget)]
+    pub fn set_uniform_render_target(
+        &self,
+        material: u32,
+        name: &str,
+        target: u32,
+    ) -> Result<(), JsValue> {
+        let texture = self.renderer.borrow().render_target_texture(target)?;
+        self.set_uniform(material, name, CustomUniform::Texture(texture))
+    }
+
+    // A built-in material showing the render target, lit like the rest of
+    // the scene, for addPrimitive. Returns its id.
+    #[wasm_bindgen(js_name = createTextureMaterial)]
+    pub fn create_texture_material(&self, target: u32) -> Result<u32, JsValue> {
+        self.renderer()
+            .create_texture_material(target)
+            .map_err(JsValue::from)
+    }
+
+    // shape is "plane", "cube", "sphere", "icosphere", "cylinder", "cone",
+    // "torus" or "capsule". Without a material it's drawn plain white.
+    #[wasm_bindgen(js_name = addPrimitive)]
+    pub fn add_primitive(
+        &self,
+        shape: &str,
+        material: Option<u32>,
+        x: f32,
+        y: f32,
+        z: f32,
+        scale: f32,
+    ) -> Result<u32, JsValue> {
+        self.renderer()
+            .add_primitive(
+                &self.gl,
+                shape,
+                material,
+                nalgebra_glm::vec3(x, y, z),
+                scale,
+            )
+            .map_err(JsValue::from)
+    }
+
+    // Animates a property from its current value to the values given, over
+    // duration seconds. property is one of "position", "rotation" (a
+    // quaternion, XYZW), "scale", "baseColor" (RGBA), "emissive",
+    // "specularStrength" and "shininess" of object id, "lightPosition" and
+    // "lightColor" of light id, or "cameraRotation" and "fogColor", which
+    // ignore id. options can have easing (like "quintOut", see the README),
+    // delay in seconds, repeat (a count, or Infinity), yoyo, after (the id of
+    // a tween to start once it completes) and onComplete. Returns the id of
+    // the tween.
+    pub fn tween(
+        &self,
+        property: &str,
+        id: u32,
+        to: &[f32],
+        duration: f32,
+        options: Option<js_sys::Object>,
+    ) -> Result<u32, JsValue> {
+        let target = tween::Target::parse(property, id)?;
+        let mut tween = tween::Tween::new(target, target.value(to)?, duration);
+        let options = options.unwrap_or_default();
+        let option = |name: &str| js_sys::Reflect::get(&options, &name.into());
+        if let Some(easing) = option("easing")?.as_string() {
+            tween.easing = tween::Easing::parse(&easing)?;
+        }
+        if let Some(delay) = option("delay")?.as_f64() {
+            tween.delay = delay as f32;
+        }
+        if let Some(repeat) = option("repeat")?.as_f64() {
+            tween.repeat = if repeat.is_finite() {
+                Some(repeat.max(0.) as u32)
+            } else {
+                None
+            };
+        }
+        tween.yoyo = option("yoyo")?.is_truthy();
+        let after = option("after")?.as_f64().map(|after| after as u32);
+        let on_complete = option("onComplete")?.dyn_into::<js_sys::Function>().ok();
+        self.renderer()
+            .tween(tween, after, on_complete)
+            .map_err(JsValue::from)
+    }
+
+    // Leaves the property where it is, without calling onComplete. Tweens
+    // chained after it are stopped too.
+    #[wasm_bindgen(js_name = stopTween)]
+    pub fn stop_tween(&self, tween: u32) {
+        self.renderer().stop_tween(tween);
+    }
+
+    // Animations, tweens and the demo's spin all stop, rendering doesn't
+    #[wasm_bindgen(js_name = pauseClock)]
+    pub fn pause_clock(&self) {
+        self.renderer().clock.paused = true;
+    }
+
+    #[wasm_bindgen(js_name = resumeClock)]
+    pub fn resume_clock(&self) {
+        self.renderer().clock.paused = false;
+    }
+
+    // 0.5 is half speed
+    #[wasm_bindgen(js_name = setTimeScale)]
+    pub fn set_time_scale(&self, scale: f32) {
+        self.renderer().clock.time_scale = scale;
+    }
+
+    // Every frame advances by seconds however long it took, or by the real
+    // time again with null
+    #[wasm_bindgen(js_name = setFixedStep)]
+    pub fn set_fixed_step(&self, seconds: Option<f32>) {
+        self.renderer().clock.fixed_step = seconds;
+    }
+
+    // Advances the next frame by seconds, even while paused
+    #[wasm_bindgen(js_name = stepClock)]
+    pub fn step_clock(&self, seconds: f32) {
+        self.renderer().clock.step(seconds);
+    }
+
+    // The clock reads the time from source, a function returning
+    // milliseconds like performance.now(), or from performance.now() again
+    // with null
+    #[wasm_bindgen(js_name = setTimeSource)]
+    pub fn set_time_source(&self, source: Option<js_sys::Function>) {
+        let source: clock::TimeSource = match source {
+            Some(source) => Box::new(move || match source.call0(&JsValue::NULL) {
+                Ok(time) => time.as_f64().unwrap_or(f64::NAN) / 1000.,
+                Err(e) => {
+                    web_sys::console::error_1(&e);
+                    f64::NAN
+                }
+            }),
+            None => Box::new(utils::performance_time),
+        };
+        self.renderer().clock.set_source(source);
+    }
+
+    // Seconds the clock has advanced, as of the last frame
+    #[wasm_bindgen(getter, js_name = elapsedTime)]
+    pub fn elapsed_time(&self) -> f64 {
+        self.renderer.borrow().clock.elapsed()
     }
 
     #[wasm_bindgen(js_name = rotateCameraLeft)]
-    pub fn rotate_camera_left(&mut self) {
-        self.renderer.rotate_camera_left();
+    pub fn rotate_camera_left(&self) {
+        self.renderer().rotate_camera_left();
     }
 
     #[wasm_bindgen(js_name = rotateCameraRight)]
-    pub fn rotate_camera_right(&mut self) {
-        self.renderer.rotate_camera_right();
+    pub fn rotate_camera_right(&self) {
+        self.renderer().rotate_camera_right();
+    }
+
+    // Another camera to draw views with, at the origin looking down -Z.
+    // Camera 0 is the main one. Returns its id.
+    #[wasm_bindgen(js_name = createCamera)]
+    pub fn create_camera(&self) -> u32 {
+        self.renderer().create_camera()
+    }
+
+    #[wasm_bindgen(js_name = setCameraPosition)]
+    pub fn set_camera_position(&self, camera: u32, x: f32, y: f32, z: f32) -> Result<(), JsValue> {
+        self.renderer().camera(camera)?.position = nalgebra_glm::vec3(x, y, z);
+        Ok(())
+    }
+
+    // Turns the camera to face the point
+    #[wasm_bindgen(js_name = cameraLookAt)]
+    pub fn camera_look_at(&self, camera: u32, x: f32, y: f32, z: f32) -> Result<(), JsValue> {
+        self.renderer()
+            .camera(camera)?
+            .look_at(&nalgebra_glm::vec3(x, y, z));
+        Ok(())
+    }
+
+    // fov is vertical, in degrees
+    #[wasm_bindgen(js_name = setCameraPerspective)]
+    pub fn set_camera_perspective(
+        &self,
+        camera: u32,
+        fov: f32,
+        near: f32,
+        far: f32,
+    ) -> Result<(), JsValue> {
+        self.renderer()
+            .camera(camera)?
+            .set_perspective(fov, near, far)
+            .map_err(JsValue::from)
+    }
+
+    // Shows height world units top to bottom, whatever the distance. For
+    // minimaps and the like.
+    #[wasm_bindgen(js_name = setCameraOrthographic)]
+    pub fn set_camera_orthographic(
+        &self,
+        camera: u32,
+        height: f32,
+        near: f32,
+        far: f32,
+    ) -> Result<(), JsValue> {
+        self.renderer()
+            .camera(camera)?
+            .set_orthographic(height, near, far)
+            .map_err(JsValue::from)
+    }
+
+    // An offscreen framebuffer with a color texture and a depth buffer, for
+    // views to draw into and materials to show. Returns its id.
+    #[wasm_bindgen(js_name = createRenderTarget)]
+    pub fn create_render_target(&self, width: u32, height: u32) -> Result<u32, JsValue> {
+        self.renderer()
+            .create_render_target(&self.gl, width, height)
+            .map_err(JsValue::from)
+    }
+
+    // Removes the views into it too
+    #[wasm_bindgen(js_name = deleteRenderTarget)]
+    pub fn delete_render_target(&self, target: u32) -> Result<(), JsValue> {
+        self.renderer()
+            .delete_render_target(&self.gl, target)
+            .map_err(JsValue::from)
+    }
+
+    // Draws the camera into a rectangle of the canvas, or of the render
+    // target. x, y, width and height are fractions of its size from the top
+    // left. View 0 is the main camera over the whole canvas. Returns its id.
+    #[wasm_bindgen(js_name = addView)]
+    pub fn add_view(
+        &self,
+        camera: u32,
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+        target: Option<u32>,
+    ) -> Result<u32, JsValue> {
+        let viewport = view::Viewport::new(x, y, width, height)?;
+        self.renderer()
+            .add_view(camera, viewport, target)
+            .map_err(JsValue::from)
+    }
+
+    #[wasm_bindgen(js_name = removeView)]
+    pub fn remove_view(&self, view: u32) -> Result<(), JsValue> {
+        self.renderer().remove_view(view).map_err(JsValue::from)
+    }
+}
+
+impl RustWebGLEngine {
+    fn capture(
+        &self,
+        width: u32,
+        height: u32,
+        supersample: Option<u32>,
+    ) -> Result<Vec<u8>, JsValue> {
+        self.renderer
+            .borrow_mut()
+            .capture(&self.gl, width, height, supersample.unwrap_or(1))
+            .map_err(JsValue::from)
+    }
+
+    // For changes, which the next frame has to show
+    fn renderer(&self) -> RefMut<'_, Renderer> {
+        let mut renderer = self.renderer.borrow_mut();
+        renderer.request_render();
+        renderer
+    }
+
+    fn set_value(&self, material: u32, name: &str, value: UniformValue) -> Result<(), JsValue> {
+        self.set_uniform(material, name, CustomUniform::Value(value))
+    }
+
+    fn set_uniform(&self, material: u32, name: &str, value: CustomUniform) -> Result<(), JsValue> {
+        self.renderer()
+            .set_uniform(material, name, value)
+            .map_err(JsValue::from)
+    }
+}
+
+fn draw_frame(gl: &GL, renderer: &RefCell<Renderer>, hooks: &FrameHooks, delta: f32) {
+    FrameHooks::call(&hooks.before, delta);
+    renderer.borrow_mut().render(gl);
+    FrameHooks::call(&hooks.after, delta);
+}
+
+impl FrameHooks {
+    fn call(hook: &RefCell<Option<js_sys::Function>>, delta: f32) {
+        // Cloned out, so the hook can replace itself
+        let hook = hook.borrow().clone();
+        if let Some(hook) = hook {
+            if let Err(e) = hook.call1(&JsValue::NULL, &delta.into()) {
+                web_sys::console::error_1(&e);
+            }
+        }
+    }
+}
+
+fn shader_error_to_js(error: &program::ShaderError) -> JsValue {
+    let object = js_sys::Object::new();
+    let stage = match error.stage {
+        program::ShaderStage::Vertex => "vertex",
+        program::ShaderStage::Fragment => "fragment",
+        program::ShaderStage::Link => "link",
+    };
+    let line = error.line.map_or(JsValue::NULL, |line| line.into());
+    js_sys::Reflect::set(&object, &"stage".into(), &stage.into()).unwrap();
+    js_sys::Reflect::set(&object, &"line".into(), &line).unwrap();
+    js_sys::Reflect::set(&object, &"message".into(), &error.message.as_str().into()).unwrap();
+    object.into()
+}
+
+fn shading(smooth: bool) -> mesh_data::Shading {
+    if smooth {
+        mesh_data::Shading::Smooth
+    } else {
+        mesh_data::Shading::Flat
     }
 }
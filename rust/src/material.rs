@@ -0,0 +1,372 @@
+use super::gl_state::{GlState, TEXTURE_UNITS};
+use super::program::Program;
+use super::shader_library::Features;
+use super::uniforms::UniformValue;
+use nalgebra_glm as na;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use web_sys::WebGlRenderingContext as GL;
+use web_sys::WebGlTexture;
+
+// Same as glTF alphaMode, with alphaCutoff carried by Mask
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum AlphaMode {
+    Opaque,
+    // Fragments with alpha below the cutoff are discarded and the rest are opaque
+    Mask(f32),
+    // Drawn after everything opaque, sorted back to front
+    Blend,
+}
+
+impl AlphaMode {
+    // From glTF's names, in any case. The cutoff is only for mask, 0.5 like
+    // glTF's when left out.
+    pub fn parse(mode: &str, cutoff: Option<f32>) -> Result<AlphaMode, String> {
+        if cutoff.is_some() && !mode.eq_ignore_ascii_case("mask") {
+            return Err(format!("Only mask has a cutoff, not {}", mode));
+        }
+        match mode.to_ascii_lowercase().as_str() {
+            "opaque" => Ok(AlphaMode::Opaque),
+            "mask" => match cutoff.unwrap_or(0.5) {
+                cutoff if (0. ..=1.).contains(&cutoff) => Ok(AlphaMode::Mask(cutoff)),
+                cutoff => Err(format!("Alpha cutoff must be 0 to 1, not {}", cutoff)),
+            },
+            "blend" => Ok(AlphaMode::Blend),
+            _ => Err(format!(
+                "Alpha mode must be \"opaque\", \"mask\" or \"blend\", not \"{}\"",
+                mode
+            )),
+        }
+    }
+}
+
+// Multiplied with (or added to) whatever the maps hold, so a material without
+// maps is just these. The defaults leave the maps unchanged.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct MaterialFactors {
+    pub base_color: na::Vec4,
+    pub specular_strength: f32,
+    pub shininess: f32,
+    pub emissive: na::Vec3,
+}
+
+impl Default for MaterialFactors {
+    fn default() -> MaterialFactors {
+        MaterialFactors {
+            base_color: na::vec4(1., 1., 1., 1.),
+            specular_strength: 1.,
+            shininess: 70.,
+            emissive: na::vec3(0., 0., 0.),
+        }
+    }
+}
+
+impl MaterialFactors {
+    // Blended if the base color is translucent
+    pub fn alpha_mode(&self) -> AlphaMode {
+        if self.base_color.w < 1. {
+            AlphaMode::Blend
+        } else {
+            AlphaMode::Opaque
+        }
+    }
+}
+
+// Values for the uniforms of a custom shader
+#[derive(Clone)]
+pub enum CustomUniform {
+    Value(UniformValue),
+    // Bound to a texture unit, which the sampler is set to
+    Texture(WebGlTexture),
+}
+
+impl CustomUniform {
+    fn gl_type(&self) -> u32 {
+        match self {
+            CustomUniform::Value(value) => value.gl_type(),
+            CustomUniform::Texture(_) => GL::SAMPLER_2D,
+        }
+    }
+}
+
+// Custom shaders' textures go after the built-in maps
+const FIRST_CUSTOM_TEXTURE_UNIT: usize = 3;
+
+// Built-in materials that only differ in their factors are drawn together, up
+// to this many, with each instance picking its factors by index. Matches
+// materials.glsl.
+pub const MAX_BATCH_MATERIALS: usize = 16;
+
+// Maps are optional, the shader variant for a material only samples the ones
+// it has. Copies share the custom uniforms, so objects given their own copy
+// still follow the setters.
+#[derive(Clone)]
+pub struct Material {
+    pub color_map: Option<WebGlTexture>,
+    pub specular_map: Option<WebGlTexture>,
+    pub normal_map: Option<WebGlTexture>,
+    // Tweens change these on the object's own copy
+    pub factors: MaterialFactors,
+    pub alpha_mode: AlphaMode,
+    // What alpha_mode was before a translucent base color made it Blend
+    pub faded_from: Option<AlphaMode>,
+    pub double_sided: bool,
+    // Drawn with this instead of a built-in variant, with uniforms set from JS
+    pub program: Option<Rc<Program>>,
+    pub uniforms: Rc<RefCell<HashMap<String, CustomUniform>>>,
+}
+
+impl Material {
+    // No maps, the color comes from the factors and any vertex colors
+    pub fn untextured(factors: MaterialFactors) -> Material {
+        Material {
+            color_map: None,
+            specular_map: None,
+            normal_map: None,
+            factors,
+            alpha_mode: factors.alpha_mode(),
+            faded_from: None,
+            double_sided: false,
+            program: None,
+            uniforms: Rc::new(RefCell::new(HashMap::new())),
+        }
+    }
+
+    pub fn custom(program: Rc<Program>) -> Material {
+        Material {
+            program: Some(program),
+            ..Material::untextured(MaterialFactors::default())
+        }
+    }
+
+    // Opaque materials would ignore the alpha, so they blend while it's under
+    // 1 and go back once it's 1 again
+    pub fn set_base_color(&mut self, color: na::Vec4) {
+        self.factors.base_color = color;
+        if color.w < 1. && self.alpha_mode == AlphaMode::Opaque {
+            self.faded_from = Some(self.alpha_mode);
+            self.alpha_mode = AlphaMode::Blend;
+        } else if color.w >= 1. {
+            if let Some(alpha_mode) = self.faded_from.take() {
+                self.alpha_mode = alpha_mode;
+            }
+        }
+    }
+
+    // Chosen from JS, kept after the base color fades back in
+    pub fn set_alpha_mode(&mut self, alpha_mode: AlphaMode) {
+        self.alpha_mode = alpha_mode;
+        self.faded_from = None;
+    }
+
+    // Checked against the type the shader declares
+    pub fn set_uniform(&self, name: &str, value: CustomUniform) -> Result<(), String> {
+        let program = self
+            .program
+            .as_ref()
+            .ok_or("Only custom materials have uniforms to set")?;
+        let type_ = program.uniforms.type_of(name).ok_or_else(|| {
+            format!(
+                "No uniform {} (uniforms the shader doesn't use are removed)",
+                name
+            )
+        })?;
+        if !accepts(type_, value.gl_type()) {
+            return Err(format!(
+                "Uniform {} is a {}, not a {}",
+                name,
+                type_name(type_),
+                type_name(value.gl_type())
+            ));
+        }
+
+        let mut uniforms = self.uniforms.borrow_mut();
+        let textures = uniforms
+            .iter()
+            .filter(|(other, value)| *other != name && matches!(value, CustomUniform::Texture(_)))
+            .count();
+        if matches!(value, CustomUniform::Texture(_))
+            && FIRST_CUSTOM_TEXTURE_UNIT + textures >= TEXTURE_UNITS
+        {
+            return Err(format!(
+                "Custom materials can have at most {} textures",
+                TEXTURE_UNITS - FIRST_CUSTOM_TEXTURE_UNIT
+            ));
+        }
+        uniforms.insert(name.to_string(), value);
+        Ok(())
+    }
+
+    // Whether drawing with it reads the texture
+    pub fn samples(&self, texture: &WebGlTexture) -> bool {
+        let maps = [&self.color_map, &self.specular_map, &self.normal_map];
+        maps.iter().any(|map| map.as_ref() == Some(texture))
+            || self.uniforms.borrow().values().any(|value| match value {
+                CustomUniform::Texture(other) => other == texture,
+                CustomUniform::Value(_) => false,
+            })
+    }
+
+    // Stops sampling a texture that's being deleted. Maps go back to none,
+    // custom uniforms lose it for every copy and their samplers read black.
+    pub fn forget_texture(&mut self, texture: &WebGlTexture) {
+        for map in [
+            &mut self.color_map,
+            &mut self.specular_map,
+            &mut self.normal_map,
+        ] {
+            if map.as_ref() == Some(texture) {
+                *map = None;
+            }
+        }
+        self.uniforms.borrow_mut().retain(|_, value| match value {
+            CustomUniform::Texture(other) => other != texture,
+            CustomUniform::Value(_) => true,
+        });
+    }
+
+    // Whether objects with the other material can be drawn in the same
+    // instanced draw, with their own factors
+    pub fn batches_with(&self, other: &Material) -> bool {
+        self.program.is_none()
+            && other.program.is_none()
+            && self.color_map == other.color_map
+            && self.specular_map == other.specular_map
+            && self.normal_map == other.normal_map
+            && self.alpha_mode == other.alpha_mode
+            && self.double_sided == other.double_sided
+    }
+
+    pub fn features(&self) -> Features {
+        let mut features = Features::default();
+        let maps = [
+            (&self.color_map, Features::COLOR_MAP),
+            (&self.specular_map, Features::SPECULAR_MAP),
+            (&self.normal_map, Features::NORMAL_MAP),
+        ];
+        for (map, feature) in maps.iter() {
+            if map.is_some() {
+                features = features | *feature;
+            }
+        }
+        match self.alpha_mode {
+            AlphaMode::Opaque => features,
+            AlphaMode::Mask(_) => features | Features::ALPHA_MASK,
+            AlphaMode::Blend => features | Features::ALPHA_BLEND,
+        }
+    }
+
+    pub fn bind(&self, gl: &GL, state: &mut GlState, program: &Program) {
+        let maps = [&self.color_map, &self.specular_map, &self.normal_map];
+        for (unit, map) in maps.iter().enumerate() {
+            if let Some(texture) = map {
+                state.bind_texture(gl, unit as u32, texture);
+            }
+        }
+
+        let uniforms = &program.uniforms;
+        if let AlphaMode::Mask(cutoff) = self.alpha_mode {
+            uniforms.set(gl, "u_alpha_cutoff", UniformValue::Float(cutoff));
+        }
+
+        let mut unit = FIRST_CUSTOM_TEXTURE_UNIT as u32;
+        for (name, value) in self.uniforms.borrow().iter() {
+            match value {
+                CustomUniform::Value(value) => uniforms.set(gl, name, *value),
+                CustomUniform::Texture(texture) => {
+                    state.bind_texture(gl, unit, texture);
+                    uniforms.set(gl, name, UniformValue::Int(unit as i32));
+                    unit += 1;
+                }
+            }
+        }
+
+        state.set_cull_face(gl, !self.double_sided);
+    }
+
+    // Into entry index of the table of factors the instances pick from
+    pub fn bind_factors(&self, gl: &GL, program: &Program, index: usize) {
+        let uniforms = &program.uniforms;
+        let factors = self.factors;
+        let set = |field, value| uniforms.set_element(gl, "u_materials", index, field, value);
+        set("base_color", UniformValue::Vec4(factors.base_color));
+        set("emissive", UniformValue::Vec3(factors.emissive));
+        set(
+            "specular_strength",
+            UniformValue::Float(factors.specular_strength),
+        );
+        set("shininess", UniformValue::Float(factors.shininess));
+    }
+}
+
+// Bools are set with ints, like uniform1i does
+fn accepts(declared: u32, given: u32) -> bool {
+    declared == given || (declared == GL::BOOL && given == GL::INT)
+}
+
+fn type_name(type_: u32) -> String {
+    match type_ {
+        GL::INT => "int".to_string(),
+        GL::BOOL => "bool".to_string(),
+        GL::FLOAT => "float".to_string(),
+        GL::FLOAT_VEC2 => "vec2".to_string(),
+        GL::FLOAT_VEC3 => "vec3".to_string(),
+        GL::FLOAT_VEC4 => "vec4".to_string(),
+        GL::FLOAT_MAT3 => "mat3".to_string(),
+        GL::FLOAT_MAT4 => "mat4".to_string(),
+        GL::SAMPLER_2D => "sampler2D".to_string(),
+        x => format!("type {:#x}", x),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_alpha_modes() {
+        assert_eq!(AlphaMode::parse("OPAQUE", None), Ok(AlphaMode::Opaque));
+        assert_eq!(AlphaMode::parse("mask", None), Ok(AlphaMode::Mask(0.5)));
+        assert_eq!(
+            AlphaMode::parse("mask", Some(0.2)),
+            Ok(AlphaMode::Mask(0.2))
+        );
+        assert_eq!(AlphaMode::parse("blend", None), Ok(AlphaMode::Blend));
+        assert!(AlphaMode::parse("blend", Some(0.2)).is_err());
+        assert!(AlphaMode::parse("mask", Some(2.)).is_err());
+        assert!(AlphaMode::parse("glass", None).is_err());
+    }
+
+    #[test]
+    fn fading_base_colors_blend_until_opaque_again() {
+        let mut material = Material::untextured(MaterialFactors::default());
+        material.set_base_color(na::vec4(1., 1., 1., 0.5));
+        assert_eq!(material.alpha_mode, AlphaMode::Blend);
+        material.set_base_color(na::vec4(1., 1., 1., 0.8));
+        assert_eq!(material.alpha_mode, AlphaMode::Blend);
+        material.set_base_color(na::vec4(1., 1., 1., 1.));
+        assert_eq!(material.alpha_mode, AlphaMode::Opaque);
+
+        let mut material = Material::untextured(MaterialFactors::default());
+        material.alpha_mode = AlphaMode::Mask(0.5);
+        material.set_base_color(na::vec4(1., 1., 1., 0.5));
+        material.set_base_color(na::vec4(1., 1., 1., 1.));
+        assert_eq!(material.alpha_mode, AlphaMode::Mask(0.5));
+
+        let mut material = Material::untextured(MaterialFactors::default());
+        material.set_base_color(na::vec4(1., 1., 1., 0.5));
+        material.set_alpha_mode(AlphaMode::Blend);
+        material.set_base_color(na::vec4(1., 1., 1., 1.));
+        assert_eq!(material.alpha_mode, AlphaMode::Blend);
+    }
+
+    #[test]
+    fn sets_bools_with_ints() {
+        assert!(accepts(GL::BOOL, GL::INT));
+        assert!(accepts(GL::INT, GL::INT));
+        assert!(!accepts(GL::BOOL, GL::FLOAT));
+        assert!(!accepts(GL::FLOAT, GL::INT));
+        assert!(!accepts(GL::SAMPLER_2D, GL::INT));
+    }
+}
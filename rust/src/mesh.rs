@@ -1,7 +1,11 @@
-use crate::renderer::Attribute;
+use crate::bounds::Bounds;
+use crate::gl_state::GlState;
+use crate::instancing::{InstanceBuffer, INSTANCE_SIZE};
+use crate::mesh_data::{self, MeshData, MAX_MORPH_TARGETS};
+use crate::program::Attribute;
+use crate::shader_library::Features;
 use crate::utils;
 use gltf::Gltf;
-use nalgebra_glm as na;
 use std::collections::HashMap;
 use wasm_bindgen::JsCast;
 use web_sys::WebGlRenderingContext as GL;
@@ -9,15 +13,25 @@ use web_sys::{WebGlBuffer, WebGlVertexArrayObject};
 
 pub struct Mesh {
     vao: WebGlVertexArrayObject,
-    index_buffer: WebGlBuffer,
     num_indices: i32,
+    // UNSIGNED_SHORT, or UNSIGNED_INT for meshes with more vertices
+    index_type: u32,
+    pub bounds: Bounds,
+    has_colors: bool,
+    // Position, normal and tangent deltas per target, bound to the morph
+    // attributes when drawn
+    morph_targets: Vec<(WebGlBuffer, Option<WebGlBuffer>, Option<WebGlBuffer>)>,
+    // Position and normal locations per slot
+    morph_slots: Vec<(u32, u32)>,
+    // Only the first slot has tangents
+    morph_tangent_location: u32,
 }
 
 impl Mesh {
     // TODO: If I'm using spawn_local on the async stuff below, does this "new"
     // and everything upstream have to be async at all?
     // I'm not solid on how all of this works. I can experiment, probably.
-    pub async fn new(gl: &GL, attributes: &HashMap<String, Attribute>, gltf_url: &str) -> Mesh {
+    pub async fn load(gl: &GL, attributes: &HashMap<String, Attribute>, gltf_url: &str) -> Mesh {
         // TODO:
         // // ----- Load the placeholder model data here
         // let s = String::from(gltf_url);
@@ -35,50 +49,20 @@ impl Mesh {
         } else {
             panic!("Can only read gltf buffer from url currently")
         };
+        let buffers = [gltf::buffer::Data(
+            js_sys::Uint8Array::new(&buffer).to_vec(),
+        )];
 
-        let mesh = gltf.meshes().next().unwrap();
-        let primitive = mesh.primitives().next().unwrap();
-        let positions = {
-            let accessor = primitive.get(&gltf::mesh::Semantic::Positions).unwrap();
-            let view = accessor.view().unwrap();
-            js_sys::Float32Array::new_with_byte_offset_and_length(
-                &buffer,
-                view.offset() as u32,
-                view.length() as u32 / 4,
-            )
-        };
-        let normals = {
-            let accessor = primitive.get(&gltf::mesh::Semantic::Normals).unwrap();
-            let view = accessor.view().unwrap();
-            js_sys::Float32Array::new_with_byte_offset_and_length(
-                &buffer,
-                view.offset() as u32,
-                view.length() as u32 / 4,
-            )
-        };
-        let texcoords = {
-            let accessor = primitive.get(&gltf::mesh::Semantic::TexCoords(0)).unwrap();
-            let view = accessor.view().unwrap();
-            js_sys::Float32Array::new_with_byte_offset_and_length(
-                &buffer,
-                view.offset() as u32,
-                view.length() as u32 / 4,
-            )
-        };
-        let indices = {
-            let accessor = primitive.indices().unwrap();
-            let view = accessor.view().unwrap();
-            js_sys::Uint16Array::new_with_byte_offset_and_length(
-                &buffer,
-                view.offset() as u32,
-                view.length() as u32 / 2,
-            )
-        };
-
-        let (tangents, bitangents) = calc_tangents_bitangents(&indices, &positions, &texcoords);
-
-        let (index_buffer, num_indices) = buffer_index_data(&gl, &indices);
+        let data = MeshData::from_gltf(&gltf.document, &buffers).unwrap();
+        Mesh::new(gl, attributes, &data).unwrap()
+    }
 
+    // Upload mesh data to the GPU
+    pub fn new(
+        gl: &GL,
+        attributes: &HashMap<String, Attribute>,
+        data: &MeshData,
+    ) -> Result<Mesh, String> {
         let oesvao = gl
             .get_extension("OES_vertex_array_object")
             .unwrap()
@@ -88,130 +72,196 @@ impl Mesh {
         let vao = oesvao.create_vertex_array_oes().unwrap();
         oesvao.bind_vertex_array_oes(Some(&vao));
 
-        buffer_and_set_pointer(&gl, attributes.get("a_position").unwrap(), &positions);
-        buffer_and_set_pointer(&gl, attributes.get("a_texcoords").unwrap(), &texcoords);
-        buffer_and_set_pointer(&gl, attributes.get("a_normal").unwrap(), &normals);
-        buffer_and_set_pointer(&gl, attributes.get("a_tangent").unwrap(), &tangents);
-        buffer_and_set_pointer(&gl, attributes.get("a_bitangent").unwrap(), &bitangents);
+        // The element array buffer binding is part of the VAO state
+        let index_type = buffer_index_data(gl, data)?;
+
+        buffer_and_set_pointer(gl, attributes.get("a_position").unwrap(), &data.positions);
+        buffer_and_set_pointer(gl, attributes.get("a_texcoords").unwrap(), &data.texcoords);
+        buffer_and_set_pointer(gl, attributes.get("a_normal").unwrap(), &data.normals);
+        buffer_and_set_pointer(gl, attributes.get("a_tangent").unwrap(), &data.tangents);
 
-        Mesh {
+        if !data.colors.is_empty() {
+            buffer_and_set_pointer(gl, attributes.get("a_color").unwrap(), &data.colors);
+        }
+        if !data.weights.is_empty() {
+            buffer_and_set_pointer(gl, attributes.get("a_joints").unwrap(), &data.joints);
+            buffer_and_set_pointer(gl, attributes.get("a_weights").unwrap(), &data.weights);
+        }
+
+        let morph_targets = data
+            .morph_targets
+            .iter()
+            .map(|target| {
+                // Targets that only move normals still need positions
+                let positions = if target.positions.is_empty() {
+                    vec![0.; data.positions.len()]
+                } else {
+                    target.positions.clone()
+                };
+                let optional =
+                    |deltas: &Vec<f32>| (!deltas.is_empty()).then(|| buffer_data(gl, deltas));
+                (
+                    buffer_data(gl, &positions),
+                    optional(&target.normals),
+                    optional(&target.tangents),
+                )
+            })
+            .collect();
+        let morph_slots = (0..MAX_MORPH_TARGETS)
+            .map(|slot| {
+                let location = |name: &str| attributes[&format!("{}{}", name, slot)].index;
+                (location("a_morph_position"), location("a_morph_normal"))
+            })
+            .collect();
+
+        oesvao.bind_vertex_array_oes(None);
+
+        Ok(Mesh {
             vao,
-            index_buffer,
-            num_indices,
+            num_indices: data.indices.len() as i32,
+            index_type,
+            bounds: data.bounds,
+            has_colors: !data.colors.is_empty(),
+            morph_targets,
+            morph_slots,
+            morph_tangent_location: attributes["a_morph_tangent0"].index,
+        })
+    }
+
+    pub fn features(&self) -> Features {
+        let mut features = Features::default();
+        if self.has_colors {
+            features = features | Features::VERTEX_COLORS;
+        }
+        if self.has_morph_targets() {
+            features = features | Features::MORPH_TARGETS;
         }
+        features
     }
 
-    pub fn render(&self, gl: &GL) {
-        let oesvao = gl
-            .get_extension("OES_vertex_array_object")
-            .unwrap()
-            .unwrap()
-            .unchecked_into::<web_sys::OesVertexArrayObject>();
-        oesvao.bind_vertex_array_oes(Some(&self.vao));
+    pub fn has_morph_targets(&self) -> bool {
+        !self.morph_targets.is_empty()
+    }
+
+    pub fn render(
+        &self,
+        gl: &GL,
+        state: &mut GlState,
+        instance_buffer: &InstanceBuffer,
+        instance_data: &[f32],
+    ) {
+        state.bind_vertex_array(&self.vao);
+        instance_buffer.bind(gl, instance_data);
+
+        instance_buffer.draw_elements(
+            self.num_indices,
+            self.index_type,
+            (instance_data.len() / INSTANCE_SIZE) as i32,
+        );
+    }
 
-        gl.bind_buffer(GL::ELEMENT_ARRAY_BUFFER, Some(&self.index_buffer));
+    // Points the morph attributes at the most influential targets and
+    // returns the weight for each slot. Empty slots read zero deltas.
+    pub fn bind_morph_targets(
+        &self,
+        gl: &GL,
+        state: &mut GlState,
+        weights: &[f32],
+    ) -> [f32; MAX_MORPH_TARGETS] {
+        state.bind_vertex_array(&self.vao);
+        let targets = mesh_data::most_influential(weights);
+        let mut slot_weights = [0.; MAX_MORPH_TARGETS];
+        for (slot, &(position_location, normal_location)) in self.morph_slots.iter().enumerate() {
+            let target = targets
+                .get(slot)
+                .and_then(|&(target, weight)| Some((self.morph_targets.get(target)?, weight)));
+            match target {
+                Some(((positions, normals, tangents), weight)) => {
+                    slot_weights[slot] = weight;
+                    set_pointer(gl, position_location, Some(positions));
+                    set_pointer(gl, normal_location, normals.as_ref());
+                    if slot == 0 {
+                        set_pointer(gl, self.morph_tangent_location, tangents.as_ref());
+                    }
+                }
+                None => {
+                    set_pointer(gl, position_location, None);
+                    set_pointer(gl, normal_location, None);
+                    if slot == 0 {
+                        set_pointer(gl, self.morph_tangent_location, None);
+                    }
+                }
+            }
+        }
+        slot_weights
+    }
 
-        gl.draw_elements_with_i32(GL::TRIANGLES, self.num_indices, GL::UNSIGNED_SHORT, 0);
+    // One object, for meshes drawn with bind_morph_targets
+    pub fn draw(&self, gl: &GL, state: &mut GlState) {
+        state.bind_vertex_array(&self.vao);
+        gl.draw_elements_with_i32(GL::TRIANGLES, self.num_indices, self.index_type, 0);
     }
 }
 
-fn buffer_and_set_pointer(gl: &GL, attrib: &Attribute, array: &js_sys::Float32Array) {
-    let buffer = gl.create_buffer().unwrap();
-    gl.bind_buffer(GL::ARRAY_BUFFER, Some(&buffer));
-    gl.buffer_data_with_array_buffer_view(GL::ARRAY_BUFFER, &array, GL::STATIC_DRAW);
+fn buffer_and_set_pointer(gl: &GL, attrib: &Attribute, data: &[f32]) {
+    buffer_data(gl, data);
     gl.vertex_attrib_pointer_with_i32(attrib.index, attrib.size, attrib.type_, false, 0, 0);
     gl.enable_vertex_attrib_array(attrib.index);
 }
 
-fn buffer_index_data(gl: &GL, array: &js_sys::Uint16Array) -> (WebGlBuffer, i32) {
+// Leaves the buffer bound
+fn buffer_data(gl: &GL, data: &[f32]) -> WebGlBuffer {
     let buffer = gl.create_buffer().unwrap();
-    gl.bind_buffer(GL::ELEMENT_ARRAY_BUFFER, Some(&buffer));
-    gl.buffer_data_with_array_buffer_view(GL::ELEMENT_ARRAY_BUFFER, &array, GL::STATIC_DRAW);
-    (buffer, array.length() as i32)
+    gl.bind_buffer(GL::ARRAY_BUFFER, Some(&buffer));
+    gl.buffer_data_with_array_buffer_view(
+        GL::ARRAY_BUFFER,
+        &js_sys::Float32Array::from(data),
+        GL::STATIC_DRAW,
+    );
+    buffer
 }
 
-// TODO: nicer
-pub fn calc_tangents_bitangents(
-    indices: &js_sys::Uint16Array,
-    positions: &js_sys::Float32Array,
-    texcoords: &js_sys::Float32Array,
-) -> (js_sys::Float32Array, js_sys::Float32Array) {
-    let indices = indices.to_vec();
-    let indices = indices.as_slice();
-    let positions = positions.to_vec();
-    let positions = positions.as_slice();
-    let texcoords = texcoords.to_vec();
-    let texcoords = texcoords.as_slice();
-
-    let mut tangents: Vec<f32> = Vec::with_capacity(positions.len() as usize);
-    tangents.resize(positions.len(), 0.);
-    let mut bitangents: Vec<f32> = Vec::with_capacity(positions.len() as usize);
-    bitangents.resize(positions.len(), 0.);
-
-    for i in (0..indices.len()).step_by(3) {
-        let v0 = na::vec3(
-            positions[indices[i + 0] as usize * 3 + 0],
-            positions[indices[i + 0] as usize * 3 + 1],
-            positions[indices[i + 0] as usize * 3 + 2],
-        );
-        let v1 = na::vec3(
-            positions[indices[i + 1] as usize * 3 + 0],
-            positions[indices[i + 1] as usize * 3 + 1],
-            positions[indices[i + 1] as usize * 3 + 2],
-        );
-        let v2 = na::vec3(
-            positions[indices[i + 2] as usize * 3 + 0],
-            positions[indices[i + 2] as usize * 3 + 1],
-            positions[indices[i + 2] as usize * 3 + 2],
-        );
+// A vec3 attribute reading the buffer, or the constant default without one
+fn set_pointer(gl: &GL, location: u32, buffer: Option<&WebGlBuffer>) {
+    match buffer {
+        Some(buffer) => {
+            gl.bind_buffer(GL::ARRAY_BUFFER, Some(buffer));
+            gl.vertex_attrib_pointer_with_i32(location, 3, GL::FLOAT, false, 0, 0);
+            gl.enable_vertex_attrib_array(location);
+        }
+        None => gl.disable_vertex_attrib_array(location),
+    }
+}
 
-        let uv0 = na::vec2(
-            texcoords[indices[i + 0] as usize * 2 + 0],
-            texcoords[indices[i + 0] as usize * 2 + 1],
-        );
-        let uv1 = na::vec2(
-            texcoords[indices[i + 1] as usize * 2 + 0],
-            texcoords[indices[i + 1] as usize * 2 + 1],
-        );
-        let uv2 = na::vec2(
-            texcoords[indices[i + 2] as usize * 2 + 0],
-            texcoords[indices[i + 2] as usize * 2 + 1],
+// 16 bit indices where they fit, otherwise 32 bit ones, which WebGL1 needs
+// an extension for. Returns the type to draw them with.
+fn buffer_index_data(gl: &GL, data: &MeshData) -> Result<u32, String> {
+    let buffer = gl.create_buffer().ok_or("Couldn't create index buffer")?;
+    gl.bind_buffer(GL::ELEMENT_ARRAY_BUFFER, Some(&buffer));
+    if !data.needs_32_bit_indices() {
+        let indices: Vec<u16> = data.indices.iter().map(|&i| i as u16).collect();
+        gl.buffer_data_with_array_buffer_view(
+            GL::ELEMENT_ARRAY_BUFFER,
+            &js_sys::Uint16Array::from(indices.as_slice()),
+            GL::STATIC_DRAW,
         );
-
-        let delta_pos_1 = v1 - v0;
-        let delta_pos_2 = v2 - v0;
-
-        let delta_uv_1 = uv1 - uv0;
-        let delta_uv_2 = uv2 - uv0;
-
-        let r = 1.0 / (delta_uv_1.x * delta_uv_2.y - delta_uv_1.y * delta_uv_2.x);
-        let tangent = (delta_pos_1 * delta_uv_2.y - delta_pos_2 * delta_uv_1.y) * r;
-        let bitangent = (delta_pos_2 * delta_uv_1.x - delta_pos_1 * delta_uv_2.x) * r;
-
-        tangents[indices[i + 0] as usize * 3 + 0] = tangent.x;
-        tangents[indices[i + 0] as usize * 3 + 1] = tangent.y;
-        tangents[indices[i + 0] as usize * 3 + 2] = tangent.z;
-        tangents[indices[i + 1] as usize * 3 + 0] = tangent.x;
-        tangents[indices[i + 1] as usize * 3 + 1] = tangent.y;
-        tangents[indices[i + 1] as usize * 3 + 2] = tangent.z;
-        tangents[indices[i + 2] as usize * 3 + 0] = tangent.x;
-        tangents[indices[i + 2] as usize * 3 + 1] = tangent.y;
-        tangents[indices[i + 2] as usize * 3 + 2] = tangent.z;
-
-        bitangents[indices[i + 0] as usize * 3 + 0] = bitangent.x;
-        bitangents[indices[i + 0] as usize * 3 + 1] = bitangent.y;
-        bitangents[indices[i + 0] as usize * 3 + 2] = bitangent.z;
-        bitangents[indices[i + 1] as usize * 3 + 0] = bitangent.x;
-        bitangents[indices[i + 1] as usize * 3 + 1] = bitangent.y;
-        bitangents[indices[i + 1] as usize * 3 + 2] = bitangent.z;
-        bitangents[indices[i + 2] as usize * 3 + 0] = bitangent.x;
-        bitangents[indices[i + 2] as usize * 3 + 1] = bitangent.y;
-        bitangents[indices[i + 2] as usize * 3 + 2] = bitangent.z;
+        return Ok(GL::UNSIGNED_SHORT);
     }
 
-    unsafe {
-        let tangents = js_sys::Float32Array::view(&tangents);
-        let bitangents = js_sys::Float32Array::view(&bitangents);
-        (tangents, bitangents)
-    }
+    gl.get_extension("OES_element_index_uint")
+        .ok()
+        .flatten()
+        .ok_or_else(|| {
+            format!(
+                "Mesh has {} vertices, over 65536 needs 32 bit indices \
+                 (OES_element_index_uint), which aren't available",
+                data.num_vertices()
+            )
+        })?;
+    gl.buffer_data_with_array_buffer_view(
+        GL::ELEMENT_ARRAY_BUFFER,
+        &js_sys::Uint32Array::from(data.indices.as_slice()),
+        GL::STATIC_DRAW,
+    );
+    Ok(GL::UNSIGNED_INT)
 }
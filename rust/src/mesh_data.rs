@@ -0,0 +1,852 @@
+// CPU-side mesh data and the processing done on it before it's uploaded.
+// Plain Rust with no WebGL, so it runs (and is tested) natively too.
+
+use crate::bounds::Bounds;
+use nalgebra_glm as na;
+use std::collections::HashMap;
+use std::path::Path;
+
+// Flat attribute arrays, one entry per vertex (3 floats for positions and
+// normals, 2 for texcoords, 4 for tangents, colors, joints and weights) and
+// triangle list indices
+pub struct MeshData {
+    pub positions: Vec<f32>,
+    pub normals: Vec<f32>,
+    pub texcoords: Vec<f32>,
+    pub tangents: Vec<f32>, // Bitangent sign in w
+    pub colors: Vec<f32>,   // RGBA, empty for none
+    // Indices into the skin's joints and how much each one moves the vertex,
+    // both empty for meshes that aren't skinned
+    pub joints: Vec<f32>,
+    pub weights: Vec<f32>,
+    pub morph_targets: Vec<MorphTarget>,
+    pub indices: Vec<u32>,
+    pub bounds: Bounds,
+}
+
+// How many morph targets the shaders blend at once, the most influential ones
+// each frame. Has to match MAX_MORPH_TARGETS in morph_targets.glsl.
+pub const MAX_MORPH_TARGETS: usize = 3;
+
+// Offsets from the base mesh, 3 floats per vertex, empty for none. Tangent
+// deltas are only drawn for the most influential target, there aren't
+// attributes left for more.
+pub struct MorphTarget {
+    pub positions: Vec<f32>,
+    pub normals: Vec<f32>,
+    pub tangents: Vec<f32>,
+}
+
+// How to generate normals for formats that don't have them
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Shading {
+    // Every triangle gets its own vertices with the face normal
+    Flat,
+    // Vertices at the same position are welded and the normals averaged
+    Smooth,
+}
+
+impl MeshData {
+    // Generates whatever is missing: smooth normals if there are no normals,
+    // zero texcoords if there are no texcoords, then tangents
+    pub fn new(
+        positions: Vec<f32>,
+        normals: Vec<f32>,
+        texcoords: Vec<f32>,
+        indices: Vec<u32>,
+    ) -> MeshData {
+        MeshData::with_tangents(positions, normals, texcoords, Vec::new(), indices)
+    }
+
+    pub fn with_tangents(
+        positions: Vec<f32>,
+        normals: Vec<f32>,
+        texcoords: Vec<f32>,
+        tangents: Vec<f32>,
+        indices: Vec<u32>,
+    ) -> MeshData {
+        let mut data = MeshData {
+            positions,
+            normals,
+            texcoords,
+            tangents,
+            colors: Vec::new(),
+            joints: Vec::new(),
+            weights: Vec::new(),
+            morph_targets: Vec::new(),
+            indices,
+            bounds: Bounds::from_positions(&[]),
+        };
+        data.complete();
+        data
+    }
+
+    // Indexed triangles with no normals or texcoords, like STL and most scans.
+    // Colors are optional.
+    pub fn from_triangles(
+        positions: Vec<f32>,
+        colors: Vec<f32>,
+        indices: Vec<u32>,
+        shading: Shading,
+    ) -> MeshData {
+        let mut data = MeshData {
+            positions,
+            normals: Vec::new(),
+            texcoords: Vec::new(),
+            tangents: Vec::new(),
+            colors,
+            joints: Vec::new(),
+            weights: Vec::new(),
+            morph_targets: Vec::new(),
+            indices,
+            bounds: Bounds::from_positions(&[]),
+        };
+        match shading {
+            Shading::Flat => data.unweld(),
+            Shading::Smooth => data.weld(1e-5),
+        }
+        data.complete();
+        data
+    }
+
+    // Fills in whatever is missing and computes the bounds
+    fn complete(&mut self) {
+        self.compute_bounds();
+        if self.normals.is_empty() {
+            self.generate_normals();
+        }
+        if self.texcoords.is_empty() {
+            self.texcoords = vec![0.; self.num_vertices() * 2];
+        }
+        if self.tangents.is_empty() {
+            self.generate_tangents();
+        }
+    }
+
+    // Reads the first primitive of the first mesh
+    pub fn from_gltf(
+        document: &gltf::Document,
+        buffers: &[gltf::buffer::Data],
+    ) -> Result<MeshData, String> {
+        let mesh = document.meshes().next().ok_or("glTF has no meshes")?;
+        let primitive = mesh.primitives().next().ok_or("Mesh has no primitives")?;
+        MeshData::from_gltf_primitive(&primitive, buffers)
+    }
+
+    // Uses the supplied tangents if there are any, so they match whatever the
+    // normal maps were baked with
+    pub fn from_gltf_primitive(
+        primitive: &gltf::Primitive,
+        buffers: &[gltf::buffer::Data],
+    ) -> Result<MeshData, String> {
+        let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+
+        let positions = reader
+            .read_positions()
+            .ok_or("Mesh has no positions")?
+            .flatten()
+            .collect::<Vec<_>>();
+        let normals = reader
+            .read_normals()
+            .map(|normals| normals.flatten().collect())
+            .unwrap_or_default();
+        let texcoords = reader
+            .read_tex_coords(0)
+            .map(|texcoords| texcoords.into_f32().flatten().collect())
+            .unwrap_or_default();
+        let tangents = reader
+            .read_tangents()
+            .map(|tangents| tangents.flatten().collect())
+            .unwrap_or_default();
+        let colors = reader.read_colors(0).map(read_colors).unwrap_or_default();
+        let joints = reader
+            .read_joints(0)
+            .map(|joints| joints.into_u16().flatten().map(f32::from).collect())
+            .unwrap_or_default();
+        let weights = reader.read_weights(0).map(read_weights).unwrap_or_default();
+        let morph_targets = reader
+            .read_morph_targets()
+            .map(|(positions, normals, tangents)| MorphTarget {
+                positions: positions.map(|p| p.flatten().collect()).unwrap_or_default(),
+                normals: normals.map(|n| n.flatten().collect()).unwrap_or_default(),
+                tangents: tangents.map(|t| t.flatten().collect()).unwrap_or_default(),
+            })
+            .collect();
+        let indices = match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect(),
+            None => (0..positions.len() as u32 / 3).collect(),
+        };
+
+        let mut data = MeshData::with_tangents(positions, normals, texcoords, tangents, indices);
+        data.colors = colors;
+        data.joints = joints;
+        data.weights = weights;
+        data.morph_targets = morph_targets;
+        Ok(data)
+    }
+
+    pub fn load_gltf(path: impl AsRef<Path>) -> Result<MeshData, String> {
+        let (document, buffers, _) = gltf::import(path).map_err(|e| e.to_string())?;
+        MeshData::from_gltf(&document, &buffers)
+    }
+
+    pub fn num_vertices(&self) -> usize {
+        self.positions.len() / 3
+    }
+
+    // WebGL1 draws 16 bit indices, 32 bit ones need OES_element_index_uint
+    pub fn needs_32_bit_indices(&self) -> bool {
+        self.indices.iter().any(|&i| i > u16::MAX as u32)
+    }
+
+    // Smooth normals, each vertex getting the sum of the face normals around
+    // it weighted by face area. Vertices that are duplicated along seams are
+    // normalized separately, so weld first for smooth normals across them.
+    pub fn generate_normals(&mut self) {
+        let mut normals = vec![0.; self.positions.len()];
+        for triangle in self.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| attribute3(&self.positions, triangle[i] as usize));
+            // Length is twice the triangle's area
+            let face_normal = na::cross(&(b - a), &(c - a));
+            for &i in triangle {
+                let i = i as usize;
+                for axis in 0..3 {
+                    normals[i * 3 + axis] += face_normal[axis];
+                }
+            }
+        }
+
+        for normal in normals.chunks_exact_mut(3) {
+            let n = na::vec3(normal[0], normal[1], normal[2]);
+            let length = na::length(&n);
+            // Unused or degenerate vertices get an arbitrary unit normal
+            let n = if length > 0. {
+                n / length
+            } else {
+                na::vec3(0., 1., 0.)
+            };
+            normal.copy_from_slice(n.as_slice());
+        }
+
+        self.normals = normals;
+    }
+
+    // MikkTSpace tangents, the same layout as the glTF TANGENT attribute
+    pub fn generate_tangents(&mut self) {
+        let mut geometry = TangentGeometry {
+            indices: &self.indices,
+            positions: &self.positions,
+            normals: &self.normals,
+            texcoords: &self.texcoords,
+            tangents: vec![[0.; 4]; self.indices.len()],
+        };
+
+        // Fails on geometry with no usable triangles, where any tangent will do
+        if !bevy_mikktspace::generate_tangents(&mut geometry) {
+            self.tangents = self
+                .normals
+                .chunks_exact(3)
+                .flat_map(|normal| {
+                    let t = perpendicular(&na::vec3(normal[0], normal[1], normal[2]));
+                    [t.x, t.y, t.z, 1.]
+                })
+                .collect();
+            return;
+        }
+
+        let corners = geometry.tangents;
+        self.set_corner_tangents(&corners);
+    }
+
+    // Gives each vertex the tangent of the face corners using it. Vertices
+    // whose corners got different tangents, like on mirrored UV seams, are
+    // copied so each corner keeps its own.
+    fn set_corner_tangents(&mut self, corners: &[[f32; 4]]) {
+        let count = self.num_vertices();
+        let mut tangents = vec![0.; count * 4];
+        let mut used = vec![false; count];
+        let mut copies = Vec::new();
+        let mut vertices = HashMap::new();
+        for (index, tangent) in self.indices.iter_mut().zip(corners) {
+            let i = *index as usize;
+            *index = *vertices
+                .entry((i, tangent.map(f32::to_bits)))
+                .or_insert_with(|| {
+                    if !used[i] {
+                        used[i] = true;
+                        tangents[i * 4..i * 4 + 4].copy_from_slice(tangent);
+                        i as u32
+                    } else {
+                        copies.push(i);
+                        tangents.extend_from_slice(tangent);
+                        (count + copies.len() - 1) as u32
+                    }
+                });
+        }
+
+        if !copies.is_empty() {
+            let vertices: Vec<usize> = (0..count).chain(copies).collect();
+            self.gather(&vertices);
+        }
+        self.tangents = tangents;
+    }
+
+    // Merges vertices whose attributes are all within epsilon of each other
+    // and drops vertices no triangle uses
+    pub fn weld(&mut self, epsilon: f32) {
+        let mut attributes: Vec<(&Vec<f32>, usize)> = vec![
+            (&self.positions, 3),
+            (&self.normals, 3),
+            (&self.texcoords, 2),
+            (&self.tangents, 4),
+            (&self.colors, 4),
+            (&self.joints, 4),
+            (&self.weights, 4),
+        ];
+        for target in &self.morph_targets {
+            attributes.extend([
+                (&target.positions, 3),
+                (&target.normals, 3),
+                (&target.tangents, 3),
+            ]);
+        }
+
+        let mut remap = HashMap::new();
+        let mut kept = Vec::new();
+        let indices = self
+            .indices
+            .iter()
+            .map(|&i| {
+                let i = i as usize;
+                let key: Vec<i64> = attributes
+                    .iter()
+                    .filter(|(data, _)| !data.is_empty())
+                    .flat_map(|(data, size)| &data[i * size..(i + 1) * size])
+                    .map(|&x| (x / epsilon).round() as i64)
+                    .collect();
+                *remap.entry(key).or_insert_with(|| {
+                    kept.push(i);
+                    kept.len() as u32 - 1
+                })
+            })
+            .collect();
+
+        self.gather(&kept);
+        self.indices = indices;
+    }
+
+    // Gives every index its own vertex
+    pub fn unweld(&mut self) {
+        let vertices: Vec<usize> = self.indices.iter().map(|&i| i as usize).collect();
+        self.gather(&vertices);
+        self.indices = (0..vertices.len() as u32).collect();
+    }
+
+    // Replaces the vertices with copies of the given ones
+    fn gather(&mut self, vertices: &[usize]) {
+        let gather = |data: &Vec<f32>, size: usize| -> Vec<f32> {
+            if data.is_empty() {
+                return Vec::new();
+            }
+            vertices
+                .iter()
+                .flat_map(|&i| &data[i * size..(i + 1) * size])
+                .copied()
+                .collect()
+        };
+        self.positions = gather(&self.positions, 3);
+        self.normals = gather(&self.normals, 3);
+        self.texcoords = gather(&self.texcoords, 2);
+        self.tangents = gather(&self.tangents, 4);
+        self.colors = gather(&self.colors, 4);
+        self.joints = gather(&self.joints, 4);
+        self.weights = gather(&self.weights, 4);
+        for target in &mut self.morph_targets {
+            target.positions = gather(&target.positions, 3);
+            target.normals = gather(&target.normals, 3);
+            target.tangents = gather(&target.tangents, 3);
+        }
+    }
+
+    pub fn compute_bounds(&mut self) {
+        self.bounds = Bounds::from_positions(&self.positions);
+    }
+}
+
+// Indices and weights of the targets to draw, largest weights first
+pub fn most_influential(weights: &[f32]) -> Vec<(usize, f32)> {
+    let mut targets: Vec<(usize, f32)> = weights
+        .iter()
+        .copied()
+        .enumerate()
+        .filter(|&(_, weight)| weight != 0.)
+        .collect();
+    targets.sort_by(|(_, a), (_, b)| b.abs().partial_cmp(&a.abs()).unwrap());
+    targets.truncate(MAX_MORPH_TARGETS);
+    targets
+}
+
+// RGB or RGBA, float or normalized integers. Not ReadColors::into_rgba_f32,
+// which divides u8 by 32767 in gltf 0.15.
+fn read_colors(colors: gltf::mesh::util::ReadColors) -> Vec<f32> {
+    use gltf::mesh::util::ReadColors::*;
+    let rgb = |c: [f32; 3]| [c[0], c[1], c[2], 1.];
+    match colors {
+        RgbU8(iter) => iter.flat_map(|c| rgb(c.map(|x| x as f32 / 255.))).collect(),
+        RgbU16(iter) => iter
+            .flat_map(|c| rgb(c.map(|x| x as f32 / 65535.)))
+            .collect(),
+        RgbF32(iter) => iter.flat_map(rgb).collect(),
+        RgbaU8(iter) => iter.flat_map(|c| c.map(|x| x as f32 / 255.)).collect(),
+        RgbaU16(iter) => iter.flat_map(|c| c.map(|x| x as f32 / 65535.)).collect(),
+        RgbaF32(iter) => iter.flatten().collect(),
+    }
+}
+
+// Normalized integers like read_colors, since ReadWeights::into_f32 has the
+// same problem
+fn read_weights(weights: gltf::mesh::util::ReadWeights) -> Vec<f32> {
+    use gltf::mesh::util::ReadWeights::*;
+    match weights {
+        U8(iter) => iter.flat_map(|w| w.map(|x| x as f32 / 255.)).collect(),
+        U16(iter) => iter.flat_map(|w| w.map(|x| x as f32 / 65535.)).collect(),
+        F32(iter) => iter.flatten().collect(),
+    }
+}
+
+pub fn attribute3(data: &[f32], i: usize) -> na::Vec3 {
+    na::vec3(data[i * 3], data[i * 3 + 1], data[i * 3 + 2])
+}
+
+// Some unit vector perpendicular to v
+fn perpendicular(v: &na::Vec3) -> na::Vec3 {
+    let axis = if v.x.abs() < 0.9 {
+        na::vec3(1., 0., 0.)
+    } else {
+        na::vec3(0., 1., 0.)
+    };
+    na::normalize(&na::cross(v, &axis))
+}
+
+struct TangentGeometry<'a> {
+    indices: &'a [u32],
+    positions: &'a [f32],
+    normals: &'a [f32],
+    texcoords: &'a [f32],
+    // One per face corner
+    tangents: Vec<[f32; 4]>,
+}
+
+impl TangentGeometry<'_> {
+    fn index(&self, face: usize, vert: usize) -> usize {
+        self.indices[face * 3 + vert] as usize
+    }
+}
+
+impl bevy_mikktspace::Geometry for TangentGeometry<'_> {
+    fn num_faces(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    fn num_vertices_of_face(&self, _face: usize) -> usize {
+        3
+    }
+
+    fn position(&self, face: usize, vert: usize) -> [f32; 3] {
+        let i = self.index(face, vert);
+        [
+            self.positions[i * 3],
+            self.positions[i * 3 + 1],
+            self.positions[i * 3 + 2],
+        ]
+    }
+
+    fn normal(&self, face: usize, vert: usize) -> [f32; 3] {
+        let i = self.index(face, vert);
+        [
+            self.normals[i * 3],
+            self.normals[i * 3 + 1],
+            self.normals[i * 3 + 2],
+        ]
+    }
+
+    fn tex_coord(&self, face: usize, vert: usize) -> [f32; 2] {
+        let i = self.index(face, vert);
+        [self.texcoords[i * 2], self.texcoords[i * 2 + 1]]
+    }
+
+    fn set_tangent_encoded(&mut self, tangent: [f32; 4], face: usize, vert: usize) {
+        self.tangents[face * 3 + vert] = tangent;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Unit quad in the XY plane facing +Z, as two triangles with unshared
+    // vertices, u along +X and v along -Y like glTF
+    fn quad() -> MeshData {
+        MeshData::new(
+            vec![
+                0., 0., 0., 1., 0., 0., 1., 1., 0., //
+                0., 0., 0., 1., 1., 0., 0., 1., 0.,
+            ],
+            Vec::new(),
+            vec![0., 1., 1., 1., 1., 0., 0., 1., 1., 0., 0., 0.],
+            vec![0, 1, 2, 3, 4, 5],
+        )
+    }
+
+    fn assert_vec_eq(actual: &[f32], expected: &[f32]) {
+        assert_eq!(
+            actual.len(),
+            expected.len(),
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-5, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn generates_face_normals() {
+        let quad = quad();
+        for normal in quad.normals.chunks_exact(3) {
+            assert_vec_eq(normal, &[0., 0., 1.]);
+        }
+    }
+
+    #[test]
+    fn smooth_normals_are_area_weighted() {
+        // Big triangle facing +Z and small one facing +X sharing vertex 0
+        let mut data = MeshData::new(
+            vec![0., 0., 0., 2., 0., 0., 0., 2., 0., 0., 0., -1., 0., 1., 0.],
+            Vec::new(),
+            Vec::new(),
+            vec![0, 1, 2, 0, 3, 4],
+        );
+        data.generate_normals();
+        let shared = attribute3(&data.normals, 0);
+        assert!(shared.z > shared.x && shared.x > 0.);
+        assert!((na::length(&shared) - 1.).abs() < 1e-5);
+    }
+
+    #[test]
+    fn generates_tangents_along_u() {
+        let quad = quad();
+        for tangent in quad.tangents.chunks_exact(4) {
+            assert_vec_eq(&tangent[..3], &[1., 0., 0.]);
+            // v runs down the quad, so the bitangent is -Y = cross(N, T) * -1
+            assert_eq!(tangent[3], -1.);
+        }
+    }
+
+    #[test]
+    fn mirrored_uvs_flip_handedness() {
+        let mut quad = quad();
+        for v in quad.texcoords.iter_mut().skip(1).step_by(2) {
+            *v = 1. - *v;
+        }
+        quad.generate_tangents();
+        for tangent in quad.tangents.chunks_exact(4) {
+            assert_vec_eq(&tangent[..3], &[1., 0., 0.]);
+            assert_eq!(tangent[3], 1.);
+        }
+    }
+
+    #[test]
+    fn splits_vertices_on_mirrored_seams() {
+        // Welded quad whose second triangle has u running along -X, sharing
+        // vertices 0 and 2 with the first
+        let data = MeshData::new(
+            vec![0., 0., 0., 1., 0., 0., 1., 1., 0., 0., 1., 0.],
+            Vec::new(),
+            vec![0., 0., 1., 0., 0., 1., 1., 1.],
+            vec![0, 1, 2, 0, 2, 3],
+        );
+        assert_eq!(data.num_vertices(), 6);
+        assert_eq!(data.normals.len(), 18);
+        for (corner, &i) in data.indices.iter().enumerate() {
+            let i = i as usize;
+            let expected = if corner < 3 { 1. } else { -1. };
+            assert_vec_eq(&data.tangents[i * 4..i * 4 + 3], &[expected, 0., 0.]);
+            assert_eq!(data.tangents[i * 4 + 3], expected);
+        }
+        // The copies are of the shared vertices
+        assert_vec_eq(&data.positions[12..], &[0., 0., 0., 1., 1., 0.]);
+    }
+
+    #[test]
+    fn degenerate_uvs_still_give_unit_tangents() {
+        let mut quad = quad();
+        quad.texcoords = vec![0.; 12];
+        quad.generate_tangents();
+        for (normal, tangent) in quad
+            .normals
+            .chunks_exact(3)
+            .zip(quad.tangents.chunks_exact(4))
+        {
+            let t = na::vec3(tangent[0], tangent[1], tangent[2]);
+            assert!((na::length(&t) - 1.).abs() < 1e-5);
+            assert!(na::dot(&t, &attribute3(normal, 0)).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn keeps_supplied_tangents() {
+        let tangents = [0., 1., 0., 1.].repeat(3);
+        let data = MeshData::with_tangents(
+            vec![0., 0., 0., 1., 0., 0., 0., 1., 0.],
+            Vec::new(),
+            vec![0., 0., 1., 0., 0., 1.],
+            tangents.clone(),
+            vec![0, 1, 2],
+        );
+        assert_eq!(data.tangents, tangents);
+    }
+
+    #[test]
+    fn weld_merges_duplicate_vertices() {
+        let mut quad = quad();
+        quad.weld(1e-4);
+        assert_eq!(quad.num_vertices(), 4);
+        assert_eq!(quad.normals.len(), 12);
+        assert_eq!(quad.texcoords.len(), 8);
+        assert_eq!(quad.tangents.len(), 16);
+        assert_eq!(quad.indices, vec![0, 1, 2, 0, 2, 3]);
+        assert_vec_eq(&quad.positions[9..], &[0., 1., 0.]);
+    }
+
+    #[test]
+    fn weld_keeps_seams() {
+        let mut quad = quad();
+        quad.texcoords[6] = 0.5;
+        quad.weld(1e-4);
+        assert_eq!(quad.num_vertices(), 5);
+    }
+
+    #[test]
+    fn weld_drops_unused_vertices() {
+        let mut data = MeshData::new(
+            vec![0., 0., 0., 5., 5., 5., 1., 0., 0., 0., 1., 0.],
+            Vec::new(),
+            Vec::new(),
+            vec![0, 2, 3],
+        );
+        data.weld(1e-4);
+        assert_eq!(data.num_vertices(), 3);
+        assert_eq!(data.indices, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn reads_gltf_colors() {
+        // Triangle with normalized RGB bytes for COLOR_0, padded to 4 bytes
+        let mut buffer = Vec::new();
+        for x in [0f32, 0., 0., 1., 0., 0., 0., 1., 0.].iter() {
+            buffer.extend_from_slice(&x.to_le_bytes());
+        }
+        buffer.extend_from_slice(&[255, 0, 0, 0, 0, 255, 0, 0, 0, 0, 255, 0]);
+        let document = gltf::Gltf::from_slice(
+            br#"{
+                "asset": { "version": "2.0" },
+                "buffers": [{ "byteLength": 48 }],
+                "bufferViews": [
+                    { "buffer": 0, "byteLength": 36 },
+                    { "buffer": 0, "byteOffset": 36, "byteLength": 12, "byteStride": 4 }
+                ],
+                "accessors": [
+                    { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                      "min": [0, 0, 0], "max": [1, 1, 0] },
+                    { "bufferView": 1, "componentType": 5121, "normalized": true,
+                      "count": 3, "type": "VEC3" }
+                ],
+                "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0, "COLOR_0": 1 } }] }]
+            }"#,
+        )
+        .unwrap()
+        .document;
+
+        let data = MeshData::from_gltf(&document, &[gltf::buffer::Data(buffer)]).unwrap();
+        assert_eq!(data.indices, vec![0, 1, 2]);
+        assert_vec_eq(
+            &data.colors,
+            &[1., 0., 0., 1., 0., 1., 0., 1., 0., 0., 1., 1.],
+        );
+        assert_vec_eq(&data.normals[..3], &[0., 0., 1.]);
+    }
+
+    #[test]
+    fn reads_each_gltf_primitive() {
+        // Two triangles, each its own primitive with its own material
+        let mut buffer = Vec::new();
+        for x in [
+            0f32, 0., 0., 1., 0., 0., 0., 1., 0., 0., 0., 1., 1., 0., 1., 0., 1., 1.,
+        ]
+        .iter()
+        {
+            buffer.extend_from_slice(&x.to_le_bytes());
+        }
+        let document = gltf::Gltf::from_slice(
+            br#"{
+                "asset": { "version": "2.0" },
+                "buffers": [{ "byteLength": 72 }],
+                "bufferViews": [
+                    { "buffer": 0, "byteLength": 36 },
+                    { "buffer": 0, "byteOffset": 36, "byteLength": 36 }
+                ],
+                "accessors": [
+                    { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                      "min": [0, 0, 0], "max": [1, 1, 0] },
+                    { "bufferView": 1, "componentType": 5126, "count": 3, "type": "VEC3",
+                      "min": [0, 0, 1], "max": [1, 1, 1] }
+                ],
+                "materials": [{}, {}],
+                "meshes": [{ "primitives": [
+                    { "attributes": { "POSITION": 0 }, "material": 0 },
+                    { "attributes": { "POSITION": 1 }, "material": 1 }
+                ] }]
+            }"#,
+        )
+        .unwrap()
+        .document;
+
+        let buffers = [gltf::buffer::Data(buffer)];
+        let mesh = document.meshes().next().unwrap();
+        let primitives: Vec<MeshData> = mesh
+            .primitives()
+            .map(|primitive| MeshData::from_gltf_primitive(&primitive, &buffers).unwrap())
+            .collect();
+        assert_eq!(primitives.len(), 2);
+        assert_vec_eq(&primitives[0].positions[..3], &[0., 0., 0.]);
+        assert_vec_eq(&primitives[1].positions[..3], &[0., 0., 1.]);
+        let materials: Vec<_> = mesh.primitives().map(|p| p.material().index()).collect();
+        assert_eq!(materials, vec![Some(0), Some(1)]);
+    }
+
+    #[test]
+    fn reads_gltf_joints_and_weights() {
+        // Triangle with byte joint indices and normalized byte weights
+        let mut buffer = Vec::new();
+        for x in [0f32, 0., 0., 1., 0., 0., 0., 1., 0.].iter() {
+            buffer.extend_from_slice(&x.to_le_bytes());
+        }
+        buffer.extend_from_slice(&[0, 1, 0, 0, 1, 2, 0, 0, 2, 0, 0, 0]);
+        buffer.extend_from_slice(&[255, 0, 0, 0, 51, 204, 0, 0, 255, 0, 0, 0]);
+        let document = gltf::Gltf::from_slice(
+            br#"{
+                "asset": { "version": "2.0" },
+                "buffers": [{ "byteLength": 60 }],
+                "bufferViews": [
+                    { "buffer": 0, "byteLength": 36 },
+                    { "buffer": 0, "byteOffset": 36, "byteLength": 12 },
+                    { "buffer": 0, "byteOffset": 48, "byteLength": 12 }
+                ],
+                "accessors": [
+                    { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                      "min": [0, 0, 0], "max": [1, 1, 0] },
+                    { "bufferView": 1, "componentType": 5121, "count": 3, "type": "VEC4" },
+                    { "bufferView": 2, "componentType": 5121, "normalized": true,
+                      "count": 3, "type": "VEC4" }
+                ],
+                "meshes": [{ "primitives": [{
+                    "attributes": { "POSITION": 0, "JOINTS_0": 1, "WEIGHTS_0": 2 }
+                }] }]
+            }"#,
+        )
+        .unwrap()
+        .document;
+
+        let data = MeshData::from_gltf(&document, &[gltf::buffer::Data(buffer)]).unwrap();
+        assert_vec_eq(
+            &data.joints,
+            &[0., 1., 0., 0., 1., 2., 0., 0., 2., 0., 0., 0.],
+        );
+        assert_vec_eq(
+            &data.weights,
+            &[1., 0., 0., 0., 0.2, 0.8, 0., 0., 1., 0., 0., 0.],
+        );
+    }
+
+    #[test]
+    fn reads_gltf_morph_targets() {
+        // Triangle whose one target lifts the first vertex
+        let mut buffer = Vec::new();
+        for x in [
+            0f32, 0., 0., 1., 0., 0., 0., 1., 0., 0., 0., 1., 0., 0., 0., 0., 0., 0.,
+        ]
+        .iter()
+        {
+            buffer.extend_from_slice(&x.to_le_bytes());
+        }
+        let document = gltf::Gltf::from_slice(
+            br#"{
+                "asset": { "version": "2.0" },
+                "buffers": [{ "byteLength": 72 }],
+                "bufferViews": [
+                    { "buffer": 0, "byteLength": 36 },
+                    { "buffer": 0, "byteOffset": 36, "byteLength": 36 }
+                ],
+                "accessors": [
+                    { "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                      "min": [0, 0, 0], "max": [1, 1, 0] },
+                    { "bufferView": 1, "componentType": 5126, "count": 3, "type": "VEC3",
+                      "min": [0, 0, 0], "max": [0, 0, 1] }
+                ],
+                "meshes": [{ "primitives": [{
+                    "attributes": { "POSITION": 0 },
+                    "targets": [{ "POSITION": 1 }]
+                }] }]
+            }"#,
+        )
+        .unwrap()
+        .document;
+
+        let data = MeshData::from_gltf(&document, &[gltf::buffer::Data(buffer)]).unwrap();
+        assert_eq!(data.morph_targets.len(), 1);
+        assert_vec_eq(
+            &data.morph_targets[0].positions,
+            &[0., 0., 1., 0., 0., 0., 0., 0., 0.],
+        );
+        assert!(data.morph_targets[0].normals.is_empty());
+    }
+
+    #[test]
+    fn weld_keeps_morph_targets_apart() {
+        let mut quad = quad();
+        let mut positions = vec![0.; 18];
+        // Only the first triangle's copy of the shared vertex moves
+        positions[2] = 1.;
+        quad.morph_targets.push(MorphTarget {
+            positions,
+            normals: Vec::new(),
+            tangents: Vec::new(),
+        });
+        quad.weld(1e-4);
+        assert_eq!(quad.num_vertices(), 5);
+        assert_eq!(quad.morph_targets[0].positions.len(), 15);
+    }
+
+    #[test]
+    fn picks_most_influential_targets() {
+        assert_eq!(
+            most_influential(&[0.1, 0., -0.9, 0.5, 0.2]),
+            vec![(2, -0.9), (3, 0.5), (4, 0.2)]
+        );
+        assert!(most_influential(&[0., 0.]).is_empty());
+    }
+
+    #[test]
+    fn computes_bounds() {
+        let mut quad = quad();
+        for x in quad.positions.iter_mut() {
+            *x = *x * 2. - 1.;
+        }
+        quad.compute_bounds();
+        assert_vec_eq(quad.bounds.aabb.min.as_slice(), &[-1., -1., -1.]);
+        assert_vec_eq(quad.bounds.aabb.max.as_slice(), &[1., 1., -1.]);
+        assert!((quad.bounds.sphere.radius - 2f32.sqrt()).abs() < 1e-5);
+    }
+}
@@ -1,24 +1,251 @@
+use super::animation::Rig;
+use super::joint_texture::JointTexture;
+use super::material::*;
 use super::mesh::*;
+use super::mesh_data::{MeshData, Shading};
+use super::obj;
+use super::ply;
+use super::program::Attribute;
+use super::shader_library::Features;
+use super::skin::Skin;
+use super::stl;
+use super::utils;
+use nalgebra_glm as na;
+use std::collections::HashMap;
 use std::rc::Rc;
 use web_sys::WebGlRenderingContext as GL;
-use web_sys::WebGlTexture;
 
+#[derive(Clone)]
 pub struct Model {
     pub mesh: Rc<Mesh>,
-    pub color_map: WebGlTexture,
-    pub specular_map: WebGlTexture,
-    pub normal_map: WebGlTexture,
+    pub material: Rc<Material>,
+    pub skin: Option<Rc<Skin>>,
+    // For glTF, which places meshes with nodes and animates those
+    pub rig: Option<Rc<Rig>>,
 }
 
 impl Model {
-    pub fn render(&self, gl: &GL) {
-        gl.active_texture(GL::TEXTURE0);
-        gl.bind_texture(GL::TEXTURE_2D, Some(&self.color_map));
-        gl.active_texture(GL::TEXTURE1);
-        gl.bind_texture(GL::TEXTURE_2D, Some(&self.specular_map));
-        gl.active_texture(GL::TEXTURE2);
-        gl.bind_texture(GL::TEXTURE_2D, Some(&self.normal_map));
-
-        self.mesh.render(&gl);
+    // Shader features needed to draw it
+    pub fn features(&self, max_uniform_joints: usize) -> Features {
+        let features = self.material.features() | self.mesh.features();
+        match &self.skin {
+            Some(skin) if skin.needs_joint_texture(max_uniform_joints) => {
+                features | Features::SKINNING | Features::JOINT_TEXTURE
+            }
+            Some(_) => features | Features::SKINNING,
+            None => features,
+        }
+    }
+
+    // One model per group and material in the OBJ file. Materials missing from
+    // the MTL file are white and matte.
+    pub async fn load_obj(
+        gl: &GL,
+        attributes: &HashMap<String, Attribute>,
+        obj_url: &str,
+    ) -> Result<Vec<Model>, String> {
+        let obj = obj::parse_obj(&utils::fetch_resource_as_string(obj_url).await)?;
+
+        // Texture paths are relative to the MTL file, which is relative to the OBJ
+        let mut mtl_materials = HashMap::new();
+        for library in &obj.material_libraries {
+            let mtl_url = relative_url(obj_url, library);
+            let materials = obj::parse_mtl(&utils::fetch_resource_as_string(&mtl_url).await)?;
+            mtl_materials.extend(
+                materials
+                    .into_iter()
+                    .map(|(name, material)| (name, (mtl_url.clone(), material))),
+            );
+        }
+
+        let mut materials: HashMap<Option<String>, Rc<Material>> = HashMap::new();
+        obj.meshes
+            .iter()
+            .map(|obj_mesh| {
+                let material = materials
+                    .entry(obj_mesh.material.clone())
+                    .or_insert_with(|| {
+                        let (mtl_url, mtl) = match obj_mesh
+                            .material
+                            .as_ref()
+                            .and_then(|name| mtl_materials.get(name))
+                        {
+                            Some((mtl_url, mtl)) => (mtl_url.as_str(), Some(mtl)),
+                            None => (obj_url, None),
+                        };
+                        let texture = |path: Option<&String>| {
+                            path.map(|path| utils::load_texture(gl, &relative_url(mtl_url, path)))
+                        };
+                        let factors =
+                            mtl.map_or_else(|| obj::MtlMaterial::default().factors, |m| m.factors);
+                        Rc::new(Material {
+                            color_map: texture(mtl.and_then(|m| m.color_map.as_ref())),
+                            specular_map: texture(mtl.and_then(|m| m.specular_map.as_ref())),
+                            normal_map: texture(mtl.and_then(|m| m.normal_map.as_ref())),
+                            ..Material::untextured(factors)
+                        })
+                    });
+
+                Ok(Model {
+                    mesh: Rc::new(Mesh::new(gl, attributes, &obj_mesh.data)?),
+                    material: Rc::clone(material),
+                    skin: None,
+                    rig: None,
+                })
+            })
+            .collect()
+    }
+
+    pub async fn load_stl(
+        gl: &GL,
+        attributes: &HashMap<String, Attribute>,
+        url: &str,
+        shading: Shading,
+    ) -> Result<Model, String> {
+        let data = stl::parse_stl(&utils::fetch_resource_as_bytes(url).await, shading)?;
+        Ok(Model {
+            mesh: Rc::new(Mesh::new(gl, attributes, &data)?),
+            material: Rc::new(Material::untextured(matte())),
+            skin: None,
+            rig: None,
+        })
+    }
+
+    // Drawn with the vertex colors if it has them
+    pub async fn load_ply(
+        gl: &GL,
+        attributes: &HashMap<String, Attribute>,
+        url: &str,
+        shading: Shading,
+    ) -> Result<Model, String> {
+        let data = ply::parse_ply(&utils::fetch_resource_as_bytes(url).await, shading)?;
+        Ok(Model {
+            mesh: Rc::new(Mesh::new(gl, attributes, &data)?),
+            material: Rc::new(Material::untextured(matte())),
+            skin: None,
+            rig: None,
+        })
+    }
+
+    // The first skinned mesh in the file, or the first mesh if none are
+    // skinned, as a model per primitive with every animation. Buffers can be
+    // in the GLB or next to the file, images in either or in a buffer.
+    pub async fn load_gltf(
+        gl: &GL,
+        attributes: &HashMap<String, Attribute>,
+        max_uniform_joints: usize,
+        url: &str,
+    ) -> Result<Vec<Model>, String> {
+        let gltf = gltf::Gltf::from_slice(&utils::fetch_resource_as_bytes(url).await)
+            .map_err(|e| e.to_string())?;
+        let mut buffers = Vec::new();
+        for buffer in gltf.buffers() {
+            let data = match buffer.source() {
+                gltf::buffer::Source::Bin => gltf.blob.clone().ok_or("GLB has no binary chunk")?,
+                gltf::buffer::Source::Uri(uri) if uri.starts_with("data:") => {
+                    return Err("Buffers embedded as data URIs aren't supported".to_string())
+                }
+                gltf::buffer::Source::Uri(uri) => {
+                    utils::fetch_resource_as_bytes(&relative_url(url, uri)).await
+                }
+            };
+            buffers.push(gltf::buffer::Data(data));
+        }
+
+        let document = &gltf.document;
+        let node = document
+            .nodes()
+            .find(|node| node.mesh().is_some() && node.skin().is_some())
+            .or_else(|| document.nodes().find(|node| node.mesh().is_some()))
+            .ok_or("glTF has no nodes with meshes")?;
+        let skin = Skin::from_gltf(&node, &buffers)?.map(Rc::new);
+        if let Some(skin) = skin
+            .as_ref()
+            .filter(|skin| skin.needs_joint_texture(max_uniform_joints))
+        {
+            JointTexture::check_support(gl, skin.joints.len(), max_uniform_joints)?;
+        }
+        let rig = Rc::new(Rig::from_gltf(document, &node, &buffers)?);
+
+        // Primitives using the same material share it
+        let mut materials: HashMap<Option<usize>, Rc<Material>> = HashMap::new();
+        let mut models = Vec::new();
+        for primitive in node.mesh().unwrap().primitives() {
+            let data = MeshData::from_gltf_primitive(&primitive, &buffers)?;
+            if skin.is_some() && data.weights.is_empty() {
+                return Err("Skinned mesh has no joint weights".to_string());
+            }
+            let gltf_material = primitive.material();
+            let material = match materials.get(&gltf_material.index()) {
+                Some(material) => Rc::clone(material),
+                None => {
+                    let material = Rc::new(material_from_gltf(gl, url, &gltf_material, &buffers)?);
+                    materials.insert(gltf_material.index(), Rc::clone(&material));
+                    material
+                }
+            };
+            models.push(Model {
+                mesh: Rc::new(Mesh::new(gl, attributes, &data)?),
+                material,
+                skin: skin.clone(),
+                rig: Some(Rc::clone(&rig)),
+            });
+        }
+        Ok(models)
+    }
+}
+
+// Images can be next to the file or in a buffer, like in a GLB
+fn material_from_gltf(
+    gl: &GL,
+    url: &str,
+    material: &gltf::Material,
+    buffers: &[gltf::buffer::Data],
+) -> Result<Material, String> {
+    let pbr = material.pbr_metallic_roughness();
+    let factors = MaterialFactors {
+        base_color: na::make_vec4(&pbr.base_color_factor()),
+        emissive: na::make_vec3(&material.emissive_factor()),
+        ..MaterialFactors::default()
+    };
+    let color_map = match pbr.base_color_texture() {
+        Some(info) => Some(match info.texture().source().source() {
+            gltf::image::Source::Uri { uri, .. } => {
+                utils::load_texture(gl, &relative_url(url, uri))
+            }
+            gltf::image::Source::View { view, mime_type } => {
+                let bytes = buffers[view.buffer().index()]
+                    .get(view.offset()..view.offset() + view.length())
+                    .ok_or("Image is outside its buffer")?;
+                utils::load_texture_from_bytes(gl, bytes, mime_type)?
+            }
+        }),
+        None => None,
+    };
+
+    Ok(Material {
+        color_map,
+        alpha_mode: match material.alpha_mode() {
+            gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+            gltf::material::AlphaMode::Mask => AlphaMode::Mask(material.alpha_cutoff()),
+            gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+        },
+        double_sided: material.double_sided(),
+        ..Material::untextured(factors)
+    })
+}
+
+fn relative_url(base_url: &str, path: &str) -> String {
+    match base_url.rfind('/') {
+        Some(i) => format!("{}{}", &base_url[..=i], path),
+        None => path.to_string(),
+    }
+}
+
+// For formats without materials: vertex colors (or white) and no highlights
+fn matte() -> MaterialFactors {
+    MaterialFactors {
+        specular_strength: 0.,
+        ..MaterialFactors::default()
     }
 }
@@ -0,0 +1,536 @@
+// Wavefront OBJ and MTL parsing, into MeshData plus the material names and
+// texture paths, without loading anything
+
+use crate::material::MaterialFactors;
+use crate::mesh_data::{attribute3, MeshData};
+use nalgebra_glm as na;
+use std::collections::HashMap;
+
+pub struct Obj {
+    pub meshes: Vec<ObjMesh>,
+    pub material_libraries: Vec<String>,
+}
+
+// Faces sharing a group and material
+pub struct ObjMesh {
+    pub name: String,
+    pub material: Option<String>,
+    pub data: MeshData,
+}
+
+// Texture paths as written in the MTL file, relative to it, and the
+// Kd/Ks/Ns/Ke/d values as factors
+pub struct MtlMaterial {
+    pub color_map: Option<String>,
+    pub specular_map: Option<String>,
+    pub normal_map: Option<String>,
+    pub factors: MaterialFactors,
+}
+
+// Without a Ks line there are no highlights
+impl Default for MtlMaterial {
+    fn default() -> MtlMaterial {
+        MtlMaterial {
+            color_map: None,
+            specular_map: None,
+            normal_map: None,
+            factors: MaterialFactors {
+                specular_strength: 0.,
+                ..MaterialFactors::default()
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Corner {
+    position: usize,
+    texcoords: Option<usize>,
+    normal: Option<usize>,
+}
+
+struct Face {
+    corners: Vec<Corner>,
+    // None when smoothing is off
+    smoothing_group: Option<u32>,
+}
+
+struct Part {
+    name: String,
+    material: Option<String>,
+    faces: Vec<Face>,
+}
+
+pub fn parse_obj(source: &str) -> Result<Obj, String> {
+    let mut positions: Vec<f32> = Vec::new();
+    let mut texcoords: Vec<f32> = Vec::new();
+    let mut normals: Vec<f32> = Vec::new();
+    let mut material_libraries = Vec::new();
+
+    let mut parts: Vec<Part> = Vec::new();
+    let mut current: Option<usize> = None;
+    let mut name = String::new();
+    let mut material: Option<String> = None;
+    let mut smoothing_group = None;
+
+    for (line_number, line) in lines(source) {
+        let error = |message: &str| format!("OBJ line {}: {}", line_number, message);
+        let mut tokens = line.split_whitespace();
+        let keyword = tokens.next().unwrap();
+        let args: Vec<&str> = tokens.collect();
+
+        match keyword {
+            "v" => positions.extend(parse_floats(&args, 3, 3, &error)?),
+            "vn" => normals.extend(parse_floats(&args, 3, 3, &error)?),
+            "vt" => {
+                // v is flipped, OBJ has it going up but textures are uploaded
+                // top row first (like glTF)
+                let uv = parse_floats(&args, 1, 2, &error)?;
+                texcoords.extend_from_slice(&[uv[0], 1. - uv.get(1).unwrap_or(&0.)]);
+            }
+            "f" => {
+                if args.len() < 3 {
+                    return Err(error("Face with fewer than 3 vertices"));
+                }
+                let corners = args
+                    .iter()
+                    .map(|corner| {
+                        parse_corner(
+                            corner,
+                            [positions.len() / 3, texcoords.len() / 2, normals.len() / 3],
+                        )
+                        .ok_or_else(|| error(&format!("Bad face vertex \"{}\"", corner)))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                let part = *current.get_or_insert_with(|| {
+                    match parts
+                        .iter()
+                        .position(|part| part.name == name && part.material == material)
+                    {
+                        Some(i) => i,
+                        None => {
+                            parts.push(Part {
+                                name: name.clone(),
+                                material: material.clone(),
+                                faces: Vec::new(),
+                            });
+                            parts.len() - 1
+                        }
+                    }
+                });
+                parts[part].faces.push(Face {
+                    corners,
+                    smoothing_group,
+                });
+            }
+            "g" | "o" => {
+                name = args.join(" ");
+                current = None;
+            }
+            "usemtl" => {
+                material = Some(args.join(" "));
+                current = None;
+            }
+            "s" => {
+                smoothing_group = match args.first() {
+                    None | Some(&"off") | Some(&"0") => None,
+                    Some(group) => Some(group.parse().unwrap_or(1)),
+                }
+            }
+            "mtllib" => material_libraries.extend(args.iter().map(|s| s.to_string())),
+            // Lines, points, free-form geometry, etc.
+            _ => {}
+        }
+    }
+
+    let meshes = parts
+        .into_iter()
+        .map(|part| ObjMesh {
+            data: build_mesh(&part.faces, &positions, &texcoords, &normals),
+            name: part.name,
+            material: part.material,
+        })
+        .collect();
+
+    Ok(Obj {
+        meshes,
+        material_libraries,
+    })
+}
+
+pub fn parse_mtl(source: &str) -> Result<HashMap<String, MtlMaterial>, String> {
+    let mut materials = HashMap::new();
+    let mut current = None;
+
+    for (line_number, line) in lines(source) {
+        let (keyword, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let keyword = keyword.to_ascii_lowercase();
+
+        if keyword == "newmtl" {
+            let name = rest.trim().to_string();
+            materials.insert(name.clone(), MtlMaterial::default());
+            current = Some(name);
+            continue;
+        }
+        let known = [
+            "map_kd", "map_ks", "map_bump", "bump", "norm", "kd", "ks", "ns", "ke", "d", "tr",
+        ];
+        if !known.contains(&keyword.as_str()) {
+            continue;
+        }
+
+        let material: &mut MtlMaterial = match &current {
+            Some(name) => materials.get_mut(name).unwrap(),
+            None => {
+                return Err(format!(
+                    "MTL line {}: \"{}\" before newmtl",
+                    line_number, keyword
+                ))
+            }
+        };
+        let error = |message: &str| format!("MTL line {}: {}", line_number, message);
+        let args: Vec<&str> = rest.split_whitespace().collect();
+        let factors = &mut material.factors;
+        // Map options (-bm 1, -o u v w, ...) come before the file name, which
+        // is assumed to have no spaces
+        let path = args.last().map(|s| s.to_string());
+        match keyword.as_str() {
+            "map_kd" => material.color_map = path,
+            "map_ks" => material.specular_map = path,
+            "map_bump" | "bump" | "norm" => material.normal_map = path,
+            "kd" => {
+                let rgb = parse_floats(&args, 3, 3, &error)?;
+                factors.base_color = na::vec4(rgb[0], rgb[1], rgb[2], factors.base_color.w);
+            }
+            // The specular map is a single channel, so Ks becomes its strength
+            "ks" => {
+                let rgb = parse_floats(&args, 3, 3, &error)?;
+                factors.specular_strength = rgb[0].max(rgb[1]).max(rgb[2]);
+            }
+            // An exponent of 0 would light the whole surface
+            "ns" => factors.shininess = parse_floats(&args, 1, 1, &error)?[0].max(1.),
+            "ke" => factors.emissive = na::Vec3::from_vec(parse_floats(&args, 3, 3, &error)?),
+            "d" => factors.base_color.w = parse_floats(&args, 1, 1, &error)?[0],
+            _ => factors.base_color.w = 1. - parse_floats(&args, 1, 1, &error)?[0],
+        }
+    }
+
+    Ok(materials)
+}
+
+// Non-empty lines with comments stripped, numbered from 1
+fn lines(source: &str) -> impl Iterator<Item = (usize, &str)> {
+    source
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.split('#').next().unwrap().trim()))
+        .filter(|(_, line)| !line.is_empty())
+}
+
+// At least min numbers, ignoring any past max
+fn parse_floats(
+    args: &[&str],
+    min: usize,
+    max: usize,
+    error: &impl Fn(&str) -> String,
+) -> Result<Vec<f32>, String> {
+    if args.len() < min {
+        return Err(error(&format!("Expected {} numbers", min)));
+    }
+    args.iter()
+        .take(max)
+        .map(|arg| {
+            arg.parse()
+                .map_err(|_| error(&format!("Bad number \"{}\"", arg)))
+        })
+        .collect()
+}
+
+// "v", "v/vt", "v//vn" or "v/vt/vn", 1-based, or negative to count back from
+// the latest of each
+fn parse_corner(corner: &str, counts: [usize; 3]) -> Option<Corner> {
+    let mut indices = corner.split('/').take(3).enumerate().map(|(i, index)| {
+        if index.is_empty() {
+            return Some(None);
+        }
+        let index: i64 = index.parse().ok()?;
+        let count = counts[i] as i64;
+        let index = if index < 0 { count + index } else { index - 1 };
+        if (0..count).contains(&index) {
+            Some(Some(index as usize))
+        } else {
+            None
+        }
+    });
+
+    Some(Corner {
+        position: indices.next()???,
+        texcoords: indices.next().unwrap_or(Some(None))?,
+        normal: indices.next().unwrap_or(Some(None))?,
+    })
+}
+
+fn build_mesh(faces: &[Face], positions: &[f32], texcoords: &[f32], normals: &[f32]) -> MeshData {
+    // Normals for faces without them: summed per position and smoothing
+    // group, area weighted
+    let mut smooth_normals: HashMap<(usize, u32), na::Vec3> = HashMap::new();
+    for face in faces {
+        if let Some(group) = face.smoothing_group {
+            let normal = face_normal(face, positions);
+            for corner in face.corners.iter().filter(|c| c.normal.is_none()) {
+                *smooth_normals
+                    .entry((corner.position, group))
+                    .or_insert_with(na::zero) += normal;
+            }
+        }
+    }
+
+    #[derive(PartialEq, Eq, Hash)]
+    enum NormalKey {
+        Given(usize),
+        Smooth(u32),
+        Flat(usize),
+    }
+
+    let mut vertices = HashMap::new();
+    let mut data_positions = Vec::new();
+    let mut data_normals = Vec::new();
+    let mut data_texcoords = Vec::new();
+    let mut indices = Vec::new();
+    let has_texcoords = faces
+        .iter()
+        .flat_map(|face| &face.corners)
+        .any(|corner| corner.texcoords.is_some());
+
+    for (face_index, face) in faces.iter().enumerate() {
+        let corner_indices: Vec<u32> = face
+            .corners
+            .iter()
+            .map(|corner| {
+                let normal_key = match (corner.normal, face.smoothing_group) {
+                    (Some(normal), _) => NormalKey::Given(normal),
+                    (None, Some(group)) => NormalKey::Smooth(group),
+                    (None, None) => NormalKey::Flat(face_index),
+                };
+                let key = (corner.position, corner.texcoords, normal_key);
+                *vertices
+                    .entry(key)
+                    .or_insert_with_key(|(_, _, normal_key)| {
+                        let normal = match normal_key {
+                            NormalKey::Given(normal) => attribute3(normals, *normal),
+                            NormalKey::Smooth(group) => smooth_normals[&(corner.position, *group)],
+                            NormalKey::Flat(_) => face_normal(face, positions),
+                        };
+                        let normal = if na::length(&normal) > 0. {
+                            na::normalize(&normal)
+                        } else {
+                            na::vec3(0., 1., 0.)
+                        };
+
+                        data_positions
+                            .extend_from_slice(attribute3(positions, corner.position).as_slice());
+                        data_normals.extend_from_slice(normal.as_slice());
+                        if has_texcoords {
+                            let uv = corner
+                                .texcoords
+                                .map_or([0., 0.], |i| [texcoords[i * 2], texcoords[i * 2 + 1]]);
+                            data_texcoords.extend_from_slice(&uv);
+                        }
+                        (data_positions.len() / 3 - 1) as u32
+                    })
+            })
+            .collect();
+
+        // Fan triangulation, so n-gons have to be convex
+        for i in 1..corner_indices.len() - 1 {
+            indices.extend_from_slice(&[
+                corner_indices[0],
+                corner_indices[i],
+                corner_indices[i + 1],
+            ]);
+        }
+    }
+
+    MeshData::new(data_positions, data_normals, data_texcoords, indices)
+}
+
+// Newell's method, which works for non-planar n-gons too. Length is twice the
+// face's area.
+fn face_normal(face: &Face, positions: &[f32]) -> na::Vec3 {
+    let corners = &face.corners;
+    (0..corners.len())
+        .map(|i| {
+            let a = attribute3(positions, corners[i].position);
+            let b = attribute3(positions, corners[(i + 1) % corners.len()].position);
+            na::vec3(
+                (a.y - b.y) * (a.z + b.z),
+                (a.z - b.z) * (a.x + b.x),
+                (a.x - b.x) * (a.y + b.y),
+            )
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normal(data: &MeshData, i: usize) -> na::Vec3 {
+        attribute3(&data.normals, i)
+    }
+
+    #[test]
+    fn triangulates_ngons() {
+        let obj = parse_obj(
+            "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv -1 0.5 0\n\
+             f 1 2 3 4 5\n",
+        )
+        .unwrap();
+        let data = &obj.meshes[0].data;
+        assert_eq!(data.indices, vec![0, 1, 2, 0, 2, 3, 0, 3, 4]);
+        assert_eq!(normal(data, 0), na::vec3(0., 0., 1.));
+    }
+
+    #[test]
+    fn reads_all_corner_formats() {
+        let obj = parse_obj(
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\n\
+             vt 0 0\nvt 1 0\nvt 0 1\n\
+             vn 0 0 -1\n\
+             f 1/1/1 2//1 3/3\n",
+        )
+        .unwrap();
+        let data = &obj.meshes[0].data;
+        assert_eq!(data.texcoords, vec![0., 1., 0., 0., 0., 0.]);
+        assert_eq!(normal(data, 0), na::vec3(0., 0., -1.));
+        assert_eq!(normal(data, 1), na::vec3(0., 0., -1.));
+        assert_eq!(normal(data, 2), na::vec3(0., 0., 1.));
+    }
+
+    #[test]
+    fn resolves_negative_indices() {
+        let obj = parse_obj(
+            "v 9 9 9\nv 0 0 0\nv 1 0 0\nv 0 1 0\n\
+             f -3 -2 -1\n",
+        )
+        .unwrap();
+        assert_eq!(
+            obj.meshes[0].data.positions,
+            vec![0., 0., 0., 1., 0., 0., 0., 1., 0.]
+        );
+    }
+
+    #[test]
+    fn splits_groups_and_materials() {
+        let obj = parse_obj(
+            "mtllib a.mtl b.mtl\n\
+             v 0 0 0\nv 1 0 0\nv 0 1 0\n\
+             g first\nusemtl red\nf 1 2 3\n\
+             usemtl blue\nf 1 2 3\n\
+             g second\nf 1 2 3\n\
+             g first\nusemtl red\nf 3 2 1\n",
+        )
+        .unwrap();
+        assert_eq!(obj.material_libraries, vec!["a.mtl", "b.mtl"]);
+        let parts: Vec<_> = obj
+            .meshes
+            .iter()
+            .map(|mesh| {
+                (
+                    mesh.name.as_str(),
+                    mesh.material.as_deref(),
+                    mesh.data.indices.len() / 3,
+                )
+            })
+            .collect();
+        assert_eq!(
+            parts,
+            vec![
+                ("first", Some("red"), 2),
+                ("first", Some("blue"), 1),
+                ("second", Some("blue"), 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn smoothing_groups() {
+        // Two faces folded 90 degrees along the edge from vertex 1 to 2
+        let source = |s: &str| {
+            format!(
+                "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nv 1 0 -1\nv 1 1 -1\n\
+                 s {}\nf 1 2 3 4\nf 2 5 6 3\n",
+                s
+            )
+        };
+
+        let smooth = parse_obj(&source("1")).unwrap();
+        let data = &smooth.meshes[0].data;
+        assert_eq!(data.num_vertices(), 6);
+        let shared = normal(data, 1);
+        assert!((shared - na::normalize(&na::vec3(1., 0., 1.))).norm() < 1e-6);
+
+        let flat = parse_obj(&source("off")).unwrap();
+        let data = &flat.meshes[0].data;
+        assert_eq!(data.num_vertices(), 8);
+        assert_eq!(normal(data, 1), na::vec3(0., 0., 1.));
+        assert_eq!(normal(data, 4), na::vec3(1., 0., 0.));
+    }
+
+    #[test]
+    fn reports_bad_lines() {
+        let error = parse_obj("v 0 0 0\nv 1 0\n").err().unwrap();
+        assert!(error.starts_with("OBJ line 2"), "{}", error);
+        let error = parse_obj("v 0 0 0\nf 1 2 3\n").err().unwrap();
+        assert!(error.starts_with("OBJ line 2"), "{}", error);
+    }
+
+    #[test]
+    fn parses_mtl_maps() {
+        let materials = parse_mtl(
+            "# Exported\n\
+             newmtl brick\n\
+             Kd 1 1 1\n\
+             map_Kd textures/brick.png\n\
+             map_Ks brick_s.png\n\
+             map_bump -bm 0.5 brick_n.png\n\
+             newmtl glass\n\
+             norm glass_n.png\n",
+        )
+        .unwrap();
+        let brick = &materials["brick"];
+        assert_eq!(brick.color_map.as_deref(), Some("textures/brick.png"));
+        assert_eq!(brick.specular_map.as_deref(), Some("brick_s.png"));
+        assert_eq!(brick.normal_map.as_deref(), Some("brick_n.png"));
+        let glass = &materials["glass"];
+        assert_eq!(glass.color_map, None);
+        assert_eq!(glass.normal_map.as_deref(), Some("glass_n.png"));
+    }
+
+    #[test]
+    fn parses_mtl_factors() {
+        let materials = parse_mtl(
+            "newmtl red\n\
+             Kd 0.8 0.1 0.1\n\
+             Ks 0.2 0.5 0.3\n\
+             Ns 0\n\
+             Ke 0 0 0.25\n\
+             d 0.5\n\
+             newmtl plain\n\
+             Tr 0.25\n",
+        )
+        .unwrap();
+        let red = materials["red"].factors;
+        assert_eq!(red.base_color, na::vec4(0.8, 0.1, 0.1, 0.5));
+        assert_eq!(red.specular_strength, 0.5);
+        assert_eq!(red.shininess, 1.);
+        assert_eq!(red.emissive, na::vec3(0., 0., 0.25));
+        let plain = materials["plain"].factors;
+        assert_eq!(plain.base_color, na::vec4(1., 1., 1., 0.75));
+        assert_eq!(plain.specular_strength, 0.);
+
+        let error = parse_mtl("newmtl red\nKd 1 1\n").err().unwrap();
+        assert!(error.starts_with("MTL line 2"), "{}", error);
+    }
+}
@@ -1,9 +1,8 @@
+use super::bounds::Bounds;
 use super::model::*;
+use super::skin::Skeleton;
 use nalgebra_glm as na;
-use std::collections::HashMap;
 use std::rc::Rc;
-use web_sys::WebGlRenderingContext as GL;
-use web_sys::WebGlUniformLocation;
 
 #[derive(Clone)]
 pub struct Object {
@@ -11,26 +10,67 @@ pub struct Object {
     pub scale: na::Mat4,
     pub rotation: na::Mat4,
     pub translation: na::Mat4,
+    // Ids handed out to JS, shared by every object from the same load
+    pub id: u32,
+    // Poses the model's rig, which is drawn in its rest pose without one
+    pub pose: Option<Skeleton>,
 }
 
 impl Object {
-    pub fn render(&self, gl: &GL, uniform_locations: &HashMap<String, WebGlUniformLocation>) {
-        // World
+    pub fn world(&self) -> na::Mat4 {
         let world = self.translation * self.rotation * self.scale;
-        gl.uniform_matrix4fv_with_f32_array(
-            Some(uniform_locations.get("u_world").unwrap()),
-            false,
-            &world.as_slice(),
-        );
+        // glTF meshes are placed by their node, unless they're skinned and
+        // the joints place them
+        match (&self.model.rig, self.skeleton()) {
+            (Some(rig), Some(skeleton)) if self.model.skin.is_none() => {
+                world * skeleton.global_transform(rig.mesh_node)
+            }
+            _ => world,
+        }
+    }
+
+    // The pose, or the rig's rest pose. None without a rig.
+    fn skeleton(&self) -> Option<&Skeleton> {
+        let rig = self.model.rig.as_ref()?;
+        Some(self.pose.as_ref().unwrap_or(&rig.rest_pose))
+    }
+
+    // Empty if the model isn't skinned
+    pub fn joint_matrices(&self) -> Vec<na::Mat4> {
+        match (&self.model.skin, self.skeleton()) {
+            (Some(skin), Some(skeleton)) => skin.joint_matrices(skeleton),
+            _ => Vec::new(),
+        }
+    }
+
+    // Of the mesh's morph targets
+    pub fn morph_weights(&self) -> Vec<f32> {
+        match (&self.model.rig, self.skeleton()) {
+            (Some(rig), Some(skeleton)) => skeleton.nodes[rig.mesh_node].weights.clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    // Skinned and morphed meshes are drawn one at a time, and their bounds
+    // are only right for the rest pose
+    pub fn deforms(&self) -> bool {
+        self.model.skin.is_some() || self.model.mesh.has_morph_targets()
+    }
+
+    pub fn bounds(&self) -> Bounds {
+        self.model.mesh.bounds.transform(&self.world())
+    }
+
+    // Append this object's per-instance attributes (see instancing::INSTANCE_SIZE)
+    pub fn push_instance_data(&self, data: &mut Vec<f32>, material_index: usize) {
+        // World
+        let world = self.world();
+        data.extend_from_slice(world.as_slice());
 
         // World Inverse Transpose
-        let world_inverse_transpose = na::transpose(&na::inverse(&world));
-        gl.uniform_matrix4fv_with_f32_array(
-            Some(uniform_locations.get("u_world_inverse_transpose").unwrap()),
-            false,
-            &world_inverse_transpose.as_slice(),
-        );
-
-        self.model.render(&gl);
+        let world_inverse_transpose = na::mat4_to_mat3(&na::transpose(&na::inverse(&world)));
+        data.extend_from_slice(world_inverse_transpose.as_slice());
+
+        data.push(material_index as f32);
     }
 }
@@ -0,0 +1,165 @@
+// Settings fixed when the engine is created, from the object passed to its
+// constructor
+
+use nalgebra_glm as na;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::{WebGlContextAttributes, WebGlPowerPreference};
+
+pub struct EngineOptions {
+    // The WebGL context attributes
+    pub antialias: bool,
+    pub alpha: bool,
+    pub premultiplied_alpha: bool,
+    pub preserve_drawing_buffer: bool,
+    pub power_preference: WebGlPowerPreference,
+    pub clear_color: na::Vec4,
+    // The eight spinning blocks
+    pub default_scene: bool,
+    // What relative URLs of assets are relative to, the page when empty
+    pub asset_base_url: String,
+    // Logs loads, resizes and shader compiles to the console
+    pub debug: bool,
+}
+
+impl Default for EngineOptions {
+    fn default() -> EngineOptions {
+        EngineOptions {
+            antialias: true,
+            alpha: true,
+            premultiplied_alpha: true,
+            preserve_drawing_buffer: false,
+            power_preference: WebGlPowerPreference::Default,
+            clear_color: na::vec4(0.8, 0.8, 0.8, 1.),
+            default_scene: false,
+            asset_base_url: String::new(),
+            debug: false,
+        }
+    }
+}
+
+impl EngineOptions {
+    // Those left out keep their defaults, unknown ones are an error
+    pub fn from_js(value: &JsValue) -> Result<EngineOptions, String> {
+        let mut options = EngineOptions::default();
+        if value.is_undefined() || value.is_null() {
+            return Ok(options);
+        }
+        let object = value
+            .dyn_ref::<js_sys::Object>()
+            .ok_or("Engine options must be an object")?;
+        for key in js_sys::Object::keys(object).iter() {
+            let key = key.as_string().unwrap_or_default();
+            let value = js_sys::Reflect::get(object, &key.as_str().into()).unwrap();
+            let boolean = || {
+                value
+                    .as_bool()
+                    .ok_or_else(|| format!("Engine option {} must be true or false", key))
+            };
+            let string = || {
+                value
+                    .as_string()
+                    .ok_or_else(|| format!("Engine option {} must be a string", key))
+            };
+            match key.as_str() {
+                "antialias" => options.antialias = boolean()?,
+                "alpha" => options.alpha = boolean()?,
+                "premultipliedAlpha" => options.premultiplied_alpha = boolean()?,
+                "preserveDrawingBuffer" => options.preserve_drawing_buffer = boolean()?,
+                "powerPreference" => {
+                    let power_preference = string()?;
+                    options.power_preference =
+                        WebGlPowerPreference::from_js_value(&power_preference.as_str().into())
+                            .ok_or_else(|| {
+                                format!(
+                                    "Engine option powerPreference must be \"default\", \
+                                     \"high-performance\" or \"low-power\", not \"{}\"",
+                                    power_preference
+                                )
+                            })?;
+                }
+                "clearColor" => {
+                    let values: Vec<f32> = js_sys::Array::from(&value)
+                        .iter()
+                        .map(|value| value.as_f64().map_or(f32::NAN, |x| x as f32))
+                        .collect();
+                    options.clear_color = clear_color(&values)?;
+                }
+                "defaultScene" => options.default_scene = boolean()?,
+                "assetBaseUrl" => options.asset_base_url = string()?,
+                "debug" => options.debug = boolean()?,
+                _ => return Err(format!("Unknown engine option \"{}\"", key)),
+            }
+        }
+        Ok(options)
+    }
+
+    pub fn context_attributes(&self) -> WebGlContextAttributes {
+        let attributes = WebGlContextAttributes::new();
+        attributes.set_antialias(self.antialias);
+        attributes.set_alpha(self.alpha);
+        attributes.set_premultiplied_alpha(self.premultiplied_alpha);
+        attributes.set_preserve_drawing_buffer(self.preserve_drawing_buffer);
+        attributes.set_power_preference(self.power_preference);
+        attributes
+    }
+
+    pub fn asset_url(&self, url: &str) -> String {
+        resolve_url(&self.asset_base_url, url)
+    }
+}
+
+// RGB, or RGBA
+fn clear_color(values: &[f32]) -> Result<na::Vec4, String> {
+    if !(3..=4).contains(&values.len()) || values.iter().any(|x| !x.is_finite()) {
+        return Err("Engine option clearColor must be 3 or 4 numbers".to_string());
+    }
+    Ok(na::vec4(
+        values[0],
+        values[1],
+        values[2],
+        values.get(3).copied().unwrap_or(1.),
+    ))
+}
+
+// Absolute URLs and those from the server's root are left alone
+fn resolve_url(base_url: &str, url: &str) -> String {
+    let absolute = url.starts_with('/')
+        || url.contains("://")
+        || url.starts_with("data:")
+        || url.starts_with("blob:");
+    if base_url.is_empty() || absolute {
+        url.to_string()
+    } else {
+        format!("{}/{}", base_url.trim_end_matches('/'), url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_relative_urls() {
+        assert_eq!(resolve_url("", "cube.gltf"), "cube.gltf");
+        assert_eq!(resolve_url("assets", "cube.gltf"), "assets/cube.gltf");
+        assert_eq!(
+            resolve_url("https://cdn.example.com/assets/", "textures/a.png"),
+            "https://cdn.example.com/assets/textures/a.png"
+        );
+        assert_eq!(resolve_url("assets", "/cube.gltf"), "/cube.gltf");
+        assert_eq!(
+            resolve_url("assets", "http://example.com/cube.gltf"),
+            "http://example.com/cube.gltf"
+        );
+        assert_eq!(resolve_url("assets", "data:,x"), "data:,x");
+    }
+
+    #[test]
+    fn reads_clear_colors() {
+        assert_eq!(clear_color(&[1., 0., 0.]), Ok(na::vec4(1., 0., 0., 1.)));
+        assert_eq!(clear_color(&[0., 0., 0., 0.]), Ok(na::vec4(0., 0., 0., 0.)));
+        assert!(clear_color(&[1., 0.]).is_err());
+        assert!(clear_color(&[1., f32::NAN, 0.]).is_err());
+    }
+}
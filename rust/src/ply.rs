@@ -0,0 +1,388 @@
+// PLY parsing, ASCII or binary. Reads vertex positions, normals and colors
+// and the face lists, and skips any other elements and properties.
+
+use crate::mesh_data::{MeshData, Shading};
+use std::convert::TryInto;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Clone, Copy)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Option<Scalar> {
+        Some(match name {
+            "char" | "int8" => Scalar::I8,
+            "uchar" | "uint8" => Scalar::U8,
+            "short" | "int16" => Scalar::I16,
+            "ushort" | "uint16" => Scalar::U16,
+            "int" | "int32" => Scalar::I32,
+            "uint" | "uint32" => Scalar::U32,
+            "float" | "float32" => Scalar::F32,
+            "double" | "float64" => Scalar::F64,
+            _ => return None,
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+
+    // What integer colors are divided by to get 0..1
+    fn color_scale(self) -> f32 {
+        match self {
+            Scalar::U8 => 255.,
+            Scalar::U16 => 65535.,
+            _ => 1.,
+        }
+    }
+}
+
+enum Property {
+    Scalar(Scalar),
+    List { count: Scalar, item: Scalar },
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<(String, Property)>,
+}
+
+pub fn parse_ply(bytes: &[u8], shading: Shading) -> Result<MeshData, String> {
+    let header_end = find(bytes, b"end_header")
+        .and_then(|i| {
+            bytes[i..]
+                .iter()
+                .position(|&b| b == b'\n')
+                .map(|j| i + j + 1)
+        })
+        .ok_or("PLY has no end_header")?;
+    let (format, elements) = parse_header(&String::from_utf8_lossy(&bytes[..header_end]))?;
+
+    let mut reader = Reader {
+        format,
+        bytes: &bytes[header_end..],
+        offset: 0,
+        tokens: match format {
+            Format::Ascii => String::from_utf8_lossy(&bytes[header_end..])
+                .split_whitespace()
+                .map(|s| s.to_string())
+                .collect(),
+            _ => Vec::new(),
+        },
+    };
+
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    let mut colors = Vec::new();
+    let mut indices = Vec::new();
+
+    for element in &elements {
+        let column = |name: &str| element.properties.iter().position(|(n, _)| n == name);
+        let position_columns = [column("x"), column("y"), column("z")];
+        let normal_columns = [column("nx"), column("ny"), column("nz")];
+        let color_columns = [
+            column("red"),
+            column("green"),
+            column("blue"),
+            column("alpha"),
+        ];
+        let index_column = column("vertex_indices").or_else(|| column("vertex_index"));
+
+        for _ in 0..element.count {
+            let mut values = Vec::with_capacity(element.properties.len());
+            let mut list = Vec::new();
+            for (i, (_, property)) in element.properties.iter().enumerate() {
+                match *property {
+                    Property::Scalar(scalar) => values.push(reader.read(scalar)?),
+                    Property::List { count, item } => {
+                        values.push(0.);
+                        let count = reader.read(count)? as usize;
+                        let items = (0..count)
+                            .map(|_| reader.read(item))
+                            .collect::<Result<Vec<_>, _>>()?;
+                        if Some(i) == index_column {
+                            list = items;
+                        }
+                    }
+                }
+            }
+
+            match element.name.as_str() {
+                "vertex" => {
+                    for column in position_columns.iter() {
+                        positions
+                            .push(values[column.ok_or("PLY vertex missing x, y or z")?] as f32);
+                    }
+                    if normal_columns.iter().all(Option::is_some) {
+                        normals.extend(normal_columns.iter().map(|c| values[c.unwrap()] as f32));
+                    }
+                    if color_columns[..3].iter().all(Option::is_some) {
+                        colors.extend(color_columns.iter().map(|column| match column {
+                            Some(c) => {
+                                let scale = match element.properties[*c].1 {
+                                    Property::Scalar(scalar) => scalar.color_scale(),
+                                    _ => 1.,
+                                };
+                                values[*c] as f32 / scale
+                            }
+                            None => 1.,
+                        }));
+                    }
+                }
+                // Fan triangulation, so polygons have to be convex
+                "face" => {
+                    for i in 1..list.len().saturating_sub(1) {
+                        indices.extend_from_slice(&[
+                            list[0] as u32,
+                            list[i] as u32,
+                            list[i + 1] as u32,
+                        ]);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let num_vertices = positions.len() / 3;
+    if indices.is_empty() {
+        return Err("PLY has no faces".to_string());
+    }
+    if let Some(&i) = indices.iter().find(|&&i| i as usize >= num_vertices) {
+        return Err(format!("PLY face uses vertex {} of {}", i, num_vertices));
+    }
+
+    // Texcoords aren't read, PLY meshes are drawn untextured
+    Ok(if normals.is_empty() {
+        MeshData::from_triangles(positions, colors, indices, shading)
+    } else {
+        let mut data = MeshData::new(positions, normals, Vec::new(), indices);
+        data.colors = colors;
+        data
+    })
+}
+
+fn parse_header(header: &str) -> Result<(Format, Vec<Element>), String> {
+    let mut lines = header.lines().enumerate();
+    if lines.next().map(|(_, line)| line.trim()) != Some("ply") {
+        return Err("Not a PLY file".to_string());
+    }
+
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    for (i, line) in lines {
+        let error = |message: &str| format!("PLY header line {}: {}", i + 1, message);
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        match tokens.as_slice() {
+            ["format", name, _version] => {
+                format = Some(match *name {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::BinaryLittleEndian,
+                    "binary_big_endian" => Format::BinaryBigEndian,
+                    _ => return Err(error(&format!("Unknown format \"{}\"", name))),
+                })
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count.parse().map_err(|_| error("Bad element count"))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count, item, name] => {
+                let property = match (Scalar::parse(count), Scalar::parse(item)) {
+                    (Some(count), Some(item)) => Property::List { count, item },
+                    _ => return Err(error("Unknown property type")),
+                };
+                elements
+                    .last_mut()
+                    .ok_or_else(|| error("Property before element"))?
+                    .properties
+                    .push((name.to_string(), property));
+            }
+            ["property", scalar, name] => {
+                let property = Property::Scalar(
+                    Scalar::parse(scalar).ok_or_else(|| error("Unknown property type"))?,
+                );
+                elements
+                    .last_mut()
+                    .ok_or_else(|| error("Property before element"))?
+                    .properties
+                    .push((name.to_string(), property));
+            }
+            // comment, obj_info, end_header
+            _ => {}
+        }
+    }
+
+    Ok((format.ok_or("PLY has no format")?, elements))
+}
+
+struct Reader<'a> {
+    format: Format,
+    bytes: &'a [u8],
+    offset: usize,
+    // Every token after the header, for ASCII files
+    tokens: Vec<String>,
+}
+
+impl Reader<'_> {
+    fn read(&mut self, scalar: Scalar) -> Result<f64, String> {
+        if self.format == Format::Ascii {
+            let token = self.tokens.get(self.offset).ok_or("PLY data ends early")?;
+            self.offset += 1;
+            return token
+                .parse()
+                .map_err(|_| format!("PLY has bad number \"{}\"", token));
+        }
+
+        let bytes = self
+            .bytes
+            .get(self.offset..self.offset + scalar.size())
+            .ok_or("PLY data ends early")?;
+        self.offset += scalar.size();
+
+        let mut array = [0; 8];
+        array[..bytes.len()].copy_from_slice(bytes);
+        if self.format == Format::BinaryBigEndian {
+            array[..bytes.len()].reverse();
+        }
+        Ok(match scalar {
+            Scalar::I8 => array[0] as i8 as f64,
+            Scalar::U8 => array[0] as f64,
+            Scalar::I16 => i16::from_le_bytes(array[..2].try_into().unwrap()) as f64,
+            Scalar::U16 => u16::from_le_bytes(array[..2].try_into().unwrap()) as f64,
+            Scalar::I32 => i32::from_le_bytes(array[..4].try_into().unwrap()) as f64,
+            Scalar::U32 => u32::from_le_bytes(array[..4].try_into().unwrap()) as f64,
+            Scalar::F32 => f32::from_le_bytes(array[..4].try_into().unwrap()) as f64,
+            Scalar::F64 => f64::from_le_bytes(array),
+        })
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "ply\n\
+        format {}\n\
+        comment made by hand\n\
+        element vertex 4\n\
+        property float x\n\
+        property float y\n\
+        property float z\n\
+        property uchar red\n\
+        property uchar green\n\
+        property uchar blue\n\
+        element face 1\n\
+        property list uchar int vertex_indices\n\
+        end_header\n";
+
+    // Unit square facing +Z as a single quad
+    const POSITIONS: [f32; 12] = [0., 0., 0., 1., 0., 0., 1., 1., 0., 0., 1., 0.];
+    const COLORS: [u8; 12] = [255, 0, 0, 0, 255, 0, 0, 0, 255, 255, 255, 255];
+
+    fn binary(
+        format: &str,
+        to_bytes: fn(f32) -> [u8; 4],
+        index_bytes: fn(i32) -> [u8; 4],
+    ) -> Vec<u8> {
+        let mut bytes = HEADER.replace("{}", format).into_bytes();
+        for (position, color) in POSITIONS.chunks_exact(3).zip(COLORS.chunks_exact(3)) {
+            for &x in position {
+                bytes.extend_from_slice(&to_bytes(x));
+            }
+            bytes.extend_from_slice(color);
+        }
+        bytes.push(4);
+        for i in 0..4 {
+            bytes.extend_from_slice(&index_bytes(i));
+        }
+        bytes
+    }
+
+    fn check(data: &MeshData) {
+        assert_eq!(data.positions, POSITIONS.to_vec());
+        assert_eq!(data.indices, vec![0, 1, 2, 0, 2, 3]);
+        assert_eq!(&data.colors[..8], &[1., 0., 0., 1., 0., 1., 0., 1.]);
+        assert_eq!(&data.normals[..3], &[0., 0., 1.]);
+    }
+
+    #[test]
+    fn parses_ascii() {
+        let mut source = HEADER.replace("{}", "ascii 1.0");
+        for (position, color) in POSITIONS.chunks_exact(3).zip(COLORS.chunks_exact(3)) {
+            source += &format!(
+                "{} {} {} {} {} {}\n",
+                position[0], position[1], position[2], color[0], color[1], color[2]
+            );
+        }
+        source += "4 0 1 2 3\n";
+        check(&parse_ply(source.as_bytes(), Shading::Smooth).unwrap());
+    }
+
+    #[test]
+    fn parses_binary() {
+        let little = binary(
+            "binary_little_endian 1.0",
+            f32::to_le_bytes,
+            i32::to_le_bytes,
+        );
+        check(&parse_ply(&little, Shading::Smooth).unwrap());
+        let big = binary("binary_big_endian 1.0", f32::to_be_bytes, i32::to_be_bytes);
+        check(&parse_ply(&big, Shading::Smooth).unwrap());
+    }
+
+    #[test]
+    fn flat_shading_unwelds() {
+        let little = binary(
+            "binary_little_endian 1.0",
+            f32::to_le_bytes,
+            i32::to_le_bytes,
+        );
+        let data = parse_ply(&little, Shading::Flat).unwrap();
+        assert_eq!(data.num_vertices(), 6);
+        assert_eq!(data.colors.len(), 24);
+    }
+
+    #[test]
+    fn reports_errors() {
+        let source = HEADER.replace("{}", "ascii 1.0") + "0 0 0 255 0 0\n";
+        assert_eq!(
+            parse_ply(source.as_bytes(), Shading::Flat).err().unwrap(),
+            "PLY data ends early"
+        );
+        let source = HEADER
+            .replace("{}", "ascii 1.0")
+            .replace("uchar blue", "color blue");
+        assert_eq!(
+            parse_ply(source.as_bytes(), Shading::Flat).err().unwrap(),
+            "PLY header line 10: Unknown property type"
+        );
+    }
+}
@@ -0,0 +1,559 @@
+// Procedural primitive meshes, centered on the origin with Y up
+//
+// Texcoords follow the glTF convention (v down) and go through the same
+// normal and tangent handling as loaded meshes, so they can be used anywhere
+// a loaded mesh can. Counts too small to make the shape, like 0 segments,
+// are raised to the least that does.
+
+use crate::mesh_data::MeshData;
+use nalgebra_glm as na;
+use std::f32::consts::PI;
+
+// Flat plane facing +Y, with the top of the texture towards -Z
+pub fn plane(width: f32, depth: f32, subdivisions_x: u32, subdivisions_z: u32) -> MeshData {
+    let mut builder = Builder::default();
+    builder.grid(subdivisions_x, subdivisions_z, |u, v| {
+        (
+            na::vec3((u - 0.5) * width, 0., (v - 0.5) * depth),
+            na::vec3(0., 1., 0.),
+        )
+    });
+    builder.build()
+}
+
+// Box with every face subdivided into a grid and mapped to the whole texture
+pub fn cuboid(size: na::Vec3, subdivisions: u32) -> MeshData {
+    // Normal, then the directions of u and v on that face
+    let faces = [
+        (
+            na::vec3(1., 0., 0.),
+            na::vec3(0., 0., -1.),
+            na::vec3(0., -1., 0.),
+        ),
+        (
+            na::vec3(-1., 0., 0.),
+            na::vec3(0., 0., 1.),
+            na::vec3(0., -1., 0.),
+        ),
+        (
+            na::vec3(0., 1., 0.),
+            na::vec3(1., 0., 0.),
+            na::vec3(0., 0., 1.),
+        ),
+        (
+            na::vec3(0., -1., 0.),
+            na::vec3(1., 0., 0.),
+            na::vec3(0., 0., -1.),
+        ),
+        (
+            na::vec3(0., 0., 1.),
+            na::vec3(1., 0., 0.),
+            na::vec3(0., -1., 0.),
+        ),
+        (
+            na::vec3(0., 0., -1.),
+            na::vec3(-1., 0., 0.),
+            na::vec3(0., -1., 0.),
+        ),
+    ];
+
+    let mut builder = Builder::default();
+    for (normal, u_axis, v_axis) in faces.iter() {
+        builder.grid(subdivisions, subdivisions, |u, v| {
+            let position =
+                (normal * 0.5 + u_axis * (u - 0.5) + v_axis * (v - 0.5)).component_mul(&size);
+            (position, *normal)
+        });
+    }
+    builder.build()
+}
+
+pub fn uv_sphere(radius: f32, segments: u32, rings: u32) -> MeshData {
+    let rings = rings.max(2);
+    let profile: Vec<ProfilePoint> = (0..=rings)
+        .map(|ring| {
+            let v = ring as f32 / rings as f32;
+            let theta = v * PI;
+            ProfilePoint {
+                radius: theta.sin() * radius,
+                y: theta.cos() * radius,
+                normal: na::vec2(theta.sin(), theta.cos()),
+                v,
+            }
+        })
+        .collect();
+
+    let mut builder = Builder::default();
+    builder.revolve(&profile, segments);
+    builder.build()
+}
+
+// Subdivided icosahedron, with the same spherical texture mapping as uv_sphere
+pub fn icosphere(radius: f32, subdivisions: u32) -> MeshData {
+    let t = (1. + 5f32.sqrt()) / 2.;
+    let vertices = [
+        na::vec3(-1., t, 0.),
+        na::vec3(1., t, 0.),
+        na::vec3(-1., -t, 0.),
+        na::vec3(1., -t, 0.),
+        na::vec3(0., -1., t),
+        na::vec3(0., 1., t),
+        na::vec3(0., -1., -t),
+        na::vec3(0., 1., -t),
+        na::vec3(t, 0., -1.),
+        na::vec3(t, 0., 1.),
+        na::vec3(-t, 0., -1.),
+        na::vec3(-t, 0., 1.),
+    ]
+    .map(|v| na::normalize(&v));
+    let faces = [
+        [0, 11, 5],
+        [0, 5, 1],
+        [0, 1, 7],
+        [0, 7, 10],
+        [0, 10, 11],
+        [1, 5, 9],
+        [5, 11, 4],
+        [11, 10, 2],
+        [10, 7, 6],
+        [7, 1, 8],
+        [3, 9, 4],
+        [3, 4, 2],
+        [3, 2, 6],
+        [3, 6, 8],
+        [3, 8, 9],
+        [4, 9, 5],
+        [2, 4, 11],
+        [6, 2, 10],
+        [8, 6, 7],
+        [9, 8, 1],
+    ];
+
+    let mut triangles: Vec<[na::Vec3; 3]> =
+        faces.iter().map(|face| face.map(|i| vertices[i])).collect();
+    for _ in 0..subdivisions {
+        triangles = triangles
+            .iter()
+            .flat_map(|[a, b, c]| {
+                let ab = na::normalize(&(a + b));
+                let bc = na::normalize(&(b + c));
+                let ca = na::normalize(&(c + a));
+                [[*a, ab, ca], [*b, bc, ab], [*c, ca, bc], [ab, bc, ca]]
+            })
+            .collect();
+    }
+
+    // Vertices aren't shared while working out texcoords, since triangles
+    // crossing the seam or touching a pole need their own, then get welded
+    let mut builder = Builder::default();
+    for triangle in triangles {
+        let mut uvs = triangle.map(|n| {
+            na::vec2(
+                (n.x.atan2(n.z) + PI) / (2. * PI),
+                n.y.clamp(-1., 1.).acos() / PI,
+            )
+        });
+
+        let max_u = uvs.iter().map(|uv| uv.x).fold(0., f32::max);
+        for uv in uvs.iter_mut() {
+            if max_u - uv.x > 0.5 {
+                uv.x += 1.;
+            }
+        }
+
+        // u is meaningless at the poles, use the middle of the other two
+        for i in 0..3 {
+            if triangle[i].x.abs() < 1e-6 && triangle[i].z.abs() < 1e-6 {
+                uvs[i].x = (uvs[(i + 1) % 3].x + uvs[(i + 2) % 3].x) / 2.;
+            }
+        }
+
+        for i in 0..3 {
+            let index = builder.vertex(triangle[i] * radius, triangle[i], uvs[i]);
+            builder.indices.push(index);
+        }
+    }
+
+    let mut data = builder.build();
+    data.weld(1e-5);
+    data
+}
+
+pub fn cylinder(radius: f32, height: f32, segments: u32, rings: u32) -> MeshData {
+    let rings = rings.max(1);
+    let profile: Vec<ProfilePoint> = (0..=rings)
+        .map(|ring| {
+            let v = ring as f32 / rings as f32;
+            ProfilePoint {
+                radius,
+                y: (0.5 - v) * height,
+                normal: na::vec2(1., 0.),
+                v,
+            }
+        })
+        .collect();
+
+    let mut builder = Builder::default();
+    builder.revolve(&profile, segments);
+    builder.disk(radius, height / 2., true, segments);
+    builder.disk(radius, -height / 2., false, segments);
+    builder.build()
+}
+
+// Apex at the top
+pub fn cone(radius: f32, height: f32, segments: u32, rings: u32) -> MeshData {
+    let rings = rings.max(1);
+    let normal = na::normalize(&na::vec2(height, radius));
+    let profile: Vec<ProfilePoint> = (0..=rings)
+        .map(|ring| {
+            let v = ring as f32 / rings as f32;
+            ProfilePoint {
+                radius: v * radius,
+                y: (0.5 - v) * height,
+                normal,
+                v,
+            }
+        })
+        .collect();
+
+    let mut builder = Builder::default();
+    builder.revolve(&profile, segments);
+    builder.disk(radius, -height / 2., false, segments);
+    builder.build()
+}
+
+// Lying flat, around the Y axis
+pub fn torus(major_radius: f32, minor_radius: f32, segments: u32, sides: u32) -> MeshData {
+    let sides = sides.max(3);
+    // Starts on the inside and goes over the top, so v runs down the outside
+    let profile: Vec<ProfilePoint> = (0..=sides)
+        .map(|side| {
+            let v = side as f32 / sides as f32;
+            let angle = PI - v * 2. * PI;
+            ProfilePoint {
+                radius: major_radius + angle.cos() * minor_radius,
+                y: angle.sin() * minor_radius,
+                normal: na::vec2(angle.cos(), angle.sin()),
+                v,
+            }
+        })
+        .collect();
+
+    let mut builder = Builder::default();
+    builder.revolve(&profile, segments);
+    builder.build()
+}
+
+// Cylinder of the given height with a hemisphere on each end, each hemisphere
+// made of rings rings. The texture is spread evenly along the whole length.
+pub fn capsule(radius: f32, height: f32, segments: u32, rings: u32) -> MeshData {
+    let rings = rings.max(1);
+    let length = PI * radius + height;
+    let hemisphere = |top: bool| {
+        (0..=rings).map(move |ring| {
+            let theta = ring as f32 / rings as f32 * PI / 2.;
+            let (theta, y, arc) = if top {
+                (theta, height / 2., theta * radius)
+            } else {
+                (
+                    theta + PI / 2.,
+                    -height / 2.,
+                    theta * radius + PI / 2. * radius + height,
+                )
+            };
+            ProfilePoint {
+                radius: theta.sin() * radius,
+                y: y + theta.cos() * radius,
+                normal: na::vec2(theta.sin(), theta.cos()),
+                v: arc / length,
+            }
+        })
+    };
+    let profile: Vec<ProfilePoint> = hemisphere(true).chain(hemisphere(false)).collect();
+
+    let mut builder = Builder::default();
+    builder.revolve(&profile, segments);
+    builder.build()
+}
+
+// A point on the outline of a surface of revolution, with the normal given as
+// (outward, up) in the plane of the outline
+struct ProfilePoint {
+    radius: f32,
+    y: f32,
+    normal: na::Vec2,
+    v: f32,
+}
+
+#[derive(Default)]
+struct Builder {
+    positions: Vec<f32>,
+    normals: Vec<f32>,
+    texcoords: Vec<f32>,
+    indices: Vec<u32>,
+}
+
+impl Builder {
+    fn vertex(&mut self, position: na::Vec3, normal: na::Vec3, uv: na::Vec2) -> u32 {
+        let index = (self.positions.len() / 3) as u32;
+        self.positions.extend_from_slice(position.as_slice());
+        self.normals.extend_from_slice(normal.as_slice());
+        self.texcoords.extend_from_slice(uv.as_slice());
+        index
+    }
+
+    // Grid of vertices over texcoords 0..1, with f giving the position and
+    // normal at each. Faces are wound counter-clockwise when u goes right and
+    // v goes down, so f has to put the front of the surface that way around.
+    fn grid(&mut self, columns: u32, rows: u32, f: impl Fn(f32, f32) -> (na::Vec3, na::Vec3)) {
+        let (columns, rows) = (columns.max(1), rows.max(1));
+        let first = (self.positions.len() / 3) as u32;
+        for row in 0..=rows {
+            for column in 0..=columns {
+                let uv = na::vec2(column as f32 / columns as f32, row as f32 / rows as f32);
+                let (position, normal) = f(uv.x, uv.y);
+                self.vertex(position, normal, uv);
+            }
+        }
+
+        let index = |column: u32, row: u32| first + row * (columns + 1) + column;
+        for row in 0..rows {
+            for column in 0..columns {
+                let top_left = index(column, row);
+                let bottom_left = index(column, row + 1);
+                let top_right = index(column + 1, row);
+                let bottom_right = index(column + 1, row + 1);
+                self.triangle([top_left, bottom_left, top_right]);
+                self.triangle([top_right, bottom_left, bottom_right]);
+            }
+        }
+    }
+
+    // Sweeps the profile (top to bottom) around the Y axis, starting and
+    // ending at -Z so the texture seam is at the back
+    fn revolve(&mut self, profile: &[ProfilePoint], segments: u32) {
+        let segments = segments.max(3);
+        let first = (self.positions.len() / 3) as u32;
+        let rows = profile.len() as u32 - 1;
+        for point in profile {
+            for segment in 0..=segments {
+                let u = segment as f32 / segments as f32;
+                let angle = u * 2. * PI - PI;
+                let (sin, cos) = angle.sin_cos();
+                self.vertex(
+                    na::vec3(sin * point.radius, point.y, cos * point.radius),
+                    na::vec3(sin * point.normal.x, point.normal.y, cos * point.normal.x),
+                    na::vec2(u, point.v),
+                );
+            }
+        }
+
+        let index = |segment: u32, row: u32| first + row * (segments + 1) + segment;
+        for row in 0..rows {
+            for segment in 0..segments {
+                let top_left = index(segment, row);
+                let bottom_left = index(segment, row + 1);
+                let top_right = index(segment + 1, row);
+                let bottom_right = index(segment + 1, row + 1);
+                self.triangle([top_left, bottom_left, top_right]);
+                self.triangle([top_right, bottom_left, bottom_right]);
+            }
+        }
+    }
+
+    // Flat cap facing up or down, with the texture mapped across it as seen
+    // from outside
+    fn disk(&mut self, radius: f32, y: f32, up: bool, segments: u32) {
+        let segments = segments.max(3);
+        let flip = if up { 1. } else { -1. };
+        let normal = na::vec3(0., flip, 0.);
+        let uv = |x: f32, z: f32| na::vec2(0.5 + x / (2. * radius), 0.5 + flip * z / (2. * radius));
+
+        let center = self.vertex(na::vec3(0., y, 0.), normal, uv(0., 0.));
+        let first = center + 1;
+        for segment in 0..=segments {
+            let angle = segment as f32 / segments as f32 * 2. * PI - PI;
+            let (x, z) = (angle.sin() * radius, angle.cos() * radius);
+            self.vertex(na::vec3(x, y, z), normal, uv(x, z));
+        }
+        for segment in 0..segments {
+            let (a, b) = (first + segment, first + segment + 1);
+            self.triangle(if up { [center, a, b] } else { [center, b, a] });
+        }
+    }
+
+    // Quads collapsed at poles and apexes would leave (nearly) zero-area
+    // triangles, with sin/cos not quite reaching 0
+    fn triangle(&mut self, triangle: [u32; 3]) {
+        let [a, b, c] = triangle.map(|i| {
+            let i = i as usize * 3;
+            na::vec3(
+                self.positions[i],
+                self.positions[i + 1],
+                self.positions[i + 2],
+            )
+        });
+        let (ab, ac) = (b - a, c - a);
+        if na::length(&na::cross(&ab, &ac)) > 1e-5 * na::length(&ab) * na::length(&ac) {
+            self.indices.extend_from_slice(&triangle);
+        }
+    }
+
+    fn build(self) -> MeshData {
+        MeshData::new(self.positions, self.normals, self.texcoords, self.indices)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh_data::attribute3;
+
+    fn all() -> Vec<(&'static str, MeshData)> {
+        vec![
+            ("plane", plane(2., 3., 4, 2)),
+            ("cuboid", cuboid(na::vec3(1., 2., 3.), 2)),
+            ("uv_sphere", uv_sphere(1., 16, 8)),
+            ("icosphere", icosphere(1., 2)),
+            ("cylinder", cylinder(1., 2., 16, 2)),
+            ("cone", cone(1., 2., 16, 2)),
+            ("torus", torus(1., 0.25, 16, 8)),
+            ("capsule", capsule(0.5, 1., 16, 4)),
+        ]
+    }
+
+    #[test]
+    fn normals_are_unit_length() {
+        for (name, data) in all() {
+            for normal in data.normals.chunks_exact(3) {
+                let length = na::length(&attribute3(normal, 0));
+                assert!((length - 1.).abs() < 1e-4, "{}: {:?}", name, normal);
+            }
+        }
+    }
+
+    #[test]
+    fn faces_are_counter_clockwise() {
+        for (name, data) in all() {
+            assert!(!data.indices.is_empty(), "{}", name);
+            for triangle in data.indices.chunks_exact(3) {
+                let [a, b, c] =
+                    [0, 1, 2].map(|i| attribute3(&data.positions, triangle[i] as usize));
+                let face_normal = na::cross(&(b - a), &(c - a));
+                for &i in triangle {
+                    let normal = attribute3(&data.normals, i as usize);
+                    assert!(
+                        na::dot(&face_normal, &normal) > 0.,
+                        "{}: {:?}",
+                        name,
+                        triangle
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn tangents_follow_u() {
+        for (name, data) in all() {
+            for triangle in data.indices.chunks_exact(3) {
+                let [a, b, c] =
+                    [0, 1, 2].map(|i| attribute3(&data.positions, triangle[i] as usize));
+                let uv = |i: u32| {
+                    na::vec2(
+                        data.texcoords[i as usize * 2],
+                        data.texcoords[i as usize * 2 + 1],
+                    )
+                };
+                let (duv1, duv2) = (
+                    uv(triangle[1]) - uv(triangle[0]),
+                    uv(triangle[2]) - uv(triangle[0]),
+                );
+                let r = duv1.x * duv2.y - duv1.y * duv2.x;
+                if r.abs() < 1e-9 {
+                    continue;
+                }
+                let dp_du = ((b - a) * duv2.y - (c - a) * duv1.y) / r;
+                for &i in triangle {
+                    let i = i as usize;
+                    let tangent = attribute3(&data.tangents[i * 4..i * 4 + 3], 0);
+                    assert!(na::dot(&tangent, &dp_du) > 0., "{}: {:?}", name, triangle);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn texcoords_are_in_range() {
+        for (name, data) in all() {
+            for uv in data.texcoords.chunks_exact(2) {
+                let in_range = |x: f32| (-1e-6..=1. + 1e-6).contains(&x);
+                // Icosphere triangles crossing the seam go past 1 and wrap
+                assert!(
+                    (in_range(uv[0]) || name == "icosphere") && in_range(uv[1]),
+                    "{}: {:?}",
+                    name,
+                    uv
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn bounds_match_size() {
+        let check = |data: MeshData, min: [f32; 3], max: [f32; 3]| {
+            for axis in 0..3 {
+                assert!((data.bounds.aabb.min[axis] - min[axis]).abs() < 1e-4);
+                assert!((data.bounds.aabb.max[axis] - max[axis]).abs() < 1e-4);
+            }
+        };
+        check(plane(2., 3., 1, 1), [-1., 0., -1.5], [1., 0., 1.5]);
+        check(
+            cuboid(na::vec3(1., 2., 3.), 1),
+            [-0.5, -1., -1.5],
+            [0.5, 1., 1.5],
+        );
+        check(uv_sphere(2., 16, 8), [-2., -2., -2.], [2., 2., 2.]);
+        check(cylinder(1., 4., 16, 1), [-1., -2., -1.], [1., 2., 1.]);
+        check(cone(1., 4., 16, 1), [-1., -2., -1.], [1., 2., 1.]);
+        check(torus(2., 0.5, 32, 8), [-2.5, -0.5, -2.5], [2.5, 0.5, 2.5]);
+        check(capsule(0.5, 1., 16, 4), [-0.5, -1., -0.5], [0.5, 1., 0.5]);
+    }
+
+    #[test]
+    fn raises_counts_too_small_for_the_shape() {
+        let shapes = [
+            ("plane", plane(1., 1., 0, 0)),
+            ("cuboid", cuboid(na::vec3(1., 1., 1.), 0)),
+            ("uv_sphere", uv_sphere(1., 0, 0)),
+            ("cylinder", cylinder(1., 1., 0, 0)),
+            ("cone", cone(1., 1., 0, 0)),
+            ("torus", torus(1., 0.25, 0, 0)),
+            ("capsule", capsule(0.5, 1., 0, 0)),
+        ];
+        for (name, data) in shapes.iter() {
+            assert!(!data.indices.is_empty(), "{} has no triangles", name);
+            assert!(
+                data.positions
+                    .iter()
+                    .chain(&data.normals)
+                    .chain(&data.texcoords)
+                    .all(|x| x.is_finite()),
+                "{} isn't finite",
+                name
+            );
+        }
+        assert_eq!(plane(1., 1., 0, 0).indices.len(), 6);
+        // Two rings of three segments, one triangle each at the poles
+        assert_eq!(uv_sphere(1., 0, 0).indices.len(), 3 * 2 * 3);
+    }
+
+    #[test]
+    fn icosphere_is_welded() {
+        // 10 * 4^n + 2 vertices on a closed icosphere, plus seam and pole
+        // duplicates, but far fewer than 3 per triangle
+        let data = icosphere(1., 2);
+        assert_eq!(data.indices.len(), 20 * 16 * 3);
+        assert!(data.num_vertices() >= 162 && data.num_vertices() < 200);
+    }
+}
@@ -0,0 +1,265 @@
+use crate::uniforms::Uniforms;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU32, Ordering};
+use web_sys::WebGlRenderingContext as GL;
+use web_sys::{WebGlProgram, WebGlShader};
+
+static NEXT_PROGRAM_ID: AtomicU32 = AtomicU32::new(0);
+
+// A linked shader program along with its reflected attributes and uniforms
+pub struct Program {
+    // Unique per program, for sorting draws
+    pub id: u32,
+    pub program: WebGlProgram,
+    pub attributes: HashMap<String, Attribute>,
+    pub uniforms: Uniforms,
+}
+
+impl Program {
+    // Attributes are bound to the given locations before linking
+    pub fn new(
+        gl: &GL,
+        vertex_source: &str,
+        fragment_source: &str,
+        attribute_locations: &HashMap<String, Attribute>,
+    ) -> Result<Program, Vec<ShaderError>> {
+        let program = link_program(gl, vertex_source, fragment_source, attribute_locations)?;
+        let attributes = get_attributes(gl, &program).map_err(|message| {
+            vec![ShaderError {
+                stage: ShaderStage::Link,
+                line: None,
+                message,
+            }]
+        })?;
+        Ok(Program {
+            id: NEXT_PROGRAM_ID.fetch_add(1, Ordering::Relaxed),
+            attributes,
+            uniforms: Uniforms::new(gl, &program),
+            program,
+        })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ShaderStage {
+    Vertex,
+    Fragment,
+    Link,
+}
+
+// One entry from a compile or link log
+#[derive(Clone, PartialEq, Debug)]
+pub struct ShaderError {
+    pub stage: ShaderStage,
+    // In the source as given, if the log has one
+    pub line: Option<u32>,
+    pub message: String,
+}
+
+impl fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let stage = match self.stage {
+            ShaderStage::Vertex => "Vertex shader",
+            ShaderStage::Fragment => "Fragment shader",
+            ShaderStage::Link => "Link",
+        };
+        match self.line {
+            Some(line) => write!(f, "{} line {}: {}", stage, line, self.message),
+            None => write!(f, "{}: {}", stage, self.message),
+        }
+    }
+}
+
+// Logs are usually "ERROR: 0:12: 'x' : undeclared identifier" per line, with
+// the source string index before the line number. Anything else is kept whole.
+pub fn parse_log(stage: ShaderStage, log: &str) -> Vec<ShaderError> {
+    let mut errors: Vec<ShaderError> = log
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('\0'))
+        .map(|line| {
+            let rest = line
+                .strip_prefix("ERROR:")
+                .or_else(|| line.strip_prefix("WARNING:"))
+                .unwrap_or(line)
+                .trim();
+            let mut parts = rest.splitn(3, ':');
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some(source), Some(line_number), Some(message))
+                    if source.trim().parse::<u32>().is_ok() =>
+                {
+                    ShaderError {
+                        stage,
+                        line: line_number.trim().parse().ok(),
+                        message: message.trim().to_string(),
+                    }
+                }
+                _ => ShaderError {
+                    stage,
+                    line: None,
+                    message: rest.to_string(),
+                },
+            }
+        })
+        .collect();
+    if errors.is_empty() {
+        errors.push(ShaderError {
+            stage,
+            line: None,
+            message: "Failed without a log".to_string(),
+        });
+    }
+    errors
+}
+
+fn link_program(
+    gl: &GL,
+    vertex_source: &str,
+    fragment_source: &str,
+    attribute_locations: &HashMap<String, Attribute>,
+) -> Result<WebGlProgram, Vec<ShaderError>> {
+    let program = gl.create_program().unwrap();
+
+    // Report both stages' errors at once
+    let vertex_shader = compile_shader(gl, GL::VERTEX_SHADER, vertex_source);
+    let fragment_shader = compile_shader(gl, GL::FRAGMENT_SHADER, fragment_source);
+    let (vertex_shader, fragment_shader) = match (vertex_shader, fragment_shader) {
+        (Ok(vertex_shader), Ok(fragment_shader)) => (vertex_shader, fragment_shader),
+        (vertex_shader, fragment_shader) => {
+            return Err(vertex_shader
+                .err()
+                .into_iter()
+                .chain(fragment_shader.err())
+                .flatten()
+                .collect())
+        }
+    };
+
+    gl.attach_shader(&program, &vertex_shader);
+    gl.attach_shader(&program, &fragment_shader);
+    for (name, attribute) in attribute_locations {
+        gl.bind_attrib_location(&program, attribute.index, name);
+    }
+    gl.link_program(&program);
+
+    if gl
+        .get_program_parameter(&program, GL::LINK_STATUS)
+        .as_bool()
+        .unwrap()
+    {
+        Ok(program)
+    } else {
+        Err(parse_log(
+            ShaderStage::Link,
+            &gl.get_program_info_log(&program).unwrap(),
+        ))
+    }
+}
+
+fn compile_shader(
+    gl: &GL,
+    shader_type: u32,
+    source: &str,
+) -> Result<WebGlShader, Vec<ShaderError>> {
+    let shader = gl.create_shader(shader_type).unwrap();
+    gl.shader_source(&shader, source);
+    gl.compile_shader(&shader);
+
+    if gl
+        .get_shader_parameter(&shader, GL::COMPILE_STATUS)
+        .as_bool()
+        .unwrap()
+    {
+        Ok(shader)
+    } else {
+        let stage = if shader_type == GL::VERTEX_SHADER {
+            ShaderStage::Vertex
+        } else {
+            ShaderStage::Fragment
+        };
+        Err(parse_log(stage, &gl.get_shader_info_log(&shader).unwrap()))
+    }
+}
+
+pub struct Attribute {
+    pub index: u32,
+    // Components per location
+    pub size: i32,
+    pub type_: u32,
+    // Matrices take one location per column, starting at index
+    pub columns: u32,
+}
+
+fn get_attributes(gl: &GL, program: &WebGlProgram) -> Result<HashMap<String, Attribute>, String> {
+    let num_attributes = gl
+        .get_program_parameter(program, GL::ACTIVE_ATTRIBUTES)
+        .as_f64()
+        .unwrap() as u32;
+    let mut map = HashMap::new();
+    for i in 0..num_attributes {
+        let info = gl.get_active_attrib(program, i).unwrap();
+        let index = gl.get_attrib_location(program, &info.name()) as u32;
+        // GLSL ES 1.0 only has float attributes
+        let (size, columns) = match info.type_() {
+            GL::FLOAT => (1, 1),
+            GL::FLOAT_VEC2 => (2, 1),
+            GL::FLOAT_VEC3 => (3, 1),
+            GL::FLOAT_VEC4 => (4, 1),
+            GL::FLOAT_MAT2 => (2, 2),
+            GL::FLOAT_MAT3 => (3, 3),
+            GL::FLOAT_MAT4 => (4, 4),
+            x => {
+                return Err(format!(
+                    "Attribute {} has unsupported type {}",
+                    info.name(),
+                    x
+                ))
+            }
+        };
+        map.insert(
+            info.name(),
+            Attribute {
+                index,
+                size,
+                type_: GL::FLOAT,
+                columns,
+            },
+        );
+    }
+    Ok(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_logs() {
+        let log = "ERROR: 0:3: 'colour' : undeclared identifier\n\
+                   ERROR: 0:7: '' : syntax error\n\0";
+        assert_eq!(
+            parse_log(ShaderStage::Fragment, log),
+            vec![
+                ShaderError {
+                    stage: ShaderStage::Fragment,
+                    line: Some(3),
+                    message: "'colour' : undeclared identifier".to_string(),
+                },
+                ShaderError {
+                    stage: ShaderStage::Fragment,
+                    line: Some(7),
+                    message: "'' : syntax error".to_string(),
+                },
+            ]
+        );
+
+        let errors = parse_log(ShaderStage::Link, "Varyings don't match\n");
+        assert_eq!(errors[0].line, None);
+        assert_eq!(errors[0].to_string(), "Link: Varyings don't match");
+        assert_eq!(
+            parse_log(ShaderStage::Vertex, "")[0].message,
+            "Failed without a log"
+        );
+    }
+}
@@ -0,0 +1,630 @@
+// CPU implementation of the simple_3d vertex and fragment shaders, for
+// rendering scenes natively without a browser or GPU (golden-image tests)
+//
+// Everything here mirrors the WebGL path as closely as possible: same
+// transforms, same lighting math, LINEAR/REPEAT texture sampling, LESS depth
+// test, back-face culling with counter-clockwise front faces, and blended
+// objects drawn after opaque ones, back to front. Shader variants are runtime
+// branches here. If the shaders or the render queue change, this has to change
+// with them.
+
+use crate::animation::Rig;
+pub use crate::camera::Camera;
+use crate::fog::Fog;
+use crate::material::{AlphaMode, MaterialFactors};
+use crate::mesh_data::{self, attribute3, MeshData};
+use crate::skin::{Skeleton, Skin};
+use nalgebra_glm as na;
+use std::path::Path;
+use std::rc::Rc;
+
+// Same as MAX_LIGHTS in lights.glsl
+const MAX_LIGHTS: usize = 5;
+
+pub struct Texture {
+    width: u32,
+    height: u32,
+    data: Vec<u8>,
+}
+
+impl Texture {
+    // RGBA8, rows from top to bottom (the same layout texImage2D gets from an image)
+    pub fn new(width: u32, height: u32, data: Vec<u8>) -> Texture {
+        assert_eq!(data.len(), (width * height * 4) as usize);
+        Texture {
+            width,
+            height,
+            data,
+        }
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Texture, String> {
+        let image = image::open(path).map_err(|e| e.to_string())?.to_rgba8();
+        Ok(Texture::new(
+            image.width(),
+            image.height(),
+            image.into_raw(),
+        ))
+    }
+
+    // Bilinear filtering with repeat wrapping, like texture2D on a texture with
+    // LINEAR filters and the default wrap modes
+    fn sample(&self, uv: &na::Vec2) -> na::Vec4 {
+        let x = uv.x * self.width as f32 - 0.5;
+        let y = uv.y * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let texel = |x: i64, y: i64| {
+            let x = x.rem_euclid(self.width as i64) as usize;
+            let y = y.rem_euclid(self.height as i64) as usize;
+            let i = (y * self.width as usize + x) * 4;
+            na::vec4(
+                self.data[i] as f32,
+                self.data[i + 1] as f32,
+                self.data[i + 2] as f32,
+                self.data[i + 3] as f32,
+            ) / 255.
+        };
+
+        let top = na::lerp(&texel(x0, y0), &texel(x0 + 1, y0), fx);
+        let bottom = na::lerp(&texel(x0, y0 + 1), &texel(x0 + 1, y0 + 1), fx);
+        na::lerp(&top, &bottom, fy)
+    }
+}
+
+pub struct Model {
+    pub mesh: Rc<MeshData>,
+    // Missing maps are skipped, like the shader variants without them
+    pub color_map: Option<Texture>,
+    pub specular_map: Option<Texture>,
+    pub normal_map: Option<Texture>,
+    pub factors: MaterialFactors,
+    pub alpha_mode: AlphaMode,
+    pub double_sided: bool,
+    pub skin: Option<Rc<Skin>>,
+    pub rig: Option<Rc<Rig>>,
+}
+
+#[derive(Clone)]
+pub struct Object {
+    pub model: Rc<Model>,
+    pub scale: na::Mat4,
+    pub rotation: na::Mat4,
+    pub translation: na::Mat4,
+    // The rig's rest pose if None
+    pub pose: Option<Skeleton>,
+}
+
+impl Object {
+    fn world(&self) -> na::Mat4 {
+        let world = self.translation * self.rotation * self.scale;
+        // glTF meshes are placed by their node, unless they're skinned and
+        // the joints place them
+        match (&self.model.rig, self.skeleton()) {
+            (Some(rig), Some(skeleton)) if self.model.skin.is_none() => {
+                world * skeleton.global_transform(rig.mesh_node)
+            }
+            _ => world,
+        }
+    }
+
+    // The pose, or the rig's rest pose. None without a rig.
+    fn skeleton(&self) -> Option<&Skeleton> {
+        let rig = self.model.rig.as_ref()?;
+        Some(self.pose.as_ref().unwrap_or(&rig.rest_pose))
+    }
+
+    fn morph_weights(&self) -> Vec<f32> {
+        match (&self.model.rig, self.skeleton()) {
+            (Some(rig), Some(skeleton)) => skeleton.nodes[rig.mesh_node].weights.clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn joint_matrices(&self) -> Vec<na::Mat4> {
+        match (&self.model.skin, self.skeleton()) {
+            (Some(skin), Some(skeleton)) => skin.joint_matrices(skeleton),
+            _ => Vec::new(),
+        }
+    }
+}
+
+pub struct Light {
+    pub position: na::Vec3,
+    pub color: na::Vec3,
+    pub attentuation_coefficient: f32,
+    pub directional: bool,
+}
+
+pub struct Scene {
+    pub camera: Camera,
+    pub lights: Vec<Light>,
+    pub objects: Vec<Object>,
+    pub clear_color: na::Vec4,
+    pub fog: Option<Fog>,
+}
+
+pub struct Framebuffer {
+    width: u32,
+    height: u32,
+    color: Vec<u8>,
+    depth: Vec<f32>,
+}
+
+impl Framebuffer {
+    pub fn new(width: u32, height: u32) -> Framebuffer {
+        Framebuffer {
+            width,
+            height,
+            color: vec![0; (width * height * 4) as usize],
+            depth: vec![1.; (width * height) as usize],
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    // RGBA8, rows from top to bottom
+    pub fn pixels(&self) -> &[u8] {
+        &self.color
+    }
+
+    pub fn save_png(&self, path: impl AsRef<Path>) -> Result<(), String> {
+        image::save_buffer(
+            path,
+            &self.color,
+            self.width,
+            self.height,
+            image::ColorType::Rgba8,
+        )
+        .map_err(|e| e.to_string())
+    }
+
+    fn clear(&mut self, color: &na::Vec4) {
+        let color = to_rgba8(color);
+        for pixel in self.color.chunks_exact_mut(4) {
+            pixel.copy_from_slice(&color);
+        }
+        self.depth.iter_mut().for_each(|depth| *depth = 1.);
+    }
+}
+
+pub fn render(scene: &Scene, framebuffer: &mut Framebuffer) {
+    framebuffer.clear(&scene.clear_color);
+
+    let camera = &scene.camera;
+    let view = camera.view();
+    let projection = camera.projection(framebuffer.width as f32 / framebuffer.height as f32);
+    let lights = &scene.lights[..scene.lights.len().min(MAX_LIGHTS)];
+
+    // Opaque objects in order, then blended objects back to front
+    let (transparent, opaque): (Vec<&Object>, Vec<&Object>) = scene
+        .objects
+        .iter()
+        .partition(|object| object.model.alpha_mode == AlphaMode::Blend);
+    let mut transparent: Vec<(f32, &Object)> = transparent
+        .into_iter()
+        .map(|object| {
+            let center = object
+                .model
+                .mesh
+                .bounds
+                .transform(&object.world())
+                .sphere
+                .center;
+            (na::distance(&center, &camera.position), object)
+        })
+        .collect();
+    transparent.sort_by(|(a, _), (b, _)| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal));
+
+    for object in opaque
+        .into_iter()
+        .chain(transparent.into_iter().map(|(_, object)| object))
+    {
+        let world = object.world();
+        let uniforms = Uniforms {
+            world,
+            view,
+            projection,
+            world_inverse_transpose: na::transpose(&na::inverse(&world)),
+            camera_position: camera.position,
+            lights,
+            fog: scene.fog,
+            joints: object.joint_matrices(),
+            morph_targets: mesh_data::most_influential(&object.morph_weights()),
+            model: &object.model,
+        };
+
+        let mesh = &object.model.mesh;
+        let vertices: Vec<Vertex> = (0..mesh.num_vertices())
+            .map(|i| vertex_shader(&uniforms, mesh, i))
+            .collect();
+
+        for triangle in mesh.indices.chunks_exact(3) {
+            let polygon = clip_polygon(vec![
+                vertices[triangle[0] as usize].clone(),
+                vertices[triangle[1] as usize].clone(),
+                vertices[triangle[2] as usize].clone(),
+            ]);
+            for i in 1..polygon.len().saturating_sub(1) {
+                rasterize_triangle(
+                    framebuffer,
+                    &uniforms,
+                    [&polygon[0], &polygon[i], &polygon[i + 1]],
+                );
+            }
+        }
+    }
+}
+
+struct Uniforms<'a> {
+    world: na::Mat4,
+    view: na::Mat4,
+    projection: na::Mat4,
+    world_inverse_transpose: na::Mat4,
+    camera_position: na::Vec3,
+    lights: &'a [Light],
+    fog: Option<Fog>,
+    // Empty without SKINNING
+    joints: Vec<na::Mat4>,
+    // Index and weight, the ones Mesh::bind_morph_targets would pick
+    morph_targets: Vec<(usize, f32)>,
+    model: &'a Model,
+}
+
+#[derive(Clone)]
+struct Varyings {
+    normal: na::Vec3,
+    position: na::Vec3,
+    texcoords: na::Vec2,
+    tangent: na::Vec4,
+    color: na::Vec4,
+}
+
+impl Varyings {
+    fn weighted_sum(varyings: [&Varyings; 3], weights: [f32; 3]) -> Varyings {
+        let sum3 = |f: fn(&Varyings) -> na::Vec3| {
+            f(varyings[0]) * weights[0] + f(varyings[1]) * weights[1] + f(varyings[2]) * weights[2]
+        };
+        Varyings {
+            normal: sum3(|v| v.normal),
+            position: sum3(|v| v.position),
+            texcoords: varyings[0].texcoords * weights[0]
+                + varyings[1].texcoords * weights[1]
+                + varyings[2].texcoords * weights[2],
+            tangent: varyings[0].tangent * weights[0]
+                + varyings[1].tangent * weights[1]
+                + varyings[2].tangent * weights[2],
+            color: varyings[0].color * weights[0]
+                + varyings[1].color * weights[1]
+                + varyings[2].color * weights[2],
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Vertex {
+    clip_position: na::Vec4,
+    varyings: Varyings,
+}
+
+impl Vertex {
+    fn lerp(a: &Vertex, b: &Vertex, t: f32) -> Vertex {
+        Vertex {
+            clip_position: na::lerp(&a.clip_position, &b.clip_position, t),
+            varyings: Varyings::weighted_sum(
+                [&a.varyings, &b.varyings, &b.varyings],
+                [1. - t, t, 0.],
+            ),
+        }
+    }
+}
+
+// simple_3d.vert
+fn vertex_shader(uniforms: &Uniforms, mesh: &MeshData, i: usize) -> Vertex {
+    let a_position = attribute3(&mesh.positions, i);
+    let a_texcoords = na::vec2(mesh.texcoords[i * 2], mesh.texcoords[i * 2 + 1]);
+    let a_normal = attribute3(&mesh.normals, i);
+    let a_tangent = na::make_vec4(&mesh.tangents[i * 4..i * 4 + 4]);
+    // Like the variant without VERTEX_COLORS
+    let a_color = if mesh.colors.is_empty() {
+        na::vec4(1., 1., 1., 1.)
+    } else {
+        na::make_vec4(&mesh.colors[i * 4..i * 4 + 4])
+    };
+
+    let mut position = a_position;
+    let mut normal = a_normal;
+    let mut tangent = a_tangent.xyz();
+    for (slot, &(target, weight)) in uniforms.morph_targets.iter().enumerate() {
+        let target = &mesh.morph_targets[target];
+        if !target.positions.is_empty() {
+            position += attribute3(&target.positions, i) * weight;
+        }
+        if !target.normals.is_empty() {
+            normal += attribute3(&target.normals, i) * weight;
+        }
+        // Only the first slot has tangents
+        if slot == 0 && !target.tangents.is_empty() {
+            tangent += attribute3(&target.tangents, i) * weight;
+        }
+    }
+    if !uniforms.joints.is_empty() {
+        let a_joints = &mesh.joints[i * 4..i * 4 + 4];
+        let a_weights = &mesh.weights[i * 4..i * 4 + 4];
+        let skin: na::Mat4 = (0..4)
+            .map(|j| uniforms.joints[a_joints[j] as usize] * a_weights[j])
+            .sum();
+        position = (skin * na::vec4(position.x, position.y, position.z, 1.)).xyz();
+        normal = (skin * na::vec4(normal.x, normal.y, normal.z, 0.)).xyz();
+        tangent = (skin * na::vec4(tangent.x, tangent.y, tangent.z, 0.)).xyz();
+    }
+    let world_position = (uniforms.world * na::vec4(position.x, position.y, position.z, 1.)).xyz();
+
+    Vertex {
+        clip_position: uniforms.projection
+            * uniforms.view
+            * na::vec4(world_position.x, world_position.y, world_position.z, 1.),
+        varyings: Varyings {
+            normal: na::mat4_to_mat3(&uniforms.world_inverse_transpose) * normal,
+            position: world_position,
+            texcoords: a_texcoords,
+            tangent: {
+                let tangent = uniforms.world * na::vec4(tangent.x, tangent.y, tangent.z, 0.);
+                na::vec4(tangent.x, tangent.y, tangent.z, a_tangent.w)
+            },
+            color: a_color,
+        },
+    }
+}
+
+// simple_3d.frag
+// Returns None if the fragment is discarded
+fn fragment_shader(uniforms: &Uniforms, v: &Varyings, front_facing: bool) -> Option<na::Vec4> {
+    let model = uniforms.model;
+    let ambient_coefficient = 0.1;
+    let specular_exponent = model.factors.shininess;
+
+    let mut material_color = model.factors.base_color.component_mul(&v.color);
+    if let Some(color_map) = &model.color_map {
+        material_color = material_color.component_mul(&color_map.sample(&v.texcoords));
+    }
+    let alpha = match model.alpha_mode {
+        AlphaMode::Opaque => 1.,
+        AlphaMode::Mask(cutoff) if material_color.w < cutoff => return None,
+        AlphaMode::Mask(_) => 1.,
+        AlphaMode::Blend => material_color.w,
+    };
+    let mut smoothness = model.factors.specular_strength;
+    if let Some(specular_map) = &model.specular_map {
+        smoothness *= specular_map.sample(&v.texcoords).x;
+    }
+    // In tangent space
+    let normal = match &model.normal_map {
+        Some(normal_map) => {
+            na::normalize(&(normal_map.sample(&v.texcoords).xyz() * 2. - na::vec3(1., 1., 1.)))
+        }
+        None => na::vec3(0., 0., 1.),
+    };
+
+    let mut surface_normal = na::normalize(&v.normal);
+    // Re-orthogonalize after interpolation
+    let mut tangent = na::normalize(
+        &(v.tangent.xyz() - surface_normal * na::dot(&surface_normal, &v.tangent.xyz())),
+    );
+    let mut bitangent = na::cross(&surface_normal, &tangent) * v.tangent.w;
+
+    // Back faces are only drawn for double-sided materials, and are lit as if
+    // the surface faced the other way
+    if !front_facing {
+        surface_normal = -surface_normal;
+        tangent = -tangent;
+        bitangent = -bitangent;
+    }
+    let to_tangent_space = na::Mat3::from_rows(&[
+        tangent.transpose(),
+        bitangent.transpose(),
+        surface_normal.transpose(),
+    ]);
+
+    let surface_to_camera = to_tangent_space * (uniforms.camera_position - v.position);
+    let surface_to_camera_dir = na::normalize(&surface_to_camera);
+
+    let mut diffuse_sum = na::vec3(0., 0., 0.);
+    let mut specular_sum = na::vec3(0., 0., 0.);
+
+    for light in uniforms.lights {
+        let surface_to_light = to_tangent_space * (light.position - v.position);
+
+        let attentuation =
+            1. / (1. + (light.attentuation_coefficient * na::length(&surface_to_light)).powi(2));
+
+        // If the light is directional, it's "position" is its inverse direction
+        let surface_to_light_dir = na::normalize(if light.directional {
+            &light.position
+        } else {
+            &surface_to_light
+        });
+
+        // Diffuse
+        let diffuse_coefficient = attentuation * na::dot(&normal, &surface_to_light_dir).max(0.);
+        diffuse_sum += light.color * diffuse_coefficient;
+
+        // Specular
+        let half_vector = na::normalize(&(surface_to_light_dir + surface_to_camera_dir));
+        let dot_half_normal = na::dot(&normal, &half_vector).max(0.);
+        let specular_coefficient = attentuation * dot_half_normal.powf(specular_exponent);
+        specular_sum += light.color * specular_coefficient;
+    }
+
+    let ambient_component = material_color.xyz() * ambient_coefficient;
+    let diffuse_component = material_color
+        .xyz()
+        .component_mul(&(diffuse_sum / diffuse_sum.max().max(1.)));
+    let specular_component = (specular_sum / specular_sum.max().max(1.)) * smoothness;
+
+    let mut color =
+        ambient_component + diffuse_component + specular_component + model.factors.emissive;
+    if let Some(fog) = &uniforms.fog {
+        color = fog.apply(&color, na::distance(&uniforms.camera_position, &v.position));
+    }
+    Some(na::vec4(color.x, color.y, color.z, alpha))
+}
+
+// Sutherland-Hodgman against the near and far planes. The other planes are
+// handled by clamping the rasterized area to the framebuffer.
+fn clip_polygon(polygon: Vec<Vertex>) -> Vec<Vertex> {
+    let near = |v: &Vertex| v.clip_position.w + v.clip_position.z;
+    let far = |v: &Vertex| v.clip_position.w - v.clip_position.z;
+
+    let mut polygon = polygon;
+    for distance in &[near, far] {
+        let mut clipped = Vec::with_capacity(polygon.len() + 1);
+        for i in 0..polygon.len() {
+            let a = &polygon[i];
+            let b = &polygon[(i + 1) % polygon.len()];
+            let (da, db) = (distance(a), distance(b));
+            if da >= 0. {
+                clipped.push(a.clone());
+            }
+            if (da >= 0.) != (db >= 0.) {
+                clipped.push(Vertex::lerp(a, b, da / (da - db)));
+            }
+        }
+        polygon = clipped;
+    }
+    polygon
+}
+
+fn rasterize_triangle(framebuffer: &mut Framebuffer, uniforms: &Uniforms, vertices: [&Vertex; 3]) {
+    let (width, height) = (framebuffer.width as f32, framebuffer.height as f32);
+
+    // Perspective divide and viewport transform, to window coordinates with
+    // the origin in the bottom left like GL
+    let window = |v: &Vertex| {
+        let ndc = v.clip_position.xyz() / v.clip_position.w;
+        na::vec3(
+            (ndc.x + 1.) / 2. * width,
+            (ndc.y + 1.) / 2. * height,
+            (ndc.z + 1.) / 2.,
+        )
+    };
+    let p = [
+        window(vertices[0]),
+        window(vertices[1]),
+        window(vertices[2]),
+    ];
+
+    // Cull back faces (counter-clockwise is front facing) unless double-sided
+    let area = edge_function(&p[0], &p[1], &p[2]);
+    let front_facing = area > 0.;
+    if area == 0. || (!front_facing && !uniforms.model.double_sided) {
+        return;
+    }
+    let blend = uniforms.model.alpha_mode == AlphaMode::Blend;
+
+    let min_x = p.iter().map(|p| p.x).fold(f32::INFINITY, f32::min).max(0.) as u32;
+    let min_y = p.iter().map(|p| p.y).fold(f32::INFINITY, f32::min).max(0.) as u32;
+    let max_x = (p
+        .iter()
+        .map(|p| p.x)
+        .fold(f32::NEG_INFINITY, f32::max)
+        .ceil() as u32)
+        .min(framebuffer.width);
+    let max_y = (p
+        .iter()
+        .map(|p| p.y)
+        .fold(f32::NEG_INFINITY, f32::max)
+        .ceil() as u32)
+        .min(framebuffer.height);
+
+    let inverse_w = [
+        1. / vertices[0].clip_position.w,
+        1. / vertices[1].clip_position.w,
+        1. / vertices[2].clip_position.w,
+    ];
+
+    for y in min_y..max_y {
+        for x in min_x..max_x {
+            let center = na::vec3(x as f32 + 0.5, y as f32 + 0.5, 0.);
+            let b = [
+                edge_function(&p[1], &p[2], &center) / area,
+                edge_function(&p[2], &p[0], &center) / area,
+                edge_function(&p[0], &p[1], &center) / area,
+            ];
+            if b[0] < 0. || b[1] < 0. || b[2] < 0. {
+                continue;
+            }
+
+            // Depth is interpolated linearly in window space
+            let depth = b[0] * p[0].z + b[1] * p[1].z + b[2] * p[2].z;
+            let i = ((framebuffer.height - 1 - y) * framebuffer.width + x) as usize;
+            if depth >= framebuffer.depth[i] {
+                continue;
+            }
+
+            // Varyings are interpolated perspective-correctly
+            let perspective = [
+                b[0] * inverse_w[0],
+                b[1] * inverse_w[1],
+                b[2] * inverse_w[2],
+            ];
+            let sum = perspective[0] + perspective[1] + perspective[2];
+            let varyings = Varyings::weighted_sum(
+                [
+                    &vertices[0].varyings,
+                    &vertices[1].varyings,
+                    &vertices[2].varyings,
+                ],
+                [
+                    perspective[0] / sum,
+                    perspective[1] / sum,
+                    perspective[2] / sum,
+                ],
+            );
+
+            let color = match fragment_shader(uniforms, &varyings, front_facing) {
+                Some(color) => color,
+                None => continue,
+            };
+
+            let pixel = &mut framebuffer.color[i * 4..i * 4 + 4];
+            if blend {
+                // Same blend func as GlState, without depth writes
+                let destination = na::vec4(
+                    pixel[0] as f32,
+                    pixel[1] as f32,
+                    pixel[2] as f32,
+                    pixel[3] as f32,
+                ) / 255.;
+                let mut blended = na::lerp(&destination, &color, color.w);
+                blended.w = destination.w;
+                pixel.copy_from_slice(&to_rgba8(&blended));
+            } else {
+                framebuffer.depth[i] = depth;
+                pixel.copy_from_slice(&to_rgba8(&color));
+            }
+        }
+    }
+}
+
+fn edge_function(a: &na::Vec3, b: &na::Vec3, p: &na::Vec3) -> f32 {
+    (b.x - a.x) * (p.y - a.y) - (b.y - a.y) * (p.x - a.x)
+}
+
+fn to_rgba8(color: &na::Vec4) -> [u8; 4] {
+    let channel = |c: f32| (c.clamp(0., 1.) * 255.).round() as u8;
+    [
+        channel(color.x),
+        channel(color.y),
+        channel(color.z),
+        channel(color.w),
+    ]
+}
@@ -0,0 +1,73 @@
+// Calls a function on every animation frame between start and stop
+
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+
+pub struct RenderLoop {
+    state: Rc<State>,
+}
+
+struct State {
+    // The pending animation frame, None when stopped
+    request: Cell<Option<i32>>,
+    callback: RefCell<Option<Closure<dyn FnMut()>>>,
+}
+
+impl State {
+    fn request_frame(&self) {
+        let callback = self.callback.borrow();
+        let id = web_sys::window()
+            .unwrap()
+            .request_animation_frame(callback.as_ref().unwrap().as_ref().unchecked_ref())
+            .unwrap();
+        self.request.set(Some(id));
+    }
+}
+
+impl RenderLoop {
+    pub fn new(mut frame: impl FnMut() + 'static) -> RenderLoop {
+        let state = Rc::new(State {
+            request: Cell::new(None),
+            callback: RefCell::new(None),
+        });
+        // Weak, or the loop would keep itself alive
+        let weak = Rc::downgrade(&state);
+        let callback = Closure::wrap(Box::new(move || {
+            // Asking for the next frame first lets frame stop the loop
+            match weak.upgrade() {
+                Some(state) => state.request_frame(),
+                None => return,
+            }
+            frame();
+        }) as Box<dyn FnMut()>);
+        *state.callback.borrow_mut() = Some(callback);
+        RenderLoop { state }
+    }
+
+    pub fn start(&self) {
+        if !self.is_running() {
+            self.state.request_frame();
+        }
+    }
+
+    pub fn stop(&self) {
+        if let Some(id) = self.state.request.take() {
+            web_sys::window()
+                .unwrap()
+                .cancel_animation_frame(id)
+                .unwrap();
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.state.request.get().is_some()
+    }
+}
+
+impl Drop for RenderLoop {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
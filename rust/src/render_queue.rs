@@ -0,0 +1,196 @@
+use crate::gl_state::GlState;
+use crate::instancing::InstanceBuffer;
+use crate::joint_texture::JointTexture;
+use crate::material::{AlphaMode, Material, MAX_BATCH_MATERIALS};
+use crate::mesh::Mesh;
+use crate::object::Object;
+use crate::program::Program;
+use crate::uniforms::UniformValue;
+use nalgebra_glm as na;
+use std::collections::HashMap;
+use std::rc::Rc;
+use web_sys::WebGlRenderingContext as GL;
+
+// Ordered from most to least expensive state change
+type SortKey = (u32, *const Material, *const Mesh);
+
+struct Draw {
+    program: Rc<Program>,
+    // Whose maps and settings are bound
+    material: Rc<Material>,
+    // Whose factors the instances pick by index, starting with material
+    materials: Vec<Rc<Material>>,
+    mesh: Rc<Mesh>,
+    instance_data: Vec<f32>,
+    // Skinned and morphed objects get a draw each, with their joints and
+    // morph target weights
+    joint_matrices: Vec<na::Mat4>,
+    morph_weights: Vec<f32>,
+}
+
+impl Draw {
+    fn new(program: &Rc<Program>, object: &Object) -> Draw {
+        Draw {
+            program: Rc::clone(program),
+            material: Rc::clone(&object.model.material),
+            materials: vec![Rc::clone(&object.model.material)],
+            mesh: Rc::clone(&object.model.mesh),
+            instance_data: Vec::new(),
+            joint_matrices: object.joint_matrices(),
+            morph_weights: object.morph_weights(),
+        }
+    }
+
+    // The index of the material's factors, None if it can't join this draw
+    fn material_index(&mut self, material: &Rc<Material>) -> Option<usize> {
+        if let Some(i) = self
+            .materials
+            .iter()
+            .position(|other| Rc::ptr_eq(other, material))
+        {
+            return Some(i);
+        }
+        if !self.material.batches_with(material) || self.materials.len() == MAX_BATCH_MATERIALS {
+            return None;
+        }
+        self.materials.push(Rc::clone(material));
+        Some(self.materials.len() - 1)
+    }
+
+    fn sort_key(&self) -> SortKey {
+        (
+            self.program.id,
+            Rc::as_ptr(&self.material),
+            Rc::as_ptr(&self.mesh),
+        )
+    }
+
+    fn render(
+        &self,
+        gl: &GL,
+        state: &mut GlState,
+        instance_buffer: &InstanceBuffer,
+        joint_texture: &mut Option<JointTexture>,
+    ) {
+        state.use_program(gl, &self.program.program);
+        self.material.bind(gl, state, &self.program);
+        for (i, material) in self.materials.iter().enumerate() {
+            material.bind_factors(gl, &self.program, i);
+        }
+        let uniforms = &self.program.uniforms;
+        // The JOINT_TEXTURE variant, for skins with too many joints for uniforms
+        if uniforms.type_of("u_joint_texture").is_some() {
+            joint_texture
+                .get_or_insert_with(|| JointTexture::new(gl, state))
+                .upload(gl, state, &self.joint_matrices);
+            let count = self.joint_matrices.len() as f32;
+            uniforms.set(gl, "u_joint_count", UniformValue::Float(count));
+        } else {
+            for (i, joint_matrix) in self.joint_matrices.iter().enumerate() {
+                uniforms.set_element(gl, "u_joints", i, "", UniformValue::Mat4(*joint_matrix));
+            }
+        }
+
+        if !self.mesh.has_morph_targets() {
+            self.mesh
+                .render(gl, state, instance_buffer, &self.instance_data);
+            return;
+        }
+        // Without instance attributes, the world matrices are uniforms
+        let weights = self.mesh.bind_morph_targets(gl, state, &self.morph_weights);
+        for (i, &weight) in weights.iter().enumerate() {
+            uniforms.set_element(gl, "u_morph_weights", i, "", UniformValue::Float(weight));
+        }
+        let instance = &self.instance_data;
+        uniforms.set(
+            gl,
+            "u_world",
+            UniformValue::Mat4(na::make_mat4(&instance[..16])),
+        );
+        uniforms.set(
+            gl,
+            "u_world_inverse_transpose",
+            UniformValue::Mat3(na::make_mat3(&instance[16..25])),
+        );
+        self.mesh.draw(gl, state);
+    }
+}
+
+// Draws for a frame. Opaque (and alpha masked) draws are batched into one
+// instanced draw per program, mesh and material, and sorted to minimize state
+// changes. Built-in materials that only differ in their factors share a draw.
+// Skinned and morphed objects can't share a draw since their joints and weights
+// differ. Blended draws are drawn one object at a time afterwards, sorted back
+// to front without writing depth.
+#[derive(Default)]
+pub struct RenderQueue {
+    opaque: Vec<Draw>,
+    // By program and mesh, the draws that objects can join
+    opaque_indices: HashMap<(u32, *const Mesh), Vec<usize>>,
+    // With distance from the camera
+    transparent: Vec<(f32, Draw)>,
+    // Made for the first skin that needs it
+    joint_texture: Option<JointTexture>,
+}
+
+impl RenderQueue {
+    pub fn push(&mut self, program: &Rc<Program>, object: &Object, camera_position: &na::Vec3) {
+        if object.model.material.alpha_mode == AlphaMode::Blend {
+            let mut draw = Draw::new(program, object);
+            object.push_instance_data(&mut draw.instance_data, 0);
+            let distance = na::distance(&object.bounds().sphere.center, camera_position);
+            self.transparent.push((distance, draw));
+            return;
+        }
+        if object.deforms() {
+            let mut draw = Draw::new(program, object);
+            object.push_instance_data(&mut draw.instance_data, 0);
+            self.opaque.push(draw);
+            return;
+        }
+
+        let key = (program.id, Rc::as_ptr(&object.model.mesh));
+        let draws = self.opaque_indices.entry(key).or_default();
+        for &i in draws.iter() {
+            if let Some(index) = self.opaque[i].material_index(&object.model.material) {
+                object.push_instance_data(&mut self.opaque[i].instance_data, index);
+                return;
+            }
+        }
+        let mut draw = Draw::new(program, object);
+        object.push_instance_data(&mut draw.instance_data, 0);
+        draws.push(self.opaque.len());
+        self.opaque.push(draw);
+    }
+
+    // Draw and clear everything in the queue, returning the number of draw calls
+    pub fn flush(
+        &mut self,
+        gl: &GL,
+        state: &mut GlState,
+        instance_buffer: &InstanceBuffer,
+    ) -> usize {
+        self.opaque.sort_by_key(Draw::sort_key);
+        state.set_blend(gl, false);
+        state.set_depth_mask(gl, true);
+        for draw in &self.opaque {
+            draw.render(gl, state, instance_buffer, &mut self.joint_texture);
+        }
+
+        self.transparent
+            .sort_by(|(a, _), (b, _)| b.partial_cmp(a).unwrap_or(std::cmp::Ordering::Equal));
+        state.set_blend(gl, true);
+        state.set_depth_mask(gl, false);
+        for (_, draw) in &self.transparent {
+            draw.render(gl, state, instance_buffer, &mut self.joint_texture);
+        }
+        // Depth writes have to be on for the depth buffer to be cleared
+        state.set_depth_mask(gl, true);
+
+        let draw_calls = self.opaque.len() + self.transparent.len();
+        self.opaque.clear();
+        self.opaque_indices.clear();
+        self.transparent.clear();
+        draw_calls
+    }
+}
@@ -0,0 +1,127 @@
+use web_sys::WebGlRenderingContext as GL;
+use web_sys::{WebGlFramebuffer, WebGlRenderbuffer, WebGlTexture};
+
+// An offscreen framebuffer with an RGBA color texture and a depth buffer, to
+// draw into instead of the canvas
+//
+// Creating one changes the texture binding, so the GlState must be reset
+// before drawing with it
+pub struct RenderTarget {
+    pub framebuffer: WebGlFramebuffer,
+    pub texture: WebGlTexture,
+    depth: WebGlRenderbuffer,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl RenderTarget {
+    pub fn new(gl: &GL, width: u32, height: u32) -> Result<RenderTarget, String> {
+        let max_size = |parameter| {
+            gl.get_parameter(parameter)
+                .ok()
+                .and_then(|value| value.as_f64())
+                .unwrap_or(0.) as u32
+        };
+        let max_size = max_size(GL::MAX_TEXTURE_SIZE).min(max_size(GL::MAX_RENDERBUFFER_SIZE));
+        if width > max_size || height > max_size {
+            return Err(format!(
+                "{}x{} is larger than this GPU can draw, {} each way at most",
+                width, height, max_size
+            ));
+        }
+
+        let texture = gl.create_texture().ok_or("Couldn't create texture")?;
+        gl.bind_texture(GL::TEXTURE_2D, Some(&texture));
+        gl.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+            GL::TEXTURE_2D,
+            0,
+            GL::RGBA as i32,
+            width as i32,
+            height as i32,
+            0,
+            GL::RGBA,
+            GL::UNSIGNED_BYTE,
+            None,
+        )
+        .map_err(|_| "Couldn't allocate texture")?;
+        // Any size, so no mipmaps or repeating
+        gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_MIN_FILTER, GL::LINEAR as i32);
+        gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_MAG_FILTER, GL::LINEAR as i32);
+        gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_WRAP_S, GL::CLAMP_TO_EDGE as i32);
+        gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_WRAP_T, GL::CLAMP_TO_EDGE as i32);
+        gl.bind_texture(GL::TEXTURE_2D, None);
+
+        let depth = gl
+            .create_renderbuffer()
+            .ok_or("Couldn't create renderbuffer")?;
+        gl.bind_renderbuffer(GL::RENDERBUFFER, Some(&depth));
+        gl.renderbuffer_storage(
+            GL::RENDERBUFFER,
+            GL::DEPTH_COMPONENT16,
+            width as i32,
+            height as i32,
+        );
+        gl.bind_renderbuffer(GL::RENDERBUFFER, None);
+
+        let framebuffer = gl
+            .create_framebuffer()
+            .ok_or("Couldn't create framebuffer")?;
+        gl.bind_framebuffer(GL::FRAMEBUFFER, Some(&framebuffer));
+        gl.framebuffer_texture_2d(
+            GL::FRAMEBUFFER,
+            GL::COLOR_ATTACHMENT0,
+            GL::TEXTURE_2D,
+            Some(&texture),
+            0,
+        );
+        gl.framebuffer_renderbuffer(
+            GL::FRAMEBUFFER,
+            GL::DEPTH_ATTACHMENT,
+            GL::RENDERBUFFER,
+            Some(&depth),
+        );
+        let status = gl.check_framebuffer_status(GL::FRAMEBUFFER);
+        gl.bind_framebuffer(GL::FRAMEBUFFER, None);
+
+        let target = RenderTarget {
+            framebuffer,
+            texture,
+            depth,
+            width,
+            height,
+        };
+        if status != GL::FRAMEBUFFER_COMPLETE {
+            target.delete(gl);
+            return Err(format!("Framebuffer incomplete, status {:#x}", status));
+        }
+        Ok(target)
+    }
+
+    // Binds it and sets the viewport to all of it
+    pub fn bind(&self, gl: &GL) {
+        gl.bind_framebuffer(GL::FRAMEBUFFER, Some(&self.framebuffer));
+        gl.viewport(0, 0, self.width as i32, self.height as i32);
+    }
+
+    // Of the whole target, bottom row first. It must be bound.
+    pub fn read_pixels(&self, gl: &GL) -> Result<Vec<u8>, String> {
+        let mut pixels = vec![0; self.width as usize * self.height as usize * 4];
+        gl.read_pixels_with_opt_u8_array(
+            0,
+            0,
+            self.width as i32,
+            self.height as i32,
+            GL::RGBA,
+            GL::UNSIGNED_BYTE,
+            Some(&mut pixels),
+        )
+        .map_err(|_| "Couldn't read pixels")?;
+        Ok(pixels)
+    }
+
+    pub fn delete(&self, gl: &GL) {
+        gl.delete_framebuffer(Some(&self.framebuffer));
+        gl.delete_renderbuffer(Some(&self.depth));
+        gl.delete_texture(Some(&self.texture));
+    }
+}
@@ -1,113 +1,819 @@
+use super::animation::Animator;
+use super::bounds::Frustum;
+use super::camera::Camera;
+use super::capture;
+use super::clock::Clock;
+use super::demo::Demo;
+use super::fog::Fog;
+use super::gl_state::GlState;
+use super::instancing::InstanceBuffer;
+use super::joint_texture::JOINT_TEXTURE_UNIT;
+use super::material::*;
 use super::mesh::*;
+use super::mesh_data::Shading;
 use super::model::*;
 use super::object::*;
+use super::options::EngineOptions;
+use super::primitives;
+use super::program::{Attribute, Program, ShaderError};
+use super::render_queue::RenderQueue;
+use super::render_target::RenderTarget;
+use super::resolution::Resolution;
+use super::shader_library::{Features, ShaderLibrary};
+use super::tween::*;
+use super::uniforms::UniformValue;
 use super::utils::*;
+use super::view::{View, Viewport};
 use nalgebra_glm as na;
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::future::Future;
 use std::rc::Rc;
+use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
+use web_sys::HtmlCanvasElement;
 use web_sys::WebGlRenderingContext as GL;
-use web_sys::{HtmlCanvasElement, WebGlProgram, WebGlShader, WebGlUniformLocation};
+use web_sys::WebGlTexture;
 
 pub struct Renderer {
-    program: WebGlProgram,
-    uniform_locations: HashMap<String, WebGlUniformLocation>,
+    pub options: EngineOptions,
+    // With the default scene
+    pub demo: Option<Demo>,
+    shaders: ShaderLibrary,
+    pub fog: Option<Fog>,
+    state: GlState,
+    queue: RenderQueue,
+    instance_buffer: InstanceBuffer,
     objects: Vec<Object>,
+    // Filled in by loads that finish between frames
+    loaded_objects: Rc<RefCell<Vec<Object>>>,
+    // Indexed by the ids handed out to JS
+    custom_materials: Vec<Rc<Material>>,
+    next_object_id: u32,
+    // By object id, for objects that have played an animation
+    animators: HashMap<u32, Animator>,
+    // By object id and target, weights set from JS that override animation
+    morph_weights: HashMap<u32, HashMap<usize, f32>>,
+    pub clock: Clock,
+    // Otherwise every frame is drawn
+    pub render_on_demand: bool,
+    // Something changed since the last frame was drawn
+    changed: bool,
+    // As of the last frame drawn
+    textures_loaded: u32,
+    pub resolution: Resolution,
+    // The clock's frame and the real time in seconds
+    last_render: Option<(u64, f64)>,
+    tweens: Tweens,
+    // By tween id, called when it completes
+    tween_callbacks: HashMap<u32, js_sys::Function>,
+    lights: Vec<Light>,
+    // Indexed by the ids handed out to JS. 0 is the one the camera rotation
+    // turns.
+    cameras: Vec<Camera>,
+    // By id
+    render_targets: HashMap<u32, RenderTarget>,
+    next_render_target_id: u32,
+    // In the order they were added
+    views: Vec<(u32, View)>,
+    next_view_id: u32,
+    stats: RenderStats,
     //
     camera_direction_index: usize,
-    camera_rotation: na::Quat,
-    camera_transition: Option<CameraRotationTransition>,
+}
+
+struct Light {
+    position: na::Vec3,
+    color: na::Vec3,
+    attentuation: f32,
 }
 
 impl Renderer {
-    pub async fn new(gl: &GL) -> Result<Renderer, String> {
-        let program = link_program(
-            &gl,
-            include_str!("./shaders/simple_3d.vert"),
-            include_str!("./shaders/simple_3d.frag"),
-        )?;
-
-        let uniform_locations = get_uniform_locations(&gl, &program);
-
-        let cube_mesh = Rc::new(Mesh::new(&gl, &get_attributes(&gl, &program), "cube.gltf").await);
-        let mut objects: Vec<Object> = Vec::with_capacity(8);
-
-        objects.push(create_block(&gl, &cube_mesh, "yellow_glazed_terracotta", 0));
-        objects.push(create_block(&gl, &cube_mesh, "nether_gold_ore", 1));
-        objects.push(create_block(&gl, &cube_mesh, "redstone_block", 2));
-        objects.push(create_block(&gl, &cube_mesh, "obsidian", 3));
-        objects.push(create_block(&gl, &cube_mesh, "blackstone", 4));
-        objects.push(create_block(&gl, &cube_mesh, "white_glazed_terracotta", 5));
-        objects.push(create_block(&gl, &cube_mesh, "lime_glazed_terracotta", 6));
-        objects.push(create_block(&gl, &cube_mesh, "red_glazed_terracotta", 7));
+    // With an empty scene
+    pub fn new(gl: &GL, options: EngineOptions) -> Result<Renderer, String> {
+        let shaders = ShaderLibrary::new(gl);
+        let instance_buffer = InstanceBuffer::new(gl, &shaders.attributes)?;
 
         Ok(Renderer {
-            program,
-            uniform_locations,
-            objects,
+            options,
+            demo: None,
+            shaders,
+            fog: None,
+            state: GlState::new(gl),
+            queue: RenderQueue::default(),
+            instance_buffer,
+            objects: Vec::new(),
+            loaded_objects: Rc::new(RefCell::new(Vec::new())),
+            custom_materials: Vec::new(),
+            next_object_id: 0,
+            animators: HashMap::new(),
+            morph_weights: HashMap::new(),
+            clock: Clock::new(Box::new(performance_time)),
+            render_on_demand: true,
+            changed: true,
+            textures_loaded: 0,
+            resolution: Resolution::default(),
+            last_render: None,
+            tweens: Tweens::default(),
+            tween_callbacks: HashMap::new(),
+            lights: vec![Light {
+                position: na::vec3(0., 0., 0.),
+                color: na::vec3(1., 1., 1.),
+                attentuation: 0.001,
+            }],
+            cameras: vec![Camera::new(na::vec3(0., 0., 0.), na::vec3(0., 0., -1.))],
+            render_targets: HashMap::new(),
+            next_render_target_id: 0,
+            views: vec![(
+                0,
+                View {
+                    camera: 0,
+                    viewport: Viewport::FULL,
+                    target: None,
+                },
+            )],
+            next_view_id: 1,
+            stats: RenderStats::default(),
             //
             camera_direction_index: 0,
-            camera_rotation: na::quat_inverse(&na::quat_look_at(
-                &na::vec3(0., 0., -1.),
-                &na::vec3(0., 1., 0.),
-            )),
-            camera_transition: None,
         })
     }
 
+    // Once per animation frame, whether or not it's drawn. Returns the delta.
+    pub fn tick(&mut self) -> f32 {
+        self.clock.tick();
+        self.clock.delta()
+    }
+
+    // For changes made from outside, which the next frame has to show
+    pub fn request_render(&mut self) {
+        self.changed = true;
+    }
+
+    // Whether the frame just ticked would look any different from the last
+    pub fn needs_render(&self, gl: &GL) -> bool {
+        let canvas = canvas(gl);
+        let resized = (canvas.width(), canvas.height()) != self.buffer_size(&canvas);
+        if !self.render_on_demand
+            || self.changed
+            || resized
+            || !self.loaded_objects.borrow().is_empty()
+            || textures_loaded() != self.textures_loaded
+        {
+            return true;
+        }
+
+        // Then whatever moves with time, if any passed
+        let animating = |object: &Object| match (&object.model.rig, self.animators.get(&object.id))
+        {
+            (Some(rig), Some(animator)) => animator.is_moving(rig),
+            _ => false,
+        };
+        self.clock.delta() != 0.
+            && (!self.tweens.is_empty()
+                || self.demo.is_some()
+                || self.objects.iter().any(animating))
+    }
+
+    // For the frame the clock last ticked
     pub fn render(&mut self, gl: &GL) {
-        let time = web_sys::window().unwrap().performance().unwrap().now() as f32;
+        let delta = self.clock.delta();
+        self.changed = false;
+        self.textures_loaded = textures_loaded();
 
-        // Rotate camera smoothly
-        if let Some(camera_transition) = &mut self.camera_transition {
-            camera_transition.update(&mut self.camera_rotation);
-            if camera_transition.finished {
-                self.camera_transition = None;
+        // Only frames rendered back to back say how long frames take
+        let now = performance_time();
+        if let Some((frame, time)) = self.last_render {
+            if frame + 1 == self.clock.frame() {
+                self.resolution.update((now - time) as f32);
             }
         }
+        self.last_render = Some((self.clock.frame(), now));
+
+        // Setting the size reallocates the drawing buffer, even to the same size
+        let canvas = canvas(gl);
+        let (width, height) = self.buffer_size(&canvas);
+        if (canvas.width(), canvas.height()) != (width, height) {
+            canvas.set_width(width);
+            canvas.set_height(height);
+            self.log(&format!("Resized to {}x{}", width, height));
+        }
+
+        self.objects.append(&mut self.loaded_objects.borrow_mut());
 
-        gl.use_program(Some(&self.program));
-        self.load_uniforms(gl);
+        let mut tweens = std::mem::take(&mut self.tweens);
+        let completed = tweens.update(delta, self);
+        self.tweens = tweens;
+        for id in completed {
+            // Called once the frame is done, when the engine can be used again
+            if let Some(callback) = self.tween_callbacks.remove(&id) {
+                wasm_bindgen_futures::spawn_local(async move {
+                    if let Err(e) = callback.call0(&JsValue::NULL) {
+                        web_sys::console::error_1(&e);
+                    }
+                });
+            }
+        }
 
+        for animator in self.animators.values_mut() {
+            animator.update(delta);
+        }
         for object in self.objects.iter_mut() {
-            object.rotation = na::rotation(0., &na::vec3(1., 0., 0.))
-                * na::rotation(
-                    std::f32::consts::PI * 2. * (time % 10000. / 10000.),
-                    &na::vec3(0., 1., 0.),
-                )
-                * na::rotation(std::f32::consts::PI * 0., &na::vec3(0., 0., 1.));
+            let rig = match &object.model.rig {
+                Some(rig) => rig,
+                None => continue,
+            };
+            let mut pose = self
+                .animators
+                .get(&object.id)
+                .and_then(|animator| animator.pose(rig));
+            if let Some(morph_weights) = self.morph_weights.get(&object.id) {
+                let pose = pose.get_or_insert_with(|| rig.rest_pose.clone());
+                let weights = &mut pose.nodes[rig.mesh_node].weights;
+                for (&target, &weight) in morph_weights {
+                    if let Some(w) = weights.get_mut(target) {
+                        *w = weight;
+                    }
+                }
+            }
+            object.pose = pose;
+        }
+
+        self.state.reset();
 
-            object.render(&gl, &self.uniform_locations);
+        if let Some(demo) = &self.demo {
+            demo.update(&mut self.objects, self.clock.elapsed());
         }
+
+        self.stats = self.draw_views(gl, (canvas.width(), canvas.height()));
     }
 
-    pub fn rotate_camera_left(&mut self) {
-        self.camera_direction_index = (self.camera_direction_index + 7) % 8;
-        let new_camera_rotation = na::quat_inverse(&na::quat_look_at(
-            &match self.camera_direction_index {
-                0 => na::vec3(0., 0., -1.),
-                1 => na::vec3(0.71, 0., -0.71),
-                2 => na::vec3(1., 0., 0.),
-                3 => na::vec3(0.71, 0., 0.71),
-                4 => na::vec3(0., 0., 1.),
-                5 => na::vec3(-0.71, 0., 0.71),
-                6 => na::vec3(-1., 0., 0.),
-                _ => na::vec3(-0.71, 0., -0.71),
+    // Those into render targets first, so the canvas shows them as they are
+    // this frame
+    fn draw_views(&mut self, gl: &GL, canvas_size: (u32, u32)) -> RenderStats {
+        let mut views: Vec<View> = self.views.iter().map(|(_, view)| *view).collect();
+        views.sort_by_key(|view| view.target.is_none());
+        let render_targets = std::mem::take(&mut self.render_targets);
+
+        // Where no view covers the canvas
+        gl.bind_framebuffer(GL::FRAMEBUFFER, None);
+        let clear_color = self.options.clear_color;
+        gl.clear_color(clear_color.x, clear_color.y, clear_color.z, clear_color.w);
+        gl.clear(GL::COLOR_BUFFER_BIT);
+
+        let mut stats = RenderStats {
+            objects: self.objects.len() as u32,
+            ..RenderStats::default()
+        };
+        gl.enable(GL::SCISSOR_TEST);
+        for view in views {
+            // Views go when their target does
+            let target = view.target.and_then(|id| render_targets.get(&id));
+            let (width, height) =
+                target.map_or(canvas_size, |target| (target.width, target.height));
+            gl.bind_framebuffer(GL::FRAMEBUFFER, target.map(|target| &target.framebuffer));
+            let (x, y, width, height) = view.viewport.pixels(width, height);
+            gl.viewport(x, y, width, height);
+            gl.scissor(x, y, width, height);
+            let camera = self.cameras[view.camera as usize];
+            let view_stats = self.draw_scene(gl, &camera, width as f32 / height as f32, target);
+            stats.culled += view_stats.culled;
+            stats.draw_calls += view_stats.draw_calls;
+        }
+        gl.disable(GL::SCISSOR_TEST);
+        gl.bind_framebuffer(GL::FRAMEBUFFER, None);
+
+        self.render_targets = render_targets;
+        stats
+    }
+
+    // Draws the scene as it is from the main camera into an offscreen
+    // framebuffer width x height big, whatever the canvas's size, without
+    // advancing time. Returns RGBA rows top down. Supersampling renders it
+    // that many times bigger and scales it down, for smoother edges.
+    pub fn capture(
+        &mut self,
+        gl: &GL,
+        width: u32,
+        height: u32,
+        supersample: u32,
+    ) -> Result<Vec<u8>, String> {
+        let (render_width, render_height) = capture::render_size(width, height, supersample)?;
+        let target = RenderTarget::new(gl, render_width, render_height)?;
+        target.bind(gl);
+        self.state.reset();
+        let camera = self.cameras[0];
+        let aspect = render_width as f32 / render_height as f32;
+        self.draw_scene(gl, &camera, aspect, Some(&target));
+        let pixels = target.read_pixels(gl);
+        gl.bind_framebuffer(GL::FRAMEBUFFER, None);
+        target.delete(gl);
+        self.state.reset();
+
+        Ok(capture::downsample(
+            &pixels?,
+            render_width,
+            render_height,
+            supersample,
+        ))
+    }
+
+    // Into the framebuffer that's bound, the canvas's or target's, its
+    // viewport already set. Render targets are drawn upside down, so they're
+    // stored top row first like loaded textures and map onto models the same
+    // way. Objects showing the target aren't drawn into it.
+    fn draw_scene(
+        &mut self,
+        gl: &GL,
+        camera: &Camera,
+        aspect: f32,
+        target: Option<&RenderTarget>,
+    ) -> RenderStats {
+        gl.enable(GL::DEPTH_TEST);
+        let clear_color = self.options.clear_color;
+        gl.clear_color(clear_color.x, clear_color.y, clear_color.z, clear_color.w);
+        gl.clear(GL::COLOR_BUFFER_BIT | GL::DEPTH_BUFFER_BIT);
+
+        let mut projection = camera.projection(aspect);
+        if target.is_some() {
+            projection = na::scaling(&na::vec3(1., -1., 1.)) * projection;
+            // Flipping turns the winding around
+            gl.front_face(GL::CW);
+            self.state.unbind_textures(gl);
+        }
+        let view = camera.view();
+
+        // Skip objects outside the view frustum. Deforming objects are always
+        // drawn, their bounds are for the rest pose.
+        let frustum = Frustum::new(&(projection * view));
+        let camera_position = camera.position;
+        let scene_features = if self.fog.is_some() {
+            Features::FOG
+        } else {
+            Features::default()
+        };
+        let variants = self.shaders.programs().count();
+        let failures = self.shaders.failures().count();
+        let max_uniform_joints = self.shaders.max_uniform_joints;
+        let mut culled = 0;
+        for object in &self.objects {
+            if target.is_some_and(|target| object.model.material.samples(&target.texture)) {
+                continue;
+            }
+            if object.deforms() || frustum.intersects(&object.bounds()) {
+                let features = scene_features | object.model.features(max_uniform_joints);
+                let program = match &object.model.material.program {
+                    Some(program) => Rc::clone(program),
+                    None => match self.shaders.program(gl, features) {
+                        Ok(program) => program,
+                        // Left out, reported below the first time
+                        Err(_) => continue,
+                    },
+                };
+                self.queue.push(&program, object, &camera_position);
+            } else {
+                culled += 1;
+            }
+        }
+
+        let compiled = self.shaders.programs().count() - variants;
+        if compiled > 0 {
+            self.log(&format!("Compiled {} shader variants", compiled));
+        }
+        for error in self.shaders.failures().skip(failures) {
+            let message = format!(
+                "A shader variant failed to compile, objects using it aren't drawn:\n{}",
+                error
+            );
+            web_sys::console::error_1(&message.into());
+        }
+
+        // Every variant gets the per-frame uniforms, including any compiled just now
+        for program in self.shaders.programs() {
+            self.state.use_program(gl, &program.program);
+            self.load_uniforms(gl, program, &view, &projection, &camera_position);
+        }
+
+        let draw_calls = self.queue.flush(gl, &mut self.state, &self.instance_buffer);
+        gl.front_face(GL::CCW);
+
+        RenderStats {
+            objects: self.objects.len() as u32,
+            culled,
+            draw_calls: draw_calls as u32,
+        }
+    }
+
+    pub fn stats(&self) -> RenderStats {
+        self.stats
+    }
+
+    // Adds every model in the OBJ file to the scene once it's loaded
+    pub fn load_obj(&mut self, gl: &GL, url: String, position: na::Vec3, scale: f32) -> u32 {
+        let url = self.options.asset_url(&url);
+        let id = self.next_object_id();
+        let gl = gl.clone();
+        let attributes = Rc::clone(&self.shaders.attributes);
+        self.add_when_loaded(id, url.clone(), position, scale, async move {
+            Model::load_obj(&gl, &attributes, &url).await
+        });
+        id
+    }
+
+    pub fn load_stl(
+        &mut self,
+        gl: &GL,
+        url: String,
+        shading: Shading,
+        position: na::Vec3,
+        scale: f32,
+    ) -> u32 {
+        let url = self.options.asset_url(&url);
+        let id = self.next_object_id();
+        let gl = gl.clone();
+        let attributes = Rc::clone(&self.shaders.attributes);
+        self.add_when_loaded(id, url.clone(), position, scale, async move {
+            Model::load_stl(&gl, &attributes, &url, shading)
+                .await
+                .map(|model| vec![model])
+        });
+        id
+    }
+
+    pub fn load_ply(
+        &mut self,
+        gl: &GL,
+        url: String,
+        shading: Shading,
+        position: na::Vec3,
+        scale: f32,
+    ) -> u32 {
+        let url = self.options.asset_url(&url);
+        let id = self.next_object_id();
+        let gl = gl.clone();
+        let attributes = Rc::clone(&self.shaders.attributes);
+        self.add_when_loaded(id, url.clone(), position, scale, async move {
+            Model::load_ply(&gl, &attributes, &url, shading)
+                .await
+                .map(|model| vec![model])
+        });
+        id
+    }
+
+    pub fn load_gltf(&mut self, gl: &GL, url: String, position: na::Vec3, scale: f32) -> u32 {
+        let url = self.options.asset_url(&url);
+        let id = self.next_object_id();
+        let gl = gl.clone();
+        let attributes = Rc::clone(&self.shaders.attributes);
+        let max_uniform_joints = self.shaders.max_uniform_joints;
+        self.add_when_loaded(id, url.clone(), position, scale, async move {
+            Model::load_gltf(&gl, &attributes, max_uniform_joints, &url).await
+        });
+        id
+    }
+
+    fn add_when_loaded(
+        &self,
+        id: u32,
+        url: String,
+        position: na::Vec3,
+        scale: f32,
+        models: impl Future<Output = Result<Vec<Model>, String>> + 'static,
+    ) {
+        let loaded_objects = Rc::clone(&self.loaded_objects);
+        let debug = self.options.debug;
+        wasm_bindgen_futures::spawn_local(async move {
+            match models.await {
+                Ok(models) => {
+                    if debug {
+                        let message = format!("Loaded {}, {} models", url, models.len());
+                        web_sys::console::log_1(&message.into());
+                    }
+                    loaded_objects
+                        .borrow_mut()
+                        .extend(models.into_iter().map(|model| Object {
+                            model: Rc::new(model),
+                            id,
+                            scale: na::scaling(&na::vec3(scale, scale, scale)),
+                            rotation: na::identity(),
+                            translation: na::translation(&position),
+                            pose: None,
+                        }))
+                }
+                Err(e) => web_sys::console::error_1(&format!("{}: {}", url, e).into()),
+            }
+        });
+    }
+
+    // Returns the id to set uniforms and add objects with
+    pub fn create_shader_material(
+        &mut self,
+        gl: &GL,
+        vertex_source: &str,
+        fragment_source: &str,
+    ) -> Result<u32, Vec<ShaderError>> {
+        let program = self.shaders.custom(gl, vertex_source, fragment_source)?;
+        self.custom_materials
+            .push(Rc::new(Material::custom(program)));
+        Ok(self.custom_materials.len() as u32 - 1)
+    }
+
+    pub fn set_uniform(
+        &self,
+        material: u32,
+        name: &str,
+        value: CustomUniform,
+    ) -> Result<(), String> {
+        self.custom_material(material)?.set_uniform(name, value)
+    }
+
+    // Unit sized, drawn with a custom material or plain white. Returns the
+    // object id.
+    pub fn add_primitive(
+        &mut self,
+        gl: &GL,
+        shape: &str,
+        material: Option<u32>,
+        position: na::Vec3,
+        scale: f32,
+    ) -> Result<u32, String> {
+        let data = match shape {
+            "plane" => primitives::plane(1., 1., 1, 1),
+            "cube" => primitives::cuboid(na::vec3(1., 1., 1.), 1),
+            "sphere" => primitives::uv_sphere(0.5, 32, 16),
+            "icosphere" => primitives::icosphere(0.5, 3),
+            "cylinder" => primitives::cylinder(0.5, 1., 32, 1),
+            "cone" => primitives::cone(0.5, 1., 32, 1),
+            "torus" => primitives::torus(0.35, 0.15, 32, 16),
+            "capsule" => primitives::capsule(0.25, 0.5, 32, 8),
+            _ => return Err(format!("Unknown primitive \"{}\"", shape)),
+        };
+        let material = match material {
+            Some(id) => Rc::clone(self.custom_material(id)?),
+            None => Rc::new(Material::untextured(MaterialFactors::default())),
+        };
+        let model = Model {
+            mesh: Rc::new(Mesh::new(gl, &self.shaders.attributes, &data)?),
+            material,
+            skin: None,
+            rig: None,
+        };
+        Ok(self.add_model(model, position, scale))
+    }
+
+    // Returns the object id
+    pub fn add_model(&mut self, model: Model, position: na::Vec3, scale: f32) -> u32 {
+        let id = self.next_object_id();
+        self.objects.push(Object {
+            id,
+            model: Rc::new(model),
+            scale: na::scaling(&na::vec3(scale, scale, scale)),
+            rotation: na::identity(),
+            translation: na::translation(&position),
+            pose: None,
+        });
+        id
+    }
+
+    // For meshes made outside
+    pub fn attributes(&self) -> &HashMap<String, Attribute> {
+        &self.shaders.attributes
+    }
+
+    fn next_object_id(&mut self) -> u32 {
+        self.next_object_id += 1;
+        self.next_object_id - 1
+    }
+
+    // Clips can be played before the object has loaded, once it has they're
+    // checked against its model
+    pub fn play_animation(
+        &mut self,
+        id: u32,
+        clip: &str,
+        looping: bool,
+        fade: f32,
+    ) -> Result<(), String> {
+        if let Some(object) = self.objects.iter().find(|object| object.id == id) {
+            let rig = object.model.rig.as_ref();
+            if rig.and_then(|rig| rig.clip(clip)).is_none() {
+                return Err(format!("Object {} has no animation \"{}\"", id, clip));
+            }
+        }
+        self.animator(id)?.play(clip, looping, fade);
+        Ok(())
+    }
+
+    pub fn animator(&mut self, id: u32) -> Result<&mut Animator, String> {
+        self.check_object_id(id)?;
+        Ok(self.animators.entry(id).or_default())
+    }
+
+    // Overrides the default or animated weight until cleared
+    pub fn set_morph_weight(&mut self, id: u32, target: usize, weight: f32) -> Result<(), String> {
+        self.check_object_id(id)?;
+        if let Some(object) = self.objects.iter().find(|object| object.id == id) {
+            let targets = object.morph_weights().len();
+            if target >= targets {
+                return Err(format!("Object {} has {} morph targets", id, targets));
+            }
+        }
+        self.morph_weights
+            .entry(id)
+            .or_default()
+            .insert(target, weight);
+        Ok(())
+    }
+
+    pub fn clear_morph_weights(&mut self, id: u32) -> Result<(), String> {
+        self.check_object_id(id)?;
+        self.morph_weights.remove(&id);
+        Ok(())
+    }
+
+    // Starts now, or when tween after completes. Properties of objects that
+    // haven't loaded yet start animating once they have.
+    pub fn tween(
+        &mut self,
+        tween: Tween,
+        after: Option<u32>,
+        on_complete: Option<js_sys::Function>,
+    ) -> Result<u32, String> {
+        if let Some(id) = tween.target.object() {
+            self.check_object_id(id)?;
+        }
+        if let Target::LightPosition(i) | Target::LightColor(i) = tween.target {
+            if i >= self.lights.len() {
+                return Err(format!("No light {}", i));
+            }
+        }
+        if let Some(after) = after {
+            if !self.tweens.contains(after) {
+                return Err(format!("No tween {}", after));
+            }
+        }
+        let id = self.tweens.start(tween, after);
+        if let Some(on_complete) = on_complete {
+            self.tween_callbacks.insert(id, on_complete);
+        }
+        Ok(id)
+    }
+
+    // Leaves the property where it is. Tweens chained after it are stopped
+    // too.
+    pub fn stop_tween(&mut self, id: u32) {
+        for id in self.tweens.stop(id) {
+            self.tween_callbacks.remove(&id);
+        }
+    }
+
+    // Objects share materials, like the models of an OBJ using one MTL
+    // material, so each of the object's models gets its own copy first
+    pub fn change_material(&mut self, id: u32, f: impl Fn(&mut Material)) -> Result<(), String> {
+        self.check_object_id(id)?;
+        let mut found = false;
+        for object in self.objects.iter_mut().filter(|object| object.id == id) {
+            f(own_material(object));
+            found = true;
+        }
+        if !found {
+            return Err(format!("Object {} hasn't loaded yet", id));
+        }
+        Ok(())
+    }
+
+    fn check_object_id(&self, id: u32) -> Result<(), String> {
+        if id >= self.next_object_id {
+            return Err(format!("No object {}", id));
+        }
+        Ok(())
+    }
+
+    fn custom_material(&self, id: u32) -> Result<&Rc<Material>, String> {
+        self.custom_materials
+            .get(id as usize)
+            .ok_or_else(|| format!("No custom material {}", id))
+    }
+
+    // At the origin looking down -Z, like the main camera at the start.
+    // Returns its id.
+    pub fn create_camera(&mut self) -> u32 {
+        self.cameras
+            .push(Camera::new(na::vec3(0., 0., 0.), na::vec3(0., 0., -1.)));
+        self.cameras.len() as u32 - 1
+    }
+
+    pub fn camera(&mut self, id: u32) -> Result<&mut Camera, String> {
+        self.cameras
+            .get_mut(id as usize)
+            .ok_or_else(|| format!("No camera {}", id))
+    }
+
+    // Empty until a view draws into it. Returns its id.
+    pub fn create_render_target(
+        &mut self,
+        gl: &GL,
+        width: u32,
+        height: u32,
+    ) -> Result<u32, String> {
+        if width == 0 || height == 0 {
+            return Err(format!("Render target can't be {}x{}", width, height));
+        }
+        let target = RenderTarget::new(gl, width, height)?;
+        self.render_targets
+            .insert(self.next_render_target_id, target);
+        self.next_render_target_id += 1;
+        Ok(self.next_render_target_id - 1)
+    }
+
+    // Along with the views into it. Materials showing it show black.
+    pub fn delete_render_target(&mut self, gl: &GL, id: u32) -> Result<(), String> {
+        let target = self
+            .render_targets
+            .remove(&id)
+            .ok_or_else(|| format!("No render target {}", id))?;
+        // Materials showing it would keep the deleted texture bound
+        for material in &mut self.custom_materials {
+            if material.samples(&target.texture) {
+                Rc::make_mut(material).forget_texture(&target.texture);
+            }
+        }
+        for object in &mut self.objects {
+            if object.model.material.samples(&target.texture) {
+                own_material(object).forget_texture(&target.texture);
+            }
+        }
+        target.delete(gl);
+        self.views.retain(|(_, view)| view.target != Some(id));
+        Ok(())
+    }
+
+    pub fn render_target_texture(&self, id: u32) -> Result<WebGlTexture, String> {
+        self.render_targets
+            .get(&id)
+            .map(|target| target.texture.clone())
+            .ok_or_else(|| format!("No render target {}", id))
+    }
+
+    // Lit like untextured models, with the render target as its color map.
+    // Returns the id to add primitives with.
+    pub fn create_texture_material(&mut self, target: u32) -> Result<u32, String> {
+        let material = Material {
+            color_map: Some(self.render_target_texture(target)?),
+            ..Material::untextured(MaterialFactors::default())
+        };
+        self.custom_materials.push(Rc::new(material));
+        Ok(self.custom_materials.len() as u32 - 1)
+    }
+
+    // Of the canvas, or of a render target. Views are drawn in the order
+    // they're added, so later ones go on top. Returns its id.
+    pub fn add_view(
+        &mut self,
+        camera: u32,
+        viewport: Viewport,
+        target: Option<u32>,
+    ) -> Result<u32, String> {
+        self.camera(camera)?;
+        if let Some(target) = target {
+            self.render_target_texture(target)?;
+        }
+        let id = self.next_view_id;
+        self.next_view_id += 1;
+        self.views.push((
+            id,
+            View {
+                camera,
+                viewport,
+                target,
             },
-            &na::vec3(0., 1., 0.),
         ));
+        Ok(id)
+    }
 
-        let now = web_sys::window().unwrap().performance().unwrap().now() as f32;
-        self.camera_transition = Some(CameraRotationTransition::new(
-            self.camera_rotation,
-            new_camera_rotation,
-            now,
-            now + 1000.,
-        ));
+    // Including view 0, the main camera over the whole canvas
+    pub fn remove_view(&mut self, id: u32) -> Result<(), String> {
+        let index = self
+            .views
+            .iter()
+            .position(|(other, _)| *other == id)
+            .ok_or_else(|| format!("No view {}", id))?;
+        self.views.remove(index);
+        Ok(())
+    }
+
+    pub fn rotate_camera_left(&mut self) {
+        self.camera_direction_index = (self.camera_direction_index + 7) % 8;
+        self.turn_camera();
     }
 
     pub fn rotate_camera_right(&mut self) {
         self.camera_direction_index = (self.camera_direction_index + 1) % 8;
+        self.turn_camera();
+    }
+
+    // Towards the current direction, taking over from any turn in progress
+    fn turn_camera(&mut self) {
         let new_camera_rotation = na::quat_inverse(&na::quat_look_at(
             &match self.camera_direction_index {
                 0 => na::vec3(0., 0., -1.),
@@ -121,241 +827,200 @@ impl Renderer {
             },
             &na::vec3(0., 1., 0.),
         ));
+        let tween = Tween {
+            easing: Easing::Out(Curve::Quint),
+            ..Tween::new(
+                Target::CameraRotation,
+                TweenValue::Quat(new_camera_rotation),
+                1.,
+            )
+        };
+        self.tweens.start(tween, None);
+    }
 
-        let now = web_sys::window().unwrap().performance().unwrap().now() as f32;
-        self.camera_transition = Some(CameraRotationTransition::new(
-            self.camera_rotation,
-            new_camera_rotation,
-            now,
-            now + 1000.,
-        ));
+    fn log(&self, message: &str) {
+        if self.options.debug {
+            web_sys::console::log_1(&message.into());
+        }
     }
 
-    fn load_uniforms(&self, gl: &GL) {
+    // For the canvas's size on the page, and the screen it's on
+    fn buffer_size(&self, canvas: &HtmlCanvasElement) -> (u32, u32) {
+        let device_pixel_ratio = web_sys::window().unwrap().device_pixel_ratio();
+        self.resolution.buffer_size(
+            (canvas.client_width() as u32, canvas.client_height() as u32),
+            device_pixel_ratio as f32,
+        )
+    }
+
+    fn load_uniforms(
+        &self,
+        gl: &GL,
+        program: &Program,
+        view: &na::Mat4,
+        projection: &na::Mat4,
+        camera_position: &na::Vec3,
+    ) {
+        let uniforms = &program.uniforms;
+
         // View
-        let camera_rotation = na::quat_to_mat4(&self.camera_rotation);
-        let camera_position = na::vec3(0., 0., 0.);
-        let camera_translation = na::translation(&camera_position);
-        let view = na::inverse(&(camera_translation * camera_rotation));
-        gl.uniform_matrix4fv_with_f32_array(
-            Some(&self.uniform_locations.get("u_view").unwrap()),
-            false,
-            &view.as_slice(),
-        );
+        uniforms.set(gl, "u_view", UniformValue::Mat4(*view));
 
         // Projection
-        let canvas = gl
-            .canvas()
-            .unwrap()
-            .dyn_into::<HtmlCanvasElement>()
-            .unwrap();
-        let fov = 60. * (std::f32::consts::PI / 180.);
-        let aspect = canvas.width() as f32 / canvas.height() as f32;
-        let projection = na::perspective(aspect, fov, 1., 2000.);
-        gl.uniform_matrix4fv_with_f32_array(
-            Some(&self.uniform_locations.get("u_projection").unwrap()),
-            false,
-            &projection.as_slice(),
-        );
+        uniforms.set(gl, "u_projection", UniformValue::Mat4(*projection));
 
         // Camera World Position
-        gl.uniform3fv_with_f32_array(
-            Some(&self.uniform_locations.get("u_camera_position").unwrap()),
-            camera_position.as_slice(),
+        uniforms.set(
+            gl,
+            "u_camera_position",
+            UniformValue::Vec3(*camera_position),
         );
 
         // Lights
-        self.make_light(&gl, 0, &[0., 0., 0.], &[1., 1., 1.], 0.001);
-        gl.uniform1i(
-            Some(&self.uniform_locations.get("u_num_lights").unwrap()),
-            1,
+        for (id, light) in self.lights.iter().enumerate() {
+            self.make_light(
+                gl,
+                program,
+                id,
+                light.position,
+                light.color,
+                light.attentuation,
+            );
+        }
+        uniforms.set(
+            gl,
+            "u_num_lights",
+            UniformValue::Int(self.lights.len() as i32),
         );
 
         // Textures
-        gl.uniform1i(Some(&self.uniform_locations.get("u_color_map").unwrap()), 0);
-        gl.uniform1i(
-            Some(&self.uniform_locations.get("u_specular_map").unwrap()),
-            1,
-        );
-        gl.uniform1i(
-            Some(&self.uniform_locations.get("u_normal_map").unwrap()),
-            2,
+        uniforms.set(gl, "u_color_map", UniformValue::Int(0));
+        uniforms.set(gl, "u_specular_map", UniformValue::Int(1));
+        uniforms.set(gl, "u_normal_map", UniformValue::Int(2));
+        uniforms.set(
+            gl,
+            "u_joint_texture",
+            UniformValue::Int(JOINT_TEXTURE_UNIT as i32),
         );
+
+        // Fog
+        if let Some(fog) = &self.fog {
+            uniforms.set(gl, "u_fog_color", UniformValue::Vec3(fog.color));
+            uniforms.set(gl, "u_fog_near", UniformValue::Float(fog.near));
+            uniforms.set(gl, "u_fog_far", UniformValue::Float(fog.far));
+        }
     }
 
-    fn make_light(&self, gl: &GL, id: i32, pos: &[f32; 3], color: &[f32; 3], attentuation: f32) {
-        gl.uniform3fv_with_f32_array(
-            Some(
-                &self
-                    .uniform_locations
-                    .get(&format!("u_lights[{}].position", id))
-                    .unwrap(),
-            ),
-            pos,
-        );
-        gl.uniform3fv_with_f32_array(
-            Some(
-                &self
-                    .uniform_locations
-                    .get(&format!("u_lights[{}].color", id))
-                    .unwrap(),
-            ),
-            color,
-        );
-        gl.uniform1f(
-            Some(
-                &self
-                    .uniform_locations
-                    .get(&format!("u_lights[{}].attentuation_coefficient", id))
-                    .unwrap(),
-            ),
-            attentuation,
+    fn make_light(
+        &self,
+        gl: &GL,
+        program: &Program,
+        id: usize,
+        pos: na::Vec3,
+        color: na::Vec3,
+        attentuation: f32,
+    ) {
+        let uniforms = &program.uniforms;
+        uniforms.set_element(gl, "u_lights", id, "position", UniformValue::Vec3(pos));
+        uniforms.set_element(gl, "u_lights", id, "color", UniformValue::Vec3(color));
+        uniforms.set_element(
+            gl,
+            "u_lights",
+            id,
+            "attentuation_coefficient",
+            UniformValue::Float(attentuation),
         );
     }
 }
 
-struct CameraRotationTransition {
-    finished: bool,
-    start_quat: na::Quat,
-    end_quat: na::Quat,
-    start_time: f32,
-    end_time: f32,
-}
+impl Animatable for Renderer {
+    fn get(&self, target: Target) -> Option<TweenValue> {
+        let object = |id| self.objects.iter().find(|object| object.id == id);
+        let factors = |id| object(id).map(|object| object.model.material.factors);
+        Some(match target {
+            Target::CameraRotation => TweenValue::Quat(self.cameras[0].rotation),
+            Target::Position(id) => {
+                let m = object(id)?.translation;
+                TweenValue::Vec3(na::vec3(m[(0, 3)], m[(1, 3)], m[(2, 3)]))
+            }
+            Target::Rotation(id) => TweenValue::Quat(na::to_quat(&object(id)?.rotation)),
+            Target::Scale(id) => {
+                let m = object(id)?.scale;
+                TweenValue::Vec3(na::vec3(m[(0, 0)], m[(1, 1)], m[(2, 2)]))
+            }
+            Target::BaseColor(id) => TweenValue::Vec4(factors(id)?.base_color),
+            Target::Emissive(id) => TweenValue::Vec3(factors(id)?.emissive),
+            Target::SpecularStrength(id) => TweenValue::Float(factors(id)?.specular_strength),
+            Target::Shininess(id) => TweenValue::Float(factors(id)?.shininess),
+            Target::LightPosition(i) => TweenValue::Vec3(self.lights.get(i)?.position),
+            Target::LightColor(i) => TweenValue::Vec3(self.lights.get(i)?.color),
+            Target::FogColor => TweenValue::Vec3(self.fog.as_ref()?.color),
+        })
+    }
 
-impl CameraRotationTransition {
-    pub fn new(
-        start_quat: na::Quat,
-        end_quat: na::Quat,
-        start_time: f32,
-        end_time: f32,
-    ) -> CameraRotationTransition {
-        CameraRotationTransition {
-            finished: false,
-            start_quat,
-            end_quat,
-            start_time,
-            end_time,
-        }
-    }
-
-    pub fn update(&mut self, camera_rotation: &mut na::Quat) {
-        if !self.finished {
-            let now = web_sys::window().unwrap().performance().unwrap().now() as f32;
-            let x = (now - self.start_time) / (self.end_time - self.start_time);
-            let y = ease_out(x);
-            *camera_rotation = na::quat_slerp(&self.start_quat, &self.end_quat, y);
-            if x >= 1. {
-                self.finished = true;
+    fn set(&mut self, target: Target, value: TweenValue) {
+        match (target, value) {
+            (Target::CameraRotation, TweenValue::Quat(q)) => self.cameras[0].rotation = q,
+            (Target::LightPosition(i), TweenValue::Vec3(v)) => self.lights[i].position = v,
+            (Target::LightColor(i), TweenValue::Vec3(v)) => self.lights[i].color = v,
+            (Target::FogColor, TweenValue::Vec3(v)) => {
+                if let Some(fog) = &mut self.fog {
+                    fog.color = v;
+                }
+            }
+            _ => {
+                // Every object the id was given to, like each model of an OBJ
+                let id = match target.object() {
+                    Some(id) => id,
+                    None => return,
+                };
+                for object in self.objects.iter_mut().filter(|object| object.id == id) {
+                    match (target, value) {
+                        (Target::Position(_), TweenValue::Vec3(v)) => {
+                            object.translation = na::translation(&v)
+                        }
+                        (Target::Rotation(_), TweenValue::Quat(q)) => {
+                            object.rotation = na::quat_to_mat4(&q)
+                        }
+                        (Target::Scale(_), TweenValue::Vec3(v)) => object.scale = na::scaling(&v),
+                        (Target::BaseColor(_), TweenValue::Vec4(v)) => {
+                            own_material(object).set_base_color(v)
+                        }
+                        (Target::Emissive(_), TweenValue::Vec3(v)) => {
+                            own_material(object).factors.emissive = v
+                        }
+                        (Target::SpecularStrength(_), TweenValue::Float(x)) => {
+                            own_material(object).factors.specular_strength = x
+                        }
+                        (Target::Shininess(_), TweenValue::Float(x)) => {
+                            own_material(object).factors.shininess = x
+                        }
+                        _ => {}
+                    }
+                }
             }
         }
     }
 }
 
-fn ease_out(x: f32) -> f32 {
-    1. - (1. - x).powi(5)
+#[wasm_bindgen]
+#[derive(Clone, Copy, Default)]
+pub struct RenderStats {
+    pub objects: u32,
+    pub culled: u32,
+    #[wasm_bindgen(js_name = drawCalls)]
+    pub draw_calls: u32,
 }
 
-fn link_program(
-    gl: &GL,
-    vertex_source: &str,
-    fragment_source: &str,
-) -> Result<WebGlProgram, String> {
-    let program = gl.create_program().unwrap();
-
-    let vertex_shader = compile_shader(&gl, GL::VERTEX_SHADER, vertex_source)?;
-    let fragment_shader = compile_shader(&gl, GL::FRAGMENT_SHADER, fragment_source)?;
-
-    gl.attach_shader(&program, &vertex_shader);
-    gl.attach_shader(&program, &fragment_shader);
-    gl.link_program(&program);
-
-    if gl
-        .get_program_parameter(&program, GL::LINK_STATUS)
-        .as_bool()
-        .unwrap()
-    {
-        Ok(program)
-    } else {
-        Err(gl.get_program_info_log(&program).unwrap())
-    }
+// The object's own copy of its material, made the first time it's changed so
+// other objects sharing the material don't change too
+fn own_material(object: &mut Object) -> &mut Material {
+    Rc::make_mut(&mut Rc::make_mut(&mut object.model).material)
 }
 
-fn compile_shader(gl: &GL, shader_type: u32, source: &str) -> Result<WebGlShader, String> {
-    let shader = gl.create_shader(shader_type).unwrap();
-    gl.shader_source(&shader, &source);
-    gl.compile_shader(&shader);
-
-    if gl
-        .get_shader_parameter(&shader, GL::COMPILE_STATUS)
-        .as_bool()
+fn canvas(gl: &GL) -> HtmlCanvasElement {
+    gl.canvas()
+        .unwrap()
+        .dyn_into::<HtmlCanvasElement>()
         .unwrap()
-    {
-        Ok(shader)
-    } else {
-        Err(gl.get_shader_info_log(&shader).unwrap())
-    }
-}
-
-pub struct Attribute {
-    pub name: String,
-    pub index: u32,
-    pub size: i32,
-    pub type_: u32,
-}
-
-fn get_attributes(gl: &GL, program: &WebGlProgram) -> HashMap<String, Attribute> {
-    let num_attributes = gl
-        .get_program_parameter(&program, GL::ACTIVE_ATTRIBUTES)
-        .as_f64()
-        .unwrap() as u32;
-    let mut map = HashMap::new();
-    for index in 0..num_attributes {
-        let info = gl.get_active_attrib(&program, index).unwrap();
-        let (size, type_) = match info.type_() {
-            GL::FLOAT_VEC3 => (3, GL::FLOAT),
-            GL::FLOAT_VEC2 => (2, GL::FLOAT),
-            x => panic!("No match for attribute type: {}", x),
-        };
-        map.insert(
-            info.name(),
-            Attribute {
-                name: info.name(),
-                index,
-                size,
-                type_,
-            },
-        );
-    }
-    map
-}
-
-fn get_uniform_locations(gl: &GL, program: &WebGlProgram) -> HashMap<String, WebGlUniformLocation> {
-    let num_uniforms = gl
-        .get_program_parameter(&program, GL::ACTIVE_UNIFORMS)
-        .as_f64()
-        .unwrap() as u32;
-    let mut map = HashMap::new();
-    for i in 0..num_uniforms {
-        let name = gl.get_active_uniform(&program, i).unwrap().name();
-        let location = gl.get_uniform_location(&program, &name).unwrap();
-        map.insert(name, location);
-    }
-    map
-}
-
-fn create_block(gl: &GL, mesh: &Rc<Mesh>, texture_name: &str, pos: i32) -> Object {
-    let angle = std::f32::consts::PI * 2. / 8. * (pos as f32);
-    let x = 600. * angle.cos();
-    let z = -(600. * angle.sin());
-    Object {
-        model: Rc::new(Model {
-            mesh: Rc::clone(&mesh),
-            color_map: load_texture(&gl, &format!("textures/{}.png", texture_name)),
-            specular_map: load_texture(&gl, &format!("textures/{}_s.png", texture_name)),
-            normal_map: load_texture(&gl, &format!("textures/{}_n.png", texture_name)),
-        }),
-        scale: na::scaling(&na::vec3(100., 100., 100.)),
-        rotation: na::identity(),
-        translation: na::translation(&na::vec3(x, 0., z)),
-    }
 }
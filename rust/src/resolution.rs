@@ -0,0 +1,156 @@
+// Sizing the canvas's drawing buffer: its CSS size times the device pixel
+// ratio, times a render scale that drops while frames are too slow. Plain
+// Rust, the renderer hands in the sizes and frame times.
+
+// Seconds of frames averaged between changes to the scale, each of which
+// reallocates the drawing buffer
+const ADJUST_INTERVAL: f32 = 0.5;
+// Intervals on target before scaling back up. Frames come at the display's
+// refresh rate at best, so they don't show how much headroom there is, and
+// going up too soon would drop straight back down.
+const INTERVALS_BEFORE_UP: u32 = 4;
+
+pub struct Resolution {
+    // High DPI screens can have ratios of 3 or more, which costs a lot of
+    // fill rate for little difference
+    pub max_pixel_ratio: f32,
+    // In seconds. With None the scale stays at 1.
+    target_frame_time: Option<f32>,
+    // The lowest the scale drops to
+    pub min_scale: f32,
+    scale: f32,
+    // Over the frames since the scale last changed
+    total_frame_time: f32,
+    frames: u32,
+    intervals_on_target: u32,
+}
+
+impl Default for Resolution {
+    fn default() -> Resolution {
+        Resolution {
+            max_pixel_ratio: 2.,
+            target_frame_time: None,
+            min_scale: 0.5,
+            scale: 1.,
+            total_frame_time: 0.,
+            frames: 0,
+            intervals_on_target: 0,
+        }
+    }
+}
+
+impl Resolution {
+    // Of the drawing buffer for a canvas client_size CSS pixels big, at least
+    // a pixel each way
+    pub fn buffer_size(&self, client_size: (u32, u32), device_pixel_ratio: f32) -> (u32, u32) {
+        let ratio = device_pixel_ratio.min(self.max_pixel_ratio) * self.scale;
+        let scale = |size: u32| ((size as f32 * ratio).round() as u32).max(1);
+        (scale(client_size.0), scale(client_size.1))
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    // Turns dynamic resolution on or off, starting again from full scale
+    pub fn set_target_frame_time(&mut self, target_frame_time: Option<f32>) {
+        self.target_frame_time = target_frame_time;
+        self.scale = 1.;
+        self.total_frame_time = 0.;
+        self.frames = 0;
+        self.intervals_on_target = 0;
+    }
+
+    // With the time since the frame before, for frames rendered back to
+    // back. Steps the scale down as soon as frames average over the target,
+    // and back up once they've been on it for a while.
+    pub fn update(&mut self, frame_time: f32) {
+        let target = match self.target_frame_time {
+            Some(target) => target,
+            None => return,
+        };
+        self.total_frame_time += frame_time;
+        self.frames += 1;
+        if self.total_frame_time < ADJUST_INTERVAL {
+            return;
+        }
+
+        let average = self.total_frame_time / self.frames as f32;
+        self.total_frame_time = 0.;
+        self.frames = 0;
+        if average > target * 1.2 {
+            self.intervals_on_target = 0;
+            self.step(-0.1);
+        } else if average < target * 1.05 {
+            self.intervals_on_target += 1;
+            if self.intervals_on_target == INTERVALS_BEFORE_UP {
+                self.intervals_on_target = 0;
+                self.step(0.05);
+            }
+        } else {
+            self.intervals_on_target = 0;
+        }
+    }
+
+    // Rounded to steps, so the scale settles on a few sizes
+    fn step(&mut self, by: f32) {
+        let scale = ((self.scale + by) * 20.).round() / 20.;
+        self.scale = scale.clamp(self.min_scale.min(1.), 1.);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_for_the_pixel_ratio() {
+        let resolution = Resolution::default();
+        assert_eq!(resolution.buffer_size((800, 600), 1.), (800, 600));
+        assert_eq!(resolution.buffer_size((800, 600), 1.5), (1200, 900));
+        // Capped
+        assert_eq!(resolution.buffer_size((800, 600), 3.), (1600, 1200));
+        assert_eq!(resolution.buffer_size((0, 600), 1.), (1, 600));
+    }
+
+    #[test]
+    fn scales_down_when_slow_and_back_up() {
+        let mut resolution = Resolution::default();
+        resolution.set_target_frame_time(Some(1. / 60.));
+        // Off target, but not for long enough
+        for _ in 0..10 {
+            resolution.update(1. / 30.);
+        }
+        assert_eq!(resolution.scale(), 1.);
+        for _ in 0..10 {
+            resolution.update(1. / 30.);
+        }
+        assert!((resolution.scale() - 0.9).abs() < 1e-6);
+        assert_eq!(resolution.buffer_size((800, 600), 1.), (720, 540));
+
+        // Never below the minimum
+        for _ in 0..1000 {
+            resolution.update(1. / 10.);
+        }
+        assert_eq!(resolution.scale(), 0.5);
+
+        // Recovers slowly once frames are on target
+        for _ in 0..160 {
+            resolution.update(1. / 60.);
+        }
+        assert!((resolution.scale() - 0.55).abs() < 1e-6);
+        for _ in 0..2000 {
+            resolution.update(1. / 60.);
+        }
+        assert_eq!(resolution.scale(), 1.);
+    }
+
+    #[test]
+    fn stays_at_full_scale_without_a_target() {
+        let mut resolution = Resolution::default();
+        for _ in 0..100 {
+            resolution.update(1.);
+        }
+        assert_eq!(resolution.scale(), 1.);
+    }
+}
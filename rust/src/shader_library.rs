@@ -0,0 +1,324 @@
+// Variants of the built-in shaders, compiled on first use. Each variant is the
+// same source with a #define per feature, so a material only pays for the
+// maps and effects it uses.
+
+use crate::mesh_data::MAX_MORPH_TARGETS;
+use crate::program::{Attribute, Program, ShaderError, ShaderStage};
+use crate::skin;
+use std::collections::HashMap;
+use std::ops::BitOr;
+use std::rc::Rc;
+use web_sys::WebGlRenderingContext as GL;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default, Debug)]
+pub struct Features(u32);
+
+impl Features {
+    pub const COLOR_MAP: Features = Features(1);
+    pub const NORMAL_MAP: Features = Features(1 << 1);
+    pub const SPECULAR_MAP: Features = Features(1 << 2);
+    pub const VERTEX_COLORS: Features = Features(1 << 3);
+    pub const ALPHA_MASK: Features = Features(1 << 4);
+    pub const ALPHA_BLEND: Features = Features(1 << 5);
+    pub const FOG: Features = Features(1 << 6);
+    pub const SKINNING: Features = Features(1 << 7);
+    pub const MORPH_TARGETS: Features = Features(1 << 8);
+    // With SKINNING, for skins with too many joints for uniforms
+    pub const JOINT_TEXTURE: Features = Features(1 << 9);
+
+    pub fn contains(self, other: Features) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for Features {
+    type Output = Features;
+
+    fn bitor(self, other: Features) -> Features {
+        Features(self.0 | other.0)
+    }
+}
+
+const DEFINES: [(Features, &str); 10] = [
+    (Features::COLOR_MAP, "COLOR_MAP"),
+    (Features::NORMAL_MAP, "NORMAL_MAP"),
+    (Features::SPECULAR_MAP, "SPECULAR_MAP"),
+    (Features::VERTEX_COLORS, "VERTEX_COLORS"),
+    (Features::ALPHA_MASK, "ALPHA_MASK"),
+    (Features::ALPHA_BLEND, "ALPHA_BLEND"),
+    (Features::FOG, "FOG"),
+    (Features::SKINNING, "SKINNING"),
+    (Features::MORPH_TARGETS, "MORPH_TARGETS"),
+    (Features::JOINT_TEXTURE, "JOINT_TEXTURE"),
+];
+
+const CHUNKS: [(&str, &str); 6] = [
+    (
+        "varyings.glsl",
+        include_str!("./shaders/chunks/varyings.glsl"),
+    ),
+    ("lights.glsl", include_str!("./shaders/chunks/lights.glsl")),
+    (
+        "materials.glsl",
+        include_str!("./shaders/chunks/materials.glsl"),
+    ),
+    ("fog.glsl", include_str!("./shaders/chunks/fog.glsl")),
+    (
+        "skinning.glsl",
+        include_str!("./shaders/chunks/skinning.glsl"),
+    ),
+    (
+        "morph_targets.glsl",
+        include_str!("./shaders/chunks/morph_targets.glsl"),
+    ),
+];
+
+// Name, components per location and number of locations. Every variant binds
+// these to the same locations so that meshes and the instance buffer work with
+// all of them. a_position comes first, attribute 0 has to be per-vertex.
+const ATTRIBUTES: [(&str, i32, u32); 10] = [
+    ("a_position", 3, 1),
+    ("a_texcoords", 2, 1),
+    ("a_normal", 3, 1),
+    ("a_tangent", 4, 1),
+    ("a_color", 4, 1),
+    ("a_joints", 4, 1),
+    ("a_weights", 4, 1),
+    ("a_world", 4, 4),
+    ("a_world_inverse_transpose", 3, 3),
+    ("a_material_index", 1, 1),
+];
+
+pub struct ShaderLibrary {
+    pub attributes: Rc<HashMap<String, Attribute>>,
+    // Joint matrices the SKINNING variants have room for as uniforms
+    pub max_uniform_joints: usize,
+    programs: HashMap<Features, Rc<Program>>,
+    // Variants that failed to compile with why, in order, so they're only
+    // compiled once
+    failed: Vec<(Features, String)>,
+    custom: Vec<Rc<Program>>,
+}
+
+impl ShaderLibrary {
+    pub fn new(gl: &GL) -> ShaderLibrary {
+        let vertex_uniform_vectors = gl
+            .get_parameter(GL::MAX_VERTEX_UNIFORM_VECTORS)
+            .ok()
+            .and_then(|value| value.as_f64())
+            .unwrap_or(128.);
+
+        let mut index = 0;
+        let mut attributes: HashMap<_, _> = ATTRIBUTES
+            .iter()
+            .map(|&(name, size, columns)| {
+                let attribute = Attribute {
+                    index,
+                    size,
+                    type_: GL::FLOAT,
+                    columns,
+                };
+                index += columns;
+                (name.to_string(), attribute)
+            })
+            .collect();
+
+        // The MORPH_TARGETS variant has no instance attributes, so the
+        // targets take their locations. Only a program using both would
+        // fail to link.
+        let mut index = attributes["a_world"].index;
+        for target in 0..MAX_MORPH_TARGETS {
+            for name in ["a_morph_position", "a_morph_normal"] {
+                let attribute = Attribute {
+                    index,
+                    size: 3,
+                    type_: GL::FLOAT,
+                    columns: 1,
+                };
+                attributes.insert(format!("{}{}", name, target), attribute);
+                index += 1;
+            }
+        }
+        let attribute = Attribute {
+            index,
+            size: 3,
+            type_: GL::FLOAT,
+            columns: 1,
+        };
+        attributes.insert("a_morph_tangent0".to_string(), attribute);
+
+        ShaderLibrary {
+            attributes: Rc::new(attributes),
+            max_uniform_joints: skin::max_uniform_joints(vertex_uniform_vectors as usize),
+            programs: HashMap::new(),
+            failed: Vec::new(),
+            custom: Vec::new(),
+        }
+    }
+
+    pub fn program(&mut self, gl: &GL, features: Features) -> Result<Rc<Program>, String> {
+        if let Some(program) = self.programs.get(&features) {
+            return Ok(Rc::clone(program));
+        }
+        if let Some((_, error)) = self.failed.iter().find(|(failed, _)| *failed == features) {
+            return Err(error.clone());
+        }
+
+        match self.compile(gl, features) {
+            Ok(program) => {
+                let program = Rc::new(program);
+                self.programs.insert(features, Rc::clone(&program));
+                Ok(program)
+            }
+            Err(error) => {
+                self.failed.push((features, error.clone()));
+                Err(error)
+            }
+        }
+    }
+
+    fn compile(&self, gl: &GL, features: Features) -> Result<Program, String> {
+        self.link(
+            gl,
+            &preprocess(
+                include_str!("./shaders/simple_3d.vert"),
+                features,
+                self.max_uniform_joints,
+            )?,
+            &preprocess(
+                include_str!("./shaders/simple_3d.frag"),
+                features,
+                self.max_uniform_joints,
+            )?,
+        )
+        .map_err(|errors| {
+            let errors: Vec<String> = errors.iter().map(ShaderError::to_string).collect();
+            errors.join("\n")
+        })
+    }
+
+    // Why each variant that failed to compile did, in the order they failed
+    pub fn failures(&self) -> impl Iterator<Item = &str> {
+        self.failed.iter().map(|(_, error)| error.as_str())
+    }
+
+    // User-supplied source, compiled as is so that error lines match it. It
+    // gets the same attributes and per-frame uniforms as the built-in shaders.
+    pub fn custom(
+        &mut self,
+        gl: &GL,
+        vertex_source: &str,
+        fragment_source: &str,
+    ) -> Result<Rc<Program>, Vec<ShaderError>> {
+        let program = Rc::new(self.link(gl, vertex_source, fragment_source)?);
+        self.custom.push(Rc::clone(&program));
+        Ok(program)
+    }
+
+    // Every program compiled so far
+    pub fn programs(&self) -> impl Iterator<Item = &Rc<Program>> {
+        self.programs.values().chain(self.custom.iter())
+    }
+
+    fn link(
+        &self,
+        gl: &GL,
+        vertex_source: &str,
+        fragment_source: &str,
+    ) -> Result<Program, Vec<ShaderError>> {
+        let program = Program::new(gl, vertex_source, fragment_source, &self.attributes)?;
+        // Anything outside the layout would be left without data
+        if let Some(name) = program
+            .attributes
+            .keys()
+            .find(|name| !self.attributes.contains_key(*name))
+        {
+            return Err(vec![ShaderError {
+                stage: ShaderStage::Link,
+                line: None,
+                message: format!("Attribute {} isn't in the shared layout", name),
+            }]);
+        }
+        Ok(program)
+    }
+}
+
+// Prepends a #define for each feature and expands #include "chunk" lines
+fn preprocess(source: &str, features: Features, max_joints: usize) -> Result<String, String> {
+    let mut output = String::new();
+    for &(feature, name) in &DEFINES {
+        if features.contains(feature) {
+            output += &format!("#define {}\n", name);
+        }
+    }
+    if features.contains(Features::SKINNING) {
+        output += &format!("#define MAX_JOINTS {}\n", max_joints);
+    }
+    expand_includes(source, &mut output, 0)?;
+    Ok(output)
+}
+
+fn expand_includes(source: &str, output: &mut String, depth: u32) -> Result<(), String> {
+    if depth > 8 {
+        return Err("Shader includes nested too deeply".to_string());
+    }
+    for line in source.lines() {
+        match line.trim().strip_prefix("#include") {
+            Some(name) => {
+                let name = name.trim().trim_matches('"');
+                let chunk = CHUNKS
+                    .iter()
+                    .find(|(chunk_name, _)| *chunk_name == name)
+                    .ok_or(format!("Unknown shader chunk \"{}\"", name))?;
+                expand_includes(chunk.1, output, depth + 1)?;
+            }
+            None => {
+                *output += line;
+                output.push('\n');
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::MAX_BATCH_MATERIALS;
+
+    #[test]
+    fn defines_features() {
+        let source =
+            preprocess("void main() {}", Features::NORMAL_MAP | Features::FOG, 24).unwrap();
+        assert_eq!(source, "#define NORMAL_MAP\n#define FOG\nvoid main() {}\n");
+    }
+
+    #[test]
+    fn batches_as_many_materials_as_the_renderer() {
+        let source = preprocess(
+            include_str!("./shaders/simple_3d.vert"),
+            Features::default(),
+            24,
+        )
+        .unwrap();
+        let define = format!("#define MAX_BATCH_MATERIALS {}\n", MAX_BATCH_MATERIALS);
+        assert!(source.contains(&define));
+    }
+
+    #[test]
+    fn expands_includes() {
+        let source = preprocess(
+            "#include \"lights.glsl\"\nvoid main() {}",
+            Features::default(),
+            24,
+        )
+        .unwrap();
+        assert!(source.starts_with("#define MAX_LIGHTS 5\n"));
+        assert!(source.ends_with("uniform lowp int u_num_lights;\nvoid main() {}\n"));
+
+        let error = preprocess("#include \"missing.glsl\"", Features::default(), 24)
+            .err()
+            .unwrap();
+        assert_eq!(error, "Unknown shader chunk \"missing.glsl\"");
+    }
+}
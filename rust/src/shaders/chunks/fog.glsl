@@ -0,0 +1,11 @@
+#ifdef FOG
+uniform vec3 u_fog_color;
+uniform float u_fog_near;
+uniform float u_fog_far;
+
+// Linear in the distance from the camera
+vec3 apply_fog(vec3 color, float distance) {
+  float visibility = clamp((u_fog_far - distance) / (u_fog_far - u_fog_near), 0.0, 1.0);
+  return mix(u_fog_color, color, visibility);
+}
+#endif
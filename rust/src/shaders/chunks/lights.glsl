@@ -0,0 +1,11 @@
+#define MAX_LIGHTS 5
+
+struct Light {
+  vec3 position;
+  vec3 color;
+  float attentuation_coefficient;
+  bool directional; // Maybe store this in the W?
+};
+
+uniform Light u_lights[MAX_LIGHTS];
+uniform lowp int u_num_lights;
@@ -0,0 +1,16 @@
+// Factors of the materials drawn together, each instance picks one
+#if defined(SKINNING) || defined(MORPH_TARGETS)
+// Drawn one object at a time, and the joints take most of the uniforms
+#define MAX_BATCH_MATERIALS 1
+#else
+#define MAX_BATCH_MATERIALS 16
+#endif
+
+struct MaterialFactors {
+  vec4 base_color;
+  vec3 emissive;
+  float specular_strength;
+  float shininess;
+};
+
+uniform MaterialFactors u_materials[MAX_BATCH_MATERIALS];
@@ -0,0 +1,32 @@
+#ifdef MORPH_TARGETS
+#define MAX_MORPH_TARGETS 3
+
+// The most influential targets, picked every frame
+attribute vec3 a_morph_position0;
+attribute vec3 a_morph_normal0;
+attribute vec3 a_morph_position1;
+attribute vec3 a_morph_normal1;
+attribute vec3 a_morph_position2;
+attribute vec3 a_morph_normal2;
+// There aren't attributes left for every target's tangents, so only the most
+// influential one moves them
+attribute vec3 a_morph_tangent0;
+
+uniform float u_morph_weights[MAX_MORPH_TARGETS];
+
+vec3 morph_position(vec3 position) {
+  return position + u_morph_weights[0] * a_morph_position0 +
+         u_morph_weights[1] * a_morph_position1 +
+         u_morph_weights[2] * a_morph_position2;
+}
+
+vec3 morph_normal(vec3 normal) {
+  return normal + u_morph_weights[0] * a_morph_normal0 +
+         u_morph_weights[1] * a_morph_normal1 +
+         u_morph_weights[2] * a_morph_normal2;
+}
+
+vec3 morph_tangent(vec3 tangent) {
+  return tangent + u_morph_weights[0] * a_morph_tangent0;
+}
+#endif
@@ -0,0 +1,35 @@
+#ifdef SKINNING
+// MAX_JOINTS is defined with the joints that fit in this GPU's uniforms
+
+attribute vec4 a_joints;
+attribute vec4 a_weights;
+
+// Mesh space to posed mesh space, per joint
+#ifdef JOINT_TEXTURE
+// A row per joint with the matrix's columns across it, for skins with more
+// than MAX_JOINTS
+uniform sampler2D u_joint_texture;
+uniform float u_joint_count;
+
+mat4 joint_matrix(float joint) {
+  float v = (joint + 0.5) / u_joint_count;
+  return mat4(texture2D(u_joint_texture, vec2(0.125, v)),
+              texture2D(u_joint_texture, vec2(0.375, v)),
+              texture2D(u_joint_texture, vec2(0.625, v)),
+              texture2D(u_joint_texture, vec2(0.875, v)));
+}
+#else
+uniform mat4 u_joints[MAX_JOINTS];
+
+mat4 joint_matrix(float joint) {
+  return u_joints[int(joint)];
+}
+#endif
+
+mat4 skin_matrix() {
+  return a_weights.x * joint_matrix(a_joints.x) +
+         a_weights.y * joint_matrix(a_joints.y) +
+         a_weights.z * joint_matrix(a_joints.z) +
+         a_weights.w * joint_matrix(a_joints.w);
+}
+#endif
@@ -0,0 +1,12 @@
+varying vec3 v_normal;  // Surface normal in world space
+varying vec3 v_position;  // In world space
+varying vec2 v_texcoords;
+varying vec4 v_tangent;  // In world space, bitangent sign in w
+// The instance's material factors
+varying vec4 v_base_color;
+varying vec3 v_emissive;
+varying float v_specular_strength;
+varying float v_shininess;
+#ifdef VERTEX_COLORS
+varying vec4 v_color;
+#endif
@@ -1,42 +1,71 @@
-#define MAX_LIGHTS 5
-
 precision mediump float;
 
-struct Light {
-  vec3 position;
-  vec3 color;
-  float attentuation_coefficient;
-  bool directional; // Maybe store this in the W?
-};
-
-varying vec3 v_normal;  // Surface normal in world space
-varying vec3 v_position;  // In world space
-varying vec2 v_texcoords;
-varying vec3 v_tangent;
-varying vec3 v_bitangent;
+#include "varyings.glsl"
+#include "lights.glsl"
+#include "fog.glsl"
 
-uniform mat4 u_world;
 uniform vec3 u_camera_position;
-uniform Light u_lights[MAX_LIGHTS];
-uniform lowp int u_num_lights;
 
+#ifdef COLOR_MAP
 uniform sampler2D u_color_map;
+#endif
+#ifdef SPECULAR_MAP
 uniform sampler2D u_specular_map;
+#endif
+#ifdef NORMAL_MAP
 uniform sampler2D u_normal_map;
+#endif
 
-// TODO: Organize all of this
+#ifdef ALPHA_MASK
+uniform float u_alpha_cutoff;
+#endif
 
 void main() {
   float ambient_coefficient = 0.1;
-  float specular_exponent = 70.0; // Can I get this from the specular map?
-
-  vec4 material_color = texture2D(u_color_map, v_texcoords);
-  float smoothness = texture2D(u_specular_map, v_texcoords).r;
+  float specular_exponent = v_shininess;
+
+  vec4 material_color = v_base_color;
+#ifdef COLOR_MAP
+  material_color *= texture2D(u_color_map, v_texcoords);
+#endif
+#ifdef VERTEX_COLORS
+  material_color *= v_color;
+#endif
+
+#if defined(ALPHA_MASK)
+  if (material_color.a < u_alpha_cutoff) discard;
+  float alpha = 1.0;
+#elif defined(ALPHA_BLEND)
+  float alpha = material_color.a;
+#else
+  float alpha = 1.0;
+#endif
+
+  float smoothness = v_specular_strength;
+#ifdef SPECULAR_MAP
+  smoothness *= texture2D(u_specular_map, v_texcoords).r;
+#endif
+
+  // In tangent space
+#ifdef NORMAL_MAP
   vec3 normal = normalize(texture2D(u_normal_map, v_texcoords).rgb * 2.0 - 1.0);
+#else
+  vec3 normal = vec3(0, 0, 1);
+#endif
 
   vec3 surface_normal = normalize(v_normal);
-  vec3 tangent = normalize((u_world * vec4(v_tangent, 0.0)).xyz);
-  vec3 bitangent = normalize((u_world * vec4(v_bitangent, 0.0)).xyz);
+  // Re-orthogonalize after interpolation
+  vec3 tangent = normalize(v_tangent.xyz - surface_normal * dot(surface_normal, v_tangent.xyz));
+  vec3 bitangent = cross(surface_normal, tangent) * v_tangent.w;
+
+  // Back faces are only drawn for double-sided materials, and are lit as if
+  // the surface faced the other way
+  if (!gl_FrontFacing) {
+    surface_normal = -surface_normal;
+    tangent = -tangent;
+    bitangent = -bitangent;
+  }
+
   mat3 to_tangent_space = mat3(
     tangent.x, bitangent.x, surface_normal.x,
     tangent.y, bitangent.y, surface_normal.y,
@@ -80,5 +109,10 @@ void main() {
   vec3 specular_component = smoothness *
     (specular_sum / max(specular_sum.r, max(specular_sum.g, max(specular_sum.b, 1.0))));
 
-  gl_FragColor = vec4((ambient_component + diffuse_component + specular_component), 1);
+  vec3 color = ambient_component + diffuse_component + specular_component + v_emissive;
+#ifdef FOG
+  color = apply_fog(color, length(u_camera_position - v_position));
+#endif
+
+  gl_FragColor = vec4(color, alpha);
 }
\ No newline at end of file
@@ -3,28 +3,64 @@ precision mediump float;
 attribute vec3 a_position;
 attribute vec2 a_texcoords;
 attribute vec3 a_normal;
-attribute vec3 a_tangent;
-attribute vec3 a_bitangent;
+attribute vec4 a_tangent; // Bitangent sign in w
+#ifdef VERTEX_COLORS
+attribute vec4 a_color;
+#endif
 
+#ifdef MORPH_TARGETS
+// Drawn one object at a time, the targets use the instance attributes' locations
 uniform mat4 u_world;
+uniform mat3 u_world_inverse_transpose;
+#define a_world u_world
+#define a_world_inverse_transpose u_world_inverse_transpose
+#define a_material_index 0.0
+#else
+// Per instance
+attribute mat4 a_world;
+attribute mat3 a_world_inverse_transpose;
+attribute float a_material_index;
+#endif
+
 uniform mat4 u_view;
 uniform mat4 u_projection;
-uniform mat4 u_world_inverse_transpose;
 
-varying vec3 v_normal;
-varying vec3 v_position;
-varying vec2 v_texcoords;
-varying vec3 v_tangent;
-varying vec3 v_bitangent;
+#include "varyings.glsl"
+#include "materials.glsl"
+#include "skinning.glsl"
+#include "morph_targets.glsl"
 
 void main() {
-  vec3 world_position = (u_world * vec4(a_position, 1)).xyz;
+  vec3 position = a_position;
+  vec3 normal = a_normal;
+  vec3 tangent = a_tangent.xyz;
+#ifdef MORPH_TARGETS
+  position = morph_position(position);
+  normal = morph_normal(normal);
+  tangent = morph_tangent(tangent);
+#endif
+#ifdef SKINNING
+  // Assumes the joints don't scale unevenly, like a_world's normal matrix
+  mat4 skin = skin_matrix();
+  position = (skin * vec4(position, 1)).xyz;
+  normal = (skin * vec4(normal, 0)).xyz;
+  tangent = (skin * vec4(tangent, 0)).xyz;
+#endif
+  vec3 world_position = (a_world * vec4(position, 1)).xyz;
 
   v_position = world_position;
-  v_normal = mat3(u_world_inverse_transpose) * a_normal;
+  v_normal = a_world_inverse_transpose * normal;
   v_texcoords = a_texcoords;
-  v_tangent = a_tangent;
-  v_bitangent = a_bitangent;
+  v_tangent = vec4((a_world * vec4(tangent, 0)).xyz, a_tangent.w);
+#ifdef VERTEX_COLORS
+  v_color = a_color;
+#endif
+  // Fragment shaders can only index uniform arrays with constants
+  MaterialFactors material = u_materials[int(a_material_index)];
+  v_base_color = material.base_color;
+  v_emissive = material.emissive;
+  v_specular_strength = material.specular_strength;
+  v_shininess = material.shininess;
 
   gl_Position = u_projection * u_view * vec4(world_position, 1);
 }
\ No newline at end of file
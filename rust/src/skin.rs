@@ -0,0 +1,272 @@
+// Skins and the node hierarchy they're posed by. Plain Rust with no WebGL,
+// so the rasterizer uses it too.
+
+use nalgebra_glm as na;
+
+// The joint matrices are uniforms when they fit in the GPU's vertex uniform
+// vectors, after UNIFORM_VECTORS_RESERVED for the rest of the vertex shader
+// (which uses 23). WebGL only promises 128, room for 24 joints. Skins with more
+// read them from a float texture instead, a row per joint, up to
+// MAX_TEXTURE_JOINTS.
+pub const UNIFORM_VECTORS_RESERVED: usize = 32;
+pub const MAX_TEXTURE_JOINTS: usize = 1024;
+
+// Each joint matrix takes 4 vectors
+pub fn max_uniform_joints(vertex_uniform_vectors: usize) -> usize {
+    vertex_uniform_vectors.saturating_sub(UNIFORM_VECTORS_RESERVED) / 4
+}
+
+#[derive(Clone, Debug)]
+pub struct Node {
+    pub parent: Option<usize>,
+    pub translation: na::Vec3,
+    pub rotation: na::Quat,
+    pub scale: na::Vec3,
+    // Of the morph targets of the node's mesh, empty if it has none
+    pub weights: Vec<f32>,
+}
+
+impl Node {
+    pub fn local_transform(&self) -> na::Mat4 {
+        na::translation(&self.translation)
+            * na::quat_to_mat4(&self.rotation)
+            * na::scaling(&self.scale)
+    }
+}
+
+// Every node of a glTF scene graph, indexed like the document's nodes. Posing
+// a skin means changing these, the joint matrices follow.
+#[derive(Clone, Debug)]
+pub struct Skeleton {
+    pub nodes: Vec<Node>,
+}
+
+impl Skeleton {
+    pub fn from_gltf(document: &gltf::Document) -> Skeleton {
+        let mut nodes: Vec<Node> = document
+            .nodes()
+            .map(|node| {
+                let (translation, rotation, scale) = node.transform().decomposed();
+                // The node's weights override its mesh's defaults
+                let weights = node
+                    .weights()
+                    .or_else(|| node.mesh().and_then(|mesh| mesh.weights()))
+                    .unwrap_or_default();
+                Node {
+                    parent: None,
+                    translation: na::make_vec3(&translation),
+                    rotation: na::quat(rotation[0], rotation[1], rotation[2], rotation[3]),
+                    scale: na::make_vec3(&scale),
+                    weights: weights.to_vec(),
+                }
+            })
+            .collect();
+        for node in document.nodes() {
+            for child in node.children() {
+                nodes[child.index()].parent = Some(node.index());
+            }
+        }
+        Skeleton { nodes }
+    }
+
+    // Node to scene space
+    pub fn global_transform(&self, i: usize) -> na::Mat4 {
+        let node = &self.nodes[i];
+        match node.parent {
+            Some(parent) => self.global_transform(parent) * node.local_transform(),
+            None => node.local_transform(),
+        }
+    }
+
+    // Node to scene space, for every node
+    pub fn global_transforms(&self) -> Vec<na::Mat4> {
+        let mut transforms = vec![None; self.nodes.len()];
+        for i in 0..self.nodes.len() {
+            self.memoized_transform(i, &mut transforms);
+        }
+        transforms.into_iter().map(Option::unwrap).collect()
+    }
+
+    // Parents first, each node computed once
+    fn memoized_transform(&self, i: usize, transforms: &mut Vec<Option<na::Mat4>>) -> na::Mat4 {
+        if let Some(transform) = transforms[i] {
+            return transform;
+        }
+        let node = &self.nodes[i];
+        let transform = match node.parent {
+            Some(parent) => self.memoized_transform(parent, transforms) * node.local_transform(),
+            None => node.local_transform(),
+        };
+        transforms[i] = Some(transform);
+        transform
+    }
+}
+
+pub struct Skin {
+    // Node index per joint
+    pub joints: Vec<usize>,
+    // Mesh space to each joint's space in the bind pose
+    pub inverse_bind_matrices: Vec<na::Mat4>,
+}
+
+impl Skin {
+    pub fn from_gltf(
+        node: &gltf::Node,
+        buffers: &[gltf::buffer::Data],
+    ) -> Result<Option<Skin>, String> {
+        let skin = match node.skin() {
+            Some(skin) => skin,
+            None => return Ok(None),
+        };
+        let joints: Vec<usize> = skin.joints().map(|joint| joint.index()).collect();
+        if joints.len() > MAX_TEXTURE_JOINTS {
+            return Err(format!(
+                "Skin has {} joints, the most supported is {}",
+                joints.len(),
+                MAX_TEXTURE_JOINTS
+            ));
+        }
+
+        // Identity matrices when they're left out
+        let inverse_bind_matrices = match skin
+            .reader(|buffer| Some(&buffers[buffer.index()]))
+            .read_inverse_bind_matrices()
+        {
+            Some(matrices) => matrices.map(|m| na::make_mat4(&m.concat())).collect(),
+            None => vec![na::identity(); joints.len()],
+        };
+
+        Ok(Some(Skin {
+            joints,
+            inverse_bind_matrices,
+        }))
+    }
+
+    pub fn needs_joint_texture(&self, max_uniform_joints: usize) -> bool {
+        self.joints.len() > max_uniform_joints
+    }
+
+    // Mesh space to posed scene space, per joint. glTF ignores the transform
+    // of the skinned mesh's own node, the joints place it.
+    pub fn joint_matrices(&self, pose: &Skeleton) -> Vec<na::Mat4> {
+        let globals = pose.global_transforms();
+        self.joints
+            .iter()
+            .zip(&self.inverse_bind_matrices)
+            .map(|(&joint, inverse_bind_matrix)| globals[joint] * inverse_bind_matrix)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(parent: Option<usize>, translation: na::Vec3) -> Node {
+        Node {
+            parent,
+            translation,
+            rotation: na::quat_identity(),
+            scale: na::vec3(1., 1., 1.),
+            weights: Vec::new(),
+        }
+    }
+
+    // A root with a joint one unit up, bound where they stand
+    fn two_joints() -> (Skin, Skeleton) {
+        let skeleton = Skeleton {
+            nodes: vec![
+                node(None, na::vec3(0., 0., 0.)),
+                node(Some(0), na::vec3(0., 1., 0.)),
+            ],
+        };
+        let skin = Skin {
+            joints: vec![0, 1],
+            inverse_bind_matrices: vec![na::identity(), na::translation(&na::vec3(0., -1., 0.))],
+        };
+        (skin, skeleton)
+    }
+
+    fn transform(m: &na::Mat4, p: na::Vec3) -> na::Vec3 {
+        (m * na::vec4(p.x, p.y, p.z, 1.)).xyz()
+    }
+
+    #[test]
+    fn rest_pose_is_identity() {
+        let (skin, skeleton) = two_joints();
+        for m in skin.joint_matrices(&skeleton) {
+            assert!((m - na::Mat4::identity()).amax() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn children_follow_parents() {
+        let (skin, mut skeleton) = two_joints();
+        skeleton.nodes[0].rotation =
+            na::quat_angle_axis(std::f32::consts::FRAC_PI_2, &na::vec3(0., 0., 1.));
+        let matrices = skin.joint_matrices(&skeleton);
+        // A vertex at the child joint swings from +Y to -X with it
+        let p = transform(&matrices[1], na::vec3(0., 1., 0.));
+        assert!(na::distance(&p, &na::vec3(-1., 0., 0.)) < 1e-5);
+    }
+
+    #[test]
+    fn reads_gltf_hierarchy() {
+        let document = gltf::Gltf::from_slice(
+            br#"{
+                "asset": { "version": "2.0" },
+                "nodes": [
+                    { "children": [1], "translation": [0, 2, 0] },
+                    { "rotation": [0, 0, 0.70710678, 0.70710678], "scale": [2, 2, 2] }
+                ]
+            }"#,
+        )
+        .unwrap()
+        .document;
+
+        let skeleton = Skeleton::from_gltf(&document);
+        assert_eq!(skeleton.nodes[0].parent, None);
+        assert_eq!(skeleton.nodes[1].parent, Some(0));
+        let globals = skeleton.global_transforms();
+        let p = transform(&globals[1], na::vec3(1., 0., 0.));
+        assert!(na::distance(&p, &na::vec3(0., 4., 0.)) < 1e-5);
+        assert_eq!(globals[1], skeleton.global_transform(1));
+    }
+
+    // Node 0 has the skin, nodes 1 and up are its joints
+    fn skin_with_joints(count: usize) -> Result<Option<Skin>, String> {
+        let joints: Vec<String> = (1..=count).map(|i| i.to_string()).collect();
+        let source = format!(
+            r#"{{
+                "asset": {{ "version": "2.0" }},
+                "nodes": [{{ "skin": 0 }}{}],
+                "skins": [{{ "joints": [{}] }}]
+            }}"#,
+            ", {}".repeat(count),
+            joints.join(", ")
+        );
+        let document = gltf::Gltf::from_slice(source.as_bytes()).unwrap().document;
+        Skin::from_gltf(&document.nodes().next().unwrap(), &[])
+    }
+
+    #[test]
+    fn fits_joints_in_the_uniforms_left() {
+        assert_eq!(max_uniform_joints(128), 24);
+        assert_eq!(max_uniform_joints(256), 56);
+        assert_eq!(max_uniform_joints(4096), 1016);
+        assert_eq!(max_uniform_joints(16), 0);
+    }
+
+    #[test]
+    fn big_skins_need_the_joint_texture() {
+        let skin = skin_with_joints(24).unwrap().unwrap();
+        assert!(!skin.needs_joint_texture(max_uniform_joints(128)));
+        // Like a Mixamo rig
+        let skin = skin_with_joints(65).unwrap().unwrap();
+        assert!(skin.needs_joint_texture(max_uniform_joints(128)));
+        assert!(skin.needs_joint_texture(max_uniform_joints(256)));
+        assert!(!skin.needs_joint_texture(max_uniform_joints(1024)));
+        assert_eq!(skin.inverse_bind_matrices.len(), 65);
+        assert!(skin_with_joints(MAX_TEXTURE_JOINTS + 1).is_err());
+    }
+}
@@ -0,0 +1,179 @@
+// STL parsing, ASCII or binary. Facet normals are ignored since exporters
+// often leave them zeroed, normals are generated from the winding instead.
+
+use crate::mesh_data::{MeshData, Shading};
+use std::convert::TryInto;
+
+pub fn parse_stl(bytes: &[u8], shading: Shading) -> Result<MeshData, String> {
+    let positions = if is_binary(bytes) {
+        parse_binary(bytes)
+    } else {
+        parse_ascii(&String::from_utf8_lossy(bytes))?
+    };
+    if positions.is_empty() {
+        return Err("STL has no facets".to_string());
+    }
+
+    let indices = (0..positions.len() as u32 / 3).collect();
+    Ok(MeshData::from_triangles(
+        positions,
+        Vec::new(),
+        indices,
+        shading,
+    ))
+}
+
+// Binary files can start with "solid" too, so go by whether the size matches
+// the triangle count in the header
+fn is_binary(bytes: &[u8]) -> bool {
+    if bytes.len() < 84 {
+        return false;
+    }
+    let count = u32::from_le_bytes(bytes[80..84].try_into().unwrap()) as usize;
+    bytes.len() == 84 + count * 50
+}
+
+// 80 byte header, triangle count, then per triangle a normal, three vertices
+// and a 2 byte attribute
+fn parse_binary(bytes: &[u8]) -> Vec<f32> {
+    bytes[84..]
+        .chunks_exact(50)
+        .flat_map(|triangle| {
+            triangle[12..48]
+                .chunks_exact(4)
+                .map(|x| f32::from_le_bytes(x.try_into().unwrap()))
+        })
+        .collect()
+}
+
+fn parse_ascii(source: &str) -> Result<Vec<f32>, String> {
+    let mut positions = Vec::new();
+    let mut vertices_in_facet = 0;
+
+    for (i, line) in source.lines().enumerate() {
+        let error = |message: &str| format!("STL line {}: {}", i + 1, message);
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            Some("facet") => vertices_in_facet = 0,
+            Some("vertex") => {
+                for token in tokens.by_ref().take(3) {
+                    positions.push(
+                        token
+                            .parse::<f32>()
+                            .map_err(|_| error(&format!("Bad number \"{}\"", token)))?,
+                    );
+                }
+                if positions.len() % 3 != 0 {
+                    return Err(error("Expected 3 numbers"));
+                }
+                vertices_in_facet += 1;
+            }
+            Some("endfacet") if vertices_in_facet != 3 => {
+                return Err(error("Facet without exactly 3 vertices"));
+            }
+            _ => {}
+        }
+    }
+
+    Ok(positions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh_data::attribute3;
+    use nalgebra_glm as na;
+
+    // Two triangles of a square in the XY plane facing +Z
+    const SQUARE: [[f32; 3]; 6] = [
+        [0., 0., 0.],
+        [1., 0., 0.],
+        [1., 1., 0.],
+        [0., 0., 0.],
+        [1., 1., 0.],
+        [0., 1., 0.],
+    ];
+
+    fn ascii() -> String {
+        let mut source = "solid square\n".to_string();
+        for triangle in SQUARE.chunks_exact(3) {
+            source += "  facet normal 0 0 0\n    outer loop\n";
+            for v in triangle {
+                source += &format!("      vertex {} {} {}\n", v[0], v[1], v[2]);
+            }
+            source += "    endloop\n  endfacet\n";
+        }
+        source + "endsolid square\n"
+    }
+
+    fn binary() -> Vec<u8> {
+        binary_of(&SQUARE)
+    }
+
+    fn binary_of(vertices: &[[f32; 3]]) -> Vec<u8> {
+        // Header starting with "solid" like some exporters write
+        let mut bytes = b"solid binary".to_vec();
+        bytes.resize(80, 0);
+        bytes.extend_from_slice(&(vertices.len() as u32 / 3).to_le_bytes());
+        for triangle in vertices.chunks_exact(3) {
+            bytes.extend_from_slice(&[0; 12]);
+            for x in triangle.iter().flatten() {
+                bytes.extend_from_slice(&x.to_le_bytes());
+            }
+            bytes.extend_from_slice(&[0; 2]);
+        }
+        bytes
+    }
+
+    #[test]
+    fn parses_ascii_and_binary() {
+        for bytes in [ascii().into_bytes(), binary()].iter() {
+            let data = parse_stl(bytes, Shading::Flat).unwrap();
+            assert_eq!(
+                data.positions,
+                SQUARE.iter().flatten().copied().collect::<Vec<_>>()
+            );
+            assert_eq!(data.indices, vec![0, 1, 2, 3, 4, 5]);
+            for i in 0..6 {
+                assert_eq!(attribute3(&data.normals, i), na::vec3(0., 0., 1.));
+            }
+        }
+    }
+
+    #[test]
+    fn flat_shading_needs_32_bit_indices_past_65536_vertices() {
+        // A strip of 22,000 triangles, 66,000 vertices once flat shaded
+        let vertices: Vec<[f32; 3]> = (0..22_000)
+            .flat_map(|i| {
+                let x = i as f32;
+                [[x, 0., 0.], [x + 1., 0., 0.], [x, 1., 0.]]
+            })
+            .collect();
+        let data = parse_stl(&binary_of(&vertices), Shading::Flat).unwrap();
+        assert_eq!(data.num_vertices(), 66_000);
+        assert_eq!(data.indices.iter().max(), Some(&65_999));
+        assert!(data.needs_32_bit_indices());
+        assert!(!parse_stl(&binary(), Shading::Flat)
+            .unwrap()
+            .needs_32_bit_indices());
+    }
+
+    #[test]
+    fn smooth_shading_welds() {
+        let data = parse_stl(&binary(), Shading::Smooth).unwrap();
+        assert_eq!(data.num_vertices(), 4);
+        assert_eq!(data.indices.len(), 6);
+    }
+
+    #[test]
+    fn reports_bad_ascii() {
+        let source = ascii().replace("vertex 1 1 0", "vertex 1 one 0");
+        let error = parse_stl(source.as_bytes(), Shading::Flat).err().unwrap();
+        assert!(error.starts_with("STL line 6"), "{}", error);
+
+        let error = parse_stl(b"solid empty\nendsolid empty\n", Shading::Flat)
+            .err()
+            .unwrap();
+        assert_eq!(error, "STL has no facets");
+    }
+}
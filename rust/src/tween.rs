@@ -0,0 +1,575 @@
+// Tweens: properties animated from their current value to a target over
+// time, along an easing curve. Plain Rust with no WebGL, whatever owns the
+// properties reads and writes them through Animatable.
+
+use crate::animation::slerp;
+use nalgebra_glm as na;
+use std::f32::consts::PI;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Curve {
+    Quad,
+    Cubic,
+    Quart,
+    Quint,
+    Sine,
+    Expo,
+    Circ,
+    // Overshoot, past 0 or 1 for part of the way
+    Back,
+    Elastic,
+    Bounce,
+}
+
+const CURVES: [(&str, Curve); 10] = [
+    ("quad", Curve::Quad),
+    ("cubic", Curve::Cubic),
+    ("quart", Curve::Quart),
+    ("quint", Curve::Quint),
+    ("sine", Curve::Sine),
+    ("expo", Curve::Expo),
+    ("circ", Curve::Circ),
+    ("back", Curve::Back),
+    ("elastic", Curve::Elastic),
+    ("bounce", Curve::Bounce),
+];
+
+impl Curve {
+    // Starting slow, from 0 at 0 to 1 at 1
+    fn ease_in(self, t: f32) -> f32 {
+        match self {
+            Curve::Quad => t.powi(2),
+            Curve::Cubic => t.powi(3),
+            Curve::Quart => t.powi(4),
+            Curve::Quint => t.powi(5),
+            Curve::Sine => 1. - (t * PI / 2.).cos(),
+            Curve::Expo if t <= 0. => 0.,
+            Curve::Expo => 2f32.powf(10. * t - 10.),
+            Curve::Circ => 1. - (1. - t * t).max(0.).sqrt(),
+            Curve::Back => {
+                let overshoot = 1.70158;
+                (overshoot + 1.) * t.powi(3) - overshoot * t.powi(2)
+            }
+            Curve::Elastic if t <= 0. || t >= 1. => t,
+            Curve::Elastic => {
+                -(2f32.powf(10. * t - 10.)) * ((t * 10. - 10.75) * PI * 2. / 3.).sin()
+            }
+            Curve::Bounce => 1. - bounce_out(1. - t),
+        }
+    }
+}
+
+fn bounce_out(t: f32) -> f32 {
+    let (n, d) = (7.5625, 2.75);
+    if t < 1. / d {
+        n * t * t
+    } else if t < 2. / d {
+        let t = t - 1.5 / d;
+        n * t * t + 0.75
+    } else if t < 2.5 / d {
+        let t = t - 2.25 / d;
+        n * t * t + 0.9375
+    } else {
+        let t = t - 2.625 / d;
+        n * t * t + 0.984375
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum Easing {
+    #[default]
+    Linear,
+    In(Curve),
+    Out(Curve),
+    InOut(Curve),
+}
+
+impl Easing {
+    // "linear", or a curve with "In", "Out" or "InOut" like "quintOut"
+    pub fn parse(name: &str) -> Result<Easing, String> {
+        if name == "linear" {
+            return Ok(Easing::Linear);
+        }
+        let unknown = || format!("Unknown easing \"{}\"", name);
+        let (curve, easing): (&str, fn(Curve) -> Easing) =
+            if let Some(curve) = name.strip_suffix("InOut") {
+                (curve, Easing::InOut)
+            } else if let Some(curve) = name.strip_suffix("Out") {
+                (curve, Easing::Out)
+            } else if let Some(curve) = name.strip_suffix("In") {
+                (curve, Easing::In)
+            } else {
+                return Err(unknown());
+            };
+        CURVES
+            .iter()
+            .find(|(n, _)| *n == curve)
+            .map(|(_, curve)| easing(*curve))
+            .ok_or_else(unknown)
+    }
+
+    // Progress along the curve at time t, both from 0 to 1
+    pub fn apply(self, t: f32) -> f32 {
+        match self {
+            Easing::Linear => t,
+            Easing::In(curve) => curve.ease_in(t),
+            Easing::Out(curve) => 1. - curve.ease_in(1. - t),
+            Easing::InOut(curve) if t < 0.5 => curve.ease_in(2. * t) / 2.,
+            Easing::InOut(curve) => 1. - curve.ease_in(2. - 2. * t) / 2.,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TweenValue {
+    Float(f32),
+    Vec3(na::Vec3),
+    // Also colors with alpha
+    Vec4(na::Vec4),
+    Quat(na::Quat),
+}
+
+impl TweenValue {
+    // t can overshoot 0 and 1, quaternions take the short way round
+    pub fn lerp(&self, to: &TweenValue, t: f32) -> TweenValue {
+        match (self, to) {
+            (TweenValue::Float(a), TweenValue::Float(b)) => TweenValue::Float(a + (b - a) * t),
+            (TweenValue::Vec3(a), TweenValue::Vec3(b)) => TweenValue::Vec3(na::lerp(a, b, t)),
+            (TweenValue::Vec4(a), TweenValue::Vec4(b)) => TweenValue::Vec4(na::lerp(a, b, t)),
+            (TweenValue::Quat(a), TweenValue::Quat(b)) => TweenValue::Quat(slerp(a, b, t)),
+            // Targets only ever get values of their own kind
+            _ => *to,
+        }
+    }
+}
+
+// Object ids for the transforms and material factors, light indices for the
+// lights
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Target {
+    CameraRotation,
+    Position(u32),
+    Rotation(u32),
+    Scale(u32),
+    BaseColor(u32),
+    Emissive(u32),
+    SpecularStrength(u32),
+    Shininess(u32),
+    LightPosition(usize),
+    LightColor(usize),
+    FogColor,
+}
+
+impl Target {
+    pub fn parse(property: &str, id: u32) -> Result<Target, String> {
+        Ok(match property {
+            "cameraRotation" => Target::CameraRotation,
+            "position" => Target::Position(id),
+            "rotation" => Target::Rotation(id),
+            "scale" => Target::Scale(id),
+            "baseColor" => Target::BaseColor(id),
+            "emissive" => Target::Emissive(id),
+            "specularStrength" => Target::SpecularStrength(id),
+            "shininess" => Target::Shininess(id),
+            "lightPosition" => Target::LightPosition(id as usize),
+            "lightColor" => Target::LightColor(id as usize),
+            "fogColor" => Target::FogColor,
+            _ => return Err(format!("Unknown property \"{}\"", property)),
+        })
+    }
+
+    // The id of the object the property belongs to, if it's an object's
+    pub fn object(&self) -> Option<u32> {
+        match *self {
+            Target::Position(id)
+            | Target::Rotation(id)
+            | Target::Scale(id)
+            | Target::BaseColor(id)
+            | Target::Emissive(id)
+            | Target::SpecularStrength(id)
+            | Target::Shininess(id) => Some(id),
+            _ => None,
+        }
+    }
+
+    // From flat values, XYZW for rotations and RGBA for the base color
+    pub fn value(&self, values: &[f32]) -> Result<TweenValue, String> {
+        let size = match self {
+            Target::SpecularStrength(_) | Target::Shininess(_) => 1,
+            Target::CameraRotation | Target::Rotation(_) | Target::BaseColor(_) => 4,
+            _ => 3,
+        };
+        if values.len() != size {
+            return Err(format!("Expected {} values", size));
+        }
+        Ok(match self {
+            Target::SpecularStrength(_) | Target::Shininess(_) => TweenValue::Float(values[0]),
+            Target::CameraRotation | Target::Rotation(_) => {
+                TweenValue::Quat(na::quat(values[0], values[1], values[2], values[3]))
+            }
+            Target::BaseColor(_) => TweenValue::Vec4(na::make_vec4(values)),
+            _ => TweenValue::Vec3(na::make_vec3(values)),
+        })
+    }
+}
+
+// What tweens animate. get is None for properties that don't exist (yet),
+// like those of objects still loading.
+pub trait Animatable {
+    fn get(&self, target: Target) -> Option<TweenValue>;
+    fn set(&mut self, target: Target, value: TweenValue);
+}
+
+#[derive(Clone, Debug)]
+pub struct Tween {
+    pub target: Target,
+    pub to: TweenValue,
+    // In seconds, per play
+    pub duration: f32,
+    // Before the first play, in seconds
+    pub delay: f32,
+    pub easing: Easing,
+    // Plays after the first, None repeating forever
+    pub repeat: Option<u32>,
+    // Every other play runs backwards
+    pub yoyo: bool,
+}
+
+impl Tween {
+    pub fn new(target: Target, to: TweenValue, duration: f32) -> Tween {
+        Tween {
+            target,
+            to,
+            duration,
+            delay: 0.,
+            easing: Easing::default(),
+            repeat: Some(0),
+            yoyo: false,
+        }
+    }
+}
+
+struct Running {
+    id: u32,
+    tween: Tween,
+    // Waiting for this tween to complete
+    after: Option<u32>,
+    // Including the delay
+    elapsed: f32,
+    // The property's value when the delay ran out
+    from: Option<TweenValue>,
+}
+
+impl Running {
+    // The value now, and whether it's the last
+    fn sample(&self) -> (TweenValue, bool) {
+        let from = self.from.unwrap();
+        let tween = &self.tween;
+        if tween.duration <= 0. {
+            return (tween.to, true);
+        }
+        let plays = (self.elapsed - tween.delay) / tween.duration;
+        let (play, t, complete) = match tween.repeat {
+            Some(repeat) if plays >= repeat as f32 + 1. => (repeat as f32, 1., true),
+            _ => (plays.floor(), plays.fract(), false),
+        };
+        let t = if tween.yoyo && play % 2. == 1. {
+            1. - t
+        } else {
+            t
+        };
+        (from.lerp(&tween.to, tween.easing.apply(t)), complete)
+    }
+}
+
+#[derive(Default)]
+pub struct Tweens {
+    next_id: u32,
+    tweens: Vec<Running>,
+}
+
+impl Tweens {
+    // Starts now, or once tween after completes. Returns the tween's id.
+    pub fn start(&mut self, tween: Tween, after: Option<u32>) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.tweens.push(Running {
+            id,
+            tween,
+            after,
+            elapsed: 0.,
+            from: None,
+        });
+        id
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tweens.is_empty()
+    }
+
+    pub fn contains(&self, id: u32) -> bool {
+        self.tweens.iter().any(|running| running.id == id)
+    }
+
+    // Along with every tween chained after it, which would never start.
+    // Returns the ids of those removed.
+    pub fn stop(&mut self, id: u32) -> Vec<u32> {
+        let mut stopped = Vec::new();
+        let mut stopping = vec![id];
+        while let Some(id) = stopping.pop() {
+            if let Some(i) = self.tweens.iter().position(|running| running.id == id) {
+                self.tweens.remove(i);
+                stopped.push(id);
+            }
+            stopping.extend(
+                self.tweens
+                    .iter()
+                    .filter(|running| running.after == Some(id))
+                    .map(|running| running.id),
+            );
+        }
+        stopped
+    }
+
+    // Advances every tween by delta seconds and sets the properties. Returns
+    // the ids of those that completed.
+    pub fn update(&mut self, delta: f32, properties: &mut impl Animatable) -> Vec<u32> {
+        let mut started = Vec::new();
+        for running in self.tweens.iter_mut().filter(|r| r.after.is_none()) {
+            running.elapsed += delta;
+            if running.from.is_some() || running.elapsed < running.tween.delay {
+                continue;
+            }
+            match properties.get(running.tween.target) {
+                Some(value) => {
+                    running.from = Some(value);
+                    started.push((running.tween.target, running.id));
+                }
+                // Held at the end of the delay until the property exists
+                None => running.elapsed = running.tween.delay,
+            }
+        }
+        // Tweens that start take their property over from those already going,
+        // the latest wins if several start at once
+        self.tweens.retain(|running| {
+            let target = running.tween.target;
+            let just_started = started.contains(&(target, running.id));
+            running.from.is_none()
+                || !started.iter().any(|&(other_target, other)| {
+                    other_target == target
+                        && other != running.id
+                        && (other > running.id || !just_started)
+                })
+        });
+
+        let mut completed = Vec::new();
+        for running in self.tweens.iter().filter(|r| r.from.is_some()) {
+            let (value, complete) = running.sample();
+            properties.set(running.tween.target, value);
+            if complete {
+                completed.push(running.id);
+            }
+        }
+        self.tweens
+            .retain(|running| !completed.contains(&running.id));
+        for running in self.tweens.iter_mut() {
+            if completed.iter().any(|&id| running.after == Some(id)) {
+                running.after = None;
+            }
+        }
+        completed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[derive(Default)]
+    struct Properties(HashMap<u32, f32>);
+
+    impl Animatable for Properties {
+        fn get(&self, target: Target) -> Option<TweenValue> {
+            match target {
+                Target::Shininess(id) => self.0.get(&id).map(|&x| TweenValue::Float(x)),
+                _ => None,
+            }
+        }
+
+        fn set(&mut self, target: Target, value: TweenValue) {
+            if let (Target::Shininess(id), TweenValue::Float(x)) = (target, value) {
+                self.0.insert(id, x);
+            }
+        }
+    }
+
+    fn tween(id: u32, to: f32, duration: f32) -> Tween {
+        Tween::new(Target::Shininess(id), TweenValue::Float(to), duration)
+    }
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-5, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn easings_start_at_0_and_end_at_1() {
+        let mut easings = vec![Easing::Linear];
+        for &(_, curve) in CURVES.iter() {
+            easings.extend(&[Easing::In(curve), Easing::Out(curve), Easing::InOut(curve)]);
+        }
+        for easing in easings {
+            assert_close(easing.apply(0.), 0.);
+            assert_close(easing.apply(1.), 1.);
+        }
+        // Symmetric, halfway through is halfway
+        assert_close(Easing::InOut(Curve::Cubic).apply(0.5), 0.5);
+        // What the camera used to turn with
+        assert_close(Easing::Out(Curve::Quint).apply(0.3), 1. - 0.7f32.powi(5));
+        assert!(Easing::In(Curve::Back).apply(0.2) < 0.);
+    }
+
+    #[test]
+    fn parses_easings() {
+        assert_eq!(Easing::parse("linear"), Ok(Easing::Linear));
+        assert_eq!(Easing::parse("quintOut"), Ok(Easing::Out(Curve::Quint)));
+        assert_eq!(
+            Easing::parse("bounceInOut"),
+            Ok(Easing::InOut(Curve::Bounce))
+        );
+        assert_eq!(Easing::parse("sineIn"), Ok(Easing::In(Curve::Sine)));
+        assert!(Easing::parse("quint").is_err());
+        assert!(Easing::parse("wobbleIn").is_err());
+    }
+
+    #[test]
+    fn checks_value_sizes() {
+        let target = Target::parse("rotation", 3).unwrap();
+        assert_eq!(target, Target::Rotation(3));
+        assert!(target.value(&[0., 0., 0.]).is_err());
+        assert_eq!(
+            Target::parse("lightColor", 0)
+                .unwrap()
+                .value(&[1., 0.5, 0.]),
+            Ok(TweenValue::Vec3(na::vec3(1., 0.5, 0.)))
+        );
+        assert!(Target::parse("opacity", 0).is_err());
+        assert_eq!(Target::Emissive(2).object(), Some(2));
+        assert_eq!(Target::LightColor(2).object(), None);
+    }
+
+    #[test]
+    fn slerps_quaternions() {
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        let a = TweenValue::Quat(na::quat(0., 0., 0., 1.));
+        let b = TweenValue::Quat(na::quat(0., 0., 1., 0.));
+        match a.lerp(&b, 0.5) {
+            TweenValue::Quat(q) => assert!((q.coords - na::vec4(0., 0., half, half)).amax() < 1e-5),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn plays_after_the_delay() {
+        let mut properties = Properties::default();
+        properties.0.insert(0, 10.);
+        let mut tweens = Tweens::default();
+        let id = tweens.start(
+            Tween {
+                delay: 1.,
+                ..tween(0, 20., 2.)
+            },
+            None,
+        );
+        tweens.update(0.5, &mut properties);
+        assert_close(properties.0[&0], 10.);
+        tweens.update(1.5, &mut properties);
+        assert_close(properties.0[&0], 15.);
+        assert_eq!(tweens.update(1.5, &mut properties), vec![id]);
+        assert_close(properties.0[&0], 20.);
+        assert!(!tweens.contains(id));
+    }
+
+    #[test]
+    fn repeats_and_yoyos() {
+        let mut properties = Properties::default();
+        properties.0.insert(0, 0.);
+        let mut tweens = Tweens::default();
+        tweens.start(
+            Tween {
+                repeat: Some(1),
+                yoyo: true,
+                ..tween(0, 1., 1.)
+            },
+            None,
+        );
+        tweens.update(0., &mut properties);
+        tweens.update(0.25, &mut properties);
+        assert_close(properties.0[&0], 0.25);
+        // On the way back
+        tweens.update(1., &mut properties);
+        assert_close(properties.0[&0], 0.75);
+        assert_eq!(tweens.update(1., &mut properties).len(), 1);
+        assert_close(properties.0[&0], 0.);
+
+        // Forever
+        let id = tweens.start(
+            Tween {
+                repeat: None,
+                ..tween(0, 1., 1.)
+            },
+            None,
+        );
+        tweens.update(0., &mut properties);
+        assert!(tweens.update(100.5, &mut properties).is_empty());
+        assert_close(properties.0[&0], 0.5);
+        assert_eq!(tweens.stop(id), vec![id]);
+    }
+
+    #[test]
+    fn chains_and_stops_chains() {
+        let mut properties = Properties::default();
+        properties.0.insert(0, 0.);
+        let mut tweens = Tweens::default();
+        let first = tweens.start(tween(0, 1., 1.), None);
+        let second = tweens.start(tween(0, 3., 1.), Some(first));
+        tweens.update(0., &mut properties);
+        assert_eq!(tweens.update(1., &mut properties), vec![first]);
+        // The second starts from where the first left off
+        tweens.update(0., &mut properties);
+        tweens.update(0.5, &mut properties);
+        assert_close(properties.0[&0], 2.);
+
+        let third = tweens.start(tween(0, 0., 1.), Some(second));
+        let fourth = tweens.start(tween(0, 0., 1.), Some(third));
+        assert_eq!(tweens.stop(second), vec![second, third, fourth]);
+        assert!(!tweens.contains(fourth));
+        assert!(tweens.is_empty());
+    }
+
+    #[test]
+    fn newer_tweens_take_over() {
+        let mut properties = Properties::default();
+        properties.0.insert(0, 0.);
+        let mut tweens = Tweens::default();
+        let first = tweens.start(tween(0, 1., 1.), None);
+        tweens.update(0.5, &mut properties);
+        let second = tweens.start(tween(0, 0., 1.), None);
+        tweens.update(0.5, &mut properties);
+        assert!(!tweens.contains(first));
+        assert!(tweens.contains(second));
+        assert_close(properties.0[&0], 0.25);
+    }
+
+    #[test]
+    fn waits_for_missing_properties() {
+        let mut properties = Properties::default();
+        let mut tweens = Tweens::default();
+        tweens.start(tween(1, 1., 1.), None);
+        tweens.update(5., &mut properties);
+        properties.0.insert(1, 0.);
+        tweens.update(0., &mut properties);
+        tweens.update(0.5, &mut properties);
+        assert_close(properties.0[&1], 0.5);
+    }
+}
@@ -0,0 +1,181 @@
+// Uniforms of a linked program, reflected once. Setting one the compiler
+// removed (because the shader doesn't use it) does nothing, and values are
+// cached so setting the same value again doesn't upload it. Setters assume the
+// program is the one in use.
+
+use nalgebra_glm as na;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use web_sys::WebGlRenderingContext as GL;
+use web_sys::{WebGlProgram, WebGlUniformLocation};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum UniformValue {
+    // Also for samplers and bools
+    Int(i32),
+    Float(f32),
+    Vec2(na::Vec2),
+    Vec3(na::Vec3),
+    Vec4(na::Vec4),
+    Mat3(na::Mat3),
+    Mat4(na::Mat4),
+}
+
+impl UniformValue {
+    // The GLSL type it's meant for
+    pub fn gl_type(&self) -> u32 {
+        match self {
+            UniformValue::Int(_) => GL::INT,
+            UniformValue::Float(_) => GL::FLOAT,
+            UniformValue::Vec2(_) => GL::FLOAT_VEC2,
+            UniformValue::Vec3(_) => GL::FLOAT_VEC3,
+            UniformValue::Vec4(_) => GL::FLOAT_VEC4,
+            UniformValue::Mat3(_) => GL::FLOAT_MAT3,
+            UniformValue::Mat4(_) => GL::FLOAT_MAT4,
+        }
+    }
+
+    fn upload(&self, gl: &GL, location: &WebGlUniformLocation) {
+        let location = Some(location);
+        match self {
+            UniformValue::Int(x) => gl.uniform1i(location, *x),
+            UniformValue::Float(x) => gl.uniform1f(location, *x),
+            UniformValue::Vec2(v) => gl.uniform2fv_with_f32_array(location, v.as_slice()),
+            UniformValue::Vec3(v) => gl.uniform3fv_with_f32_array(location, v.as_slice()),
+            UniformValue::Vec4(v) => gl.uniform4fv_with_f32_array(location, v.as_slice()),
+            UniformValue::Mat3(m) => {
+                gl.uniform_matrix3fv_with_f32_array(location, false, m.as_slice())
+            }
+            UniformValue::Mat4(m) => {
+                gl.uniform_matrix4fv_with_f32_array(location, false, m.as_slice())
+            }
+        }
+    }
+}
+
+struct Slot {
+    location: WebGlUniformLocation,
+    type_: u32,
+}
+
+pub struct Uniforms {
+    slots: Vec<Slot>,
+    // Last uploaded, per slot
+    values: RefCell<Vec<Option<UniformValue>>>,
+    names: HashMap<String, usize>,
+    // Array elements by array name, index and struct field ("" for arrays of
+    // plain values), so they can be set without building the full name
+    elements: HashMap<String, Vec<HashMap<String, usize>>>,
+}
+
+impl Uniforms {
+    pub fn new(gl: &GL, program: &WebGlProgram) -> Uniforms {
+        let mut uniforms = Uniforms {
+            slots: Vec::new(),
+            values: RefCell::new(Vec::new()),
+            names: HashMap::new(),
+            elements: HashMap::new(),
+        };
+
+        let num_uniforms = gl
+            .get_program_parameter(program, GL::ACTIVE_UNIFORMS)
+            .as_f64()
+            .unwrap() as u32;
+        for i in 0..num_uniforms {
+            let info = gl.get_active_uniform(program, i).unwrap();
+            let name = info.name();
+            // Arrays of plain values are reported once as "name[0]" with their
+            // size, arrays of structs once per element and field
+            let names = match name.strip_suffix("[0]") {
+                Some(base_name) if info.size() > 1 => (0..info.size())
+                    .map(|i| format!("{}[{}]", base_name, i))
+                    .collect(),
+                _ => vec![name.clone()],
+            };
+            for name in names {
+                let location = gl.get_uniform_location(program, &name).unwrap();
+                uniforms.add(name, location, info.type_());
+            }
+        }
+        uniforms
+    }
+
+    fn add(&mut self, name: String, location: WebGlUniformLocation, type_: u32) {
+        let slot = self.slots.len();
+        self.slots.push(Slot { location, type_ });
+        self.values.borrow_mut().push(None);
+
+        if let Some((array_name, index, field)) = split_element(&name) {
+            let elements = self.elements.entry(array_name.to_string()).or_default();
+            if elements.len() <= index {
+                elements.resize_with(index + 1, HashMap::new);
+            }
+            elements[index].insert(field.to_string(), slot);
+            // The first element can also be set by the array name
+            if index == 0 && field.is_empty() {
+                self.names.insert(array_name.to_string(), slot);
+            }
+        }
+        self.names.insert(name, slot);
+    }
+
+    // None if the program doesn't have it
+    pub fn type_of(&self, name: &str) -> Option<u32> {
+        self.names.get(name).map(|&slot| self.slots[slot].type_)
+    }
+
+    pub fn set(&self, gl: &GL, name: &str, value: UniformValue) {
+        if let Some(&slot) = self.names.get(name) {
+            self.set_slot(gl, slot, value);
+        }
+    }
+
+    // set_element("u_lights", 2, "color", ..) sets u_lights[2].color, and
+    // set_element("u_weights", 2, "", ..) sets u_weights[2]
+    pub fn set_element(&self, gl: &GL, name: &str, index: usize, field: &str, value: UniformValue) {
+        let slot = self
+            .elements
+            .get(name)
+            .and_then(|elements| elements.get(index))
+            .and_then(|fields| fields.get(field));
+        if let Some(&slot) = slot {
+            self.set_slot(gl, slot, value);
+        }
+    }
+
+    fn set_slot(&self, gl: &GL, slot: usize, value: UniformValue) {
+        let mut values = self.values.borrow_mut();
+        if values[slot] != Some(value) {
+            value.upload(gl, &self.slots[slot].location);
+            values[slot] = Some(value);
+        }
+    }
+}
+
+// "u_lights[2].color" to ("u_lights", 2, "color"), "u_weights[2]" to
+// ("u_weights", 2, "")
+fn split_element(name: &str) -> Option<(&str, usize, &str)> {
+    let (array_name, rest) = name.split_once('[')?;
+    let (index, field) = rest.split_once(']')?;
+    Some((
+        array_name,
+        index.parse().ok()?,
+        field.strip_prefix('.').unwrap_or(field),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_elements() {
+        assert_eq!(
+            split_element("u_lights[2].color"),
+            Some(("u_lights", 2, "color"))
+        );
+        assert_eq!(split_element("u_weights[10]"), Some(("u_weights", 10, "")));
+        assert_eq!(split_element("u_view"), None);
+        assert_eq!(split_element("u_bad[x]"), None);
+    }
+}
@@ -1,8 +1,9 @@
+use std::cell::Cell;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen::JsCast;
 use wasm_bindgen_futures::JsFuture;
 use web_sys::WebGlRenderingContext as GL;
-use web_sys::{Response, WebGlTexture};
+use web_sys::{Blob, BlobPropertyBag, Response, Url, WebGlTexture};
 
 pub async fn fetch_resource_as_array_buffer(url: &str) -> js_sys::ArrayBuffer {
     let response = JsFuture::from(web_sys::window().unwrap().fetch_with_str(url))
@@ -19,15 +20,80 @@ pub async fn fetch_resource_as_array_buffer(url: &str) -> js_sys::ArrayBuffer {
     buffer
 }
 
+pub async fn fetch_resource_as_bytes(url: &str) -> Vec<u8> {
+    let buffer = fetch_resource_as_array_buffer(url).await;
+    js_sys::Uint8Array::new(&buffer).to_vec()
+}
+
+pub async fn fetch_resource_as_string(url: &str) -> String {
+    String::from_utf8_lossy(&fetch_resource_as_bytes(url).await).into_owned()
+}
+
+// In seconds, the engine clock's default source
+pub fn performance_time() -> f64 {
+    web_sys::window().unwrap().performance().unwrap().now() / 1000.
+}
+
+thread_local! {
+    // Textures filled in so far, for knowing when to draw them
+    static TEXTURES_LOADED: Cell<u32> = const { Cell::new(0) };
+}
+
+pub fn textures_loaded() -> u32 {
+    TEXTURES_LOADED.with(Cell::get)
+}
+
 // Return a new texture filled with placeholder data and then call a
 // JS func that will fetch the source image and fill the texture
 // with new data once it's ready
 //
 // TODO: Do the async work here in Rust, without a call to JS
 pub fn load_texture(gl: &GL, source_url: &str) -> WebGlTexture {
-    let texture = gl.create_texture().unwrap();
+    load_texture_then(gl, source_url, || {})
+}
 
+// Images in memory, like ones in a GLB's buffers, through a blob URL that's
+// revoked once it's loaded
+pub fn load_texture_from_bytes(
+    gl: &GL,
+    bytes: &[u8],
+    mime_type: &str,
+) -> Result<WebGlTexture, String> {
+    let parts = js_sys::Array::of1(&js_sys::Uint8Array::from(bytes));
+    let properties = BlobPropertyBag::new();
+    properties.set_type(mime_type);
+    let blob = Blob::new_with_u8_array_sequence_and_options(&parts, &properties)
+        .map_err(|_| "Couldn't make a blob of the image")?;
+    let url =
+        Url::create_object_url_with_blob(&blob).map_err(|_| "Couldn't make a URL for the image")?;
+    let revoked_url = url.clone();
+    Ok(load_texture_then(gl, &url, move || {
+        let _ = Url::revoke_object_url(&revoked_url);
+    }))
+}
+
+fn load_texture_then(gl: &GL, source_url: &str, done: impl FnOnce() + 'static) -> WebGlTexture {
     // Fill texture with placeholder data
+    let texture = create_solid_texture(gl, [0, 0, 255, 255]);
+
+    // Asynchronously fill texture with image data with call to JS
+    let loaded = JsFuture::from(load_texture_image(gl, &texture, source_url));
+    let source_url = source_url.to_string();
+    wasm_bindgen_futures::spawn_local(async move {
+        match loaded.await {
+            Ok(_) => TEXTURES_LOADED.with(|count| count.set(count.get() + 1)),
+            Err(_) => web_sys::console::error_1(&format!("{}: Failed to load", source_url).into()),
+        }
+        done();
+    });
+
+    texture
+}
+
+// 1x1 texture of a single color
+pub fn create_solid_texture(gl: &GL, color: [u8; 4]) -> WebGlTexture {
+    let texture = gl.create_texture().unwrap();
+
     gl.active_texture(GL::TEXTURE0);
     gl.bind_texture(GL::TEXTURE_2D, Some(&texture));
     gl.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
@@ -39,20 +105,17 @@ pub fn load_texture(gl: &GL, source_url: &str) -> WebGlTexture {
         0,
         GL::RGBA,
         GL::UNSIGNED_BYTE,
-        Some(&[0, 0, 255, 255]),
+        Some(&color),
     )
     .unwrap();
     gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_MAG_FILTER, GL::LINEAR as i32);
     gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_MIN_FILTER, GL::LINEAR as i32);
 
-    // Asynchronously fill texture with image data with call to JS
-    load_texture_image(&gl, &texture, &source_url);
-
     texture
 }
 
 #[wasm_bindgen(raw_module = "../js/index.js")]
 extern "C" {
     #[wasm_bindgen(js_name = loadTextureImage)]
-    fn load_texture_image(gl: &GL, texture: &WebGlTexture, source_url: &str);
+    fn load_texture_image(gl: &GL, texture: &WebGlTexture, source_url: &str) -> js_sys::Promise;
 }
@@ -0,0 +1,96 @@
+// What a camera is drawn into: a rectangle of the canvas or of a render
+// target. Plain Rust, the renderer binds the framebuffers.
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Viewport {
+    // Fractions of the target's size, from its top left
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Viewport {
+    pub const FULL: Viewport = Viewport {
+        x: 0.,
+        y: 0.,
+        width: 1.,
+        height: 1.,
+    };
+
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> Result<Viewport, String> {
+        let fraction = |x: f32| (0. ..=1.).contains(&x);
+        if !(fraction(x) && fraction(y) && width > 0. && height > 0.)
+            || x + width > 1.0001
+            || y + height > 1.0001
+        {
+            return Err(format!(
+                "Viewport {} {} {}x{} isn't inside the target, its sides are fractions of it",
+                x, y, width, height
+            ));
+        }
+        Ok(Viewport {
+            x,
+            y,
+            width,
+            height,
+        })
+    }
+
+    // In GL's window coordinates for a target width x height big: x, y from
+    // the bottom left, width, height. At least a pixel each way.
+    pub fn pixels(&self, width: u32, height: u32) -> (i32, i32, i32, i32) {
+        let (width, height) = (width as f32, height as f32);
+        let left = (self.x * width).round().min(width - 1.);
+        let right = ((self.x + self.width) * width)
+            .round()
+            .clamp(left + 1., width);
+        let top = (self.y * height).round().min(height - 1.);
+        let bottom = ((self.y + self.height) * height)
+            .round()
+            .clamp(top + 1., height);
+        (
+            left as i32,
+            (height - bottom) as i32,
+            (right - left) as i32,
+            (bottom - top) as i32,
+        )
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct View {
+    pub camera: u32,
+    pub viewport: Viewport,
+    // A render target's id, or the canvas when None
+    pub target: Option<u32>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_to_pixels() {
+        assert_eq!(Viewport::FULL.pixels(800, 600), (0, 0, 800, 600));
+        // Top right quarter
+        let viewport = Viewport::new(0.5, 0., 0.5, 0.5).unwrap();
+        assert_eq!(viewport.pixels(800, 600), (400, 300, 400, 300));
+        // Halves of odd sizes meet without a gap
+        let left = Viewport::new(0., 0., 0.5, 1.).unwrap().pixels(801, 600);
+        let right = Viewport::new(0.5, 0., 0.5, 1.).unwrap().pixels(801, 600);
+        assert_eq!(left.0 + left.2, right.0);
+        assert_eq!(right.0 + right.2, 801);
+        let tiny = Viewport::new(0., 0., 0.0001, 0.0001).unwrap();
+        assert_eq!(tiny.pixels(800, 600), (0, 599, 1, 1));
+    }
+
+    #[test]
+    fn must_be_inside_the_target() {
+        assert!(Viewport::new(0.75, 0.75, 0.25, 0.25).is_ok());
+        assert!(Viewport::new(0.75, 0., 0.5, 1.).is_err());
+        assert!(Viewport::new(-0.1, 0., 0.5, 1.).is_err());
+        assert!(Viewport::new(0., 0., 0., 1.).is_err());
+        assert!(Viewport::new(0., f32::NAN, 1., 1.).is_err());
+    }
+}
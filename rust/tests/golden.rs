@@ -0,0 +1,580 @@
+// Golden-image tests for the software rasterizer
+//
+// Run with UPDATE_GOLDEN=1 to regenerate the images in tests/golden after an
+// intentional change to the rendering. Failing renders are written to
+// target/golden so they can be compared with the expected images.
+
+use nalgebra_glm as na;
+use rwgle::animation::Rig;
+use rwgle::mesh_data::{MeshData, MorphTarget, Shading};
+use rwgle::ply::parse_ply;
+use rwgle::primitives;
+use rwgle::rasterizer::*;
+use rwgle::skin::{Node, Skeleton, Skin};
+use rwgle::{AlphaMode, Fog, MaterialFactors};
+use std::path::PathBuf;
+use std::rc::Rc;
+
+const WIDTH: u32 = 320;
+const HEIGHT: u32 = 180;
+
+// Channel differences up to this are treated as rounding noise
+const TOLERANCE: u8 = 2;
+// Fraction of pixels allowed to differ by more than TOLERANCE
+const MAX_MISMATCHED: f32 = 0.001;
+
+fn manifest_path(path: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(path)
+}
+
+// The eight-block ring from Renderer::new, with every block frozen at the
+// same rotation instead of spinning
+fn block_ring(angle: f32) -> Vec<Object> {
+    let cube_mesh = Rc::new(MeshData::load_gltf(manifest_path("../static/cube.gltf")).unwrap());
+    let texture =
+        |name: String| Texture::load(manifest_path(&format!("../static/textures/{}", name)));
+
+    [
+        "yellow_glazed_terracotta",
+        "nether_gold_ore",
+        "redstone_block",
+        "obsidian",
+        "blackstone",
+        "white_glazed_terracotta",
+        "lime_glazed_terracotta",
+        "red_glazed_terracotta",
+    ]
+    .iter()
+    .enumerate()
+    .map(|(pos, texture_name)| {
+        let position_angle = std::f32::consts::PI * 2. / 8. * (pos as f32);
+        Object {
+            model: Rc::new(Model {
+                mesh: Rc::clone(&cube_mesh),
+                color_map: Some(texture(format!("{}.png", texture_name)).unwrap()),
+                specular_map: Some(texture(format!("{}_s.png", texture_name)).unwrap()),
+                normal_map: Some(texture(format!("{}_n.png", texture_name)).unwrap()),
+                factors: MaterialFactors::default(),
+                alpha_mode: AlphaMode::Opaque,
+                double_sided: false,
+                skin: None,
+                rig: None,
+            }),
+            scale: na::scaling(&na::vec3(100., 100., 100.)),
+            rotation: na::rotation(angle, &na::vec3(0., 1., 0.)),
+            translation: na::translation(&na::vec3(
+                600. * position_angle.cos(),
+                0.,
+                -(600. * position_angle.sin()),
+            )),
+            pose: None,
+        }
+    })
+    .collect()
+}
+
+// Same light as Renderer::load_uniforms
+fn default_light() -> Light {
+    Light {
+        position: na::vec3(0., 0., 0.),
+        color: na::vec3(1., 1., 1.),
+        attentuation_coefficient: 0.001,
+        directional: false,
+    }
+}
+
+fn ring_scene(camera: Camera) -> Scene {
+    Scene {
+        camera,
+        lights: vec![default_light()],
+        objects: block_ring(std::f32::consts::PI / 6.),
+        clear_color: na::vec4(0.8, 0.8, 0.8, 1.),
+        fog: None,
+    }
+}
+
+// Cube with a flat normal map and no specular
+fn plain_block(color_map: Texture, alpha_mode: AlphaMode, position: na::Vec3) -> Object {
+    Object {
+        model: Rc::new(Model {
+            mesh: Rc::new(MeshData::load_gltf(manifest_path("../static/cube.gltf")).unwrap()),
+            color_map: Some(color_map),
+            specular_map: Some(Texture::new(1, 1, vec![0, 0, 0, 255])),
+            normal_map: Some(Texture::new(1, 1, vec![128, 128, 255, 255])),
+            factors: MaterialFactors::default(),
+            alpha_mode,
+            double_sided: alpha_mode == AlphaMode::Blend,
+            skin: None,
+            rig: None,
+        }),
+        scale: na::scaling(&na::vec3(60., 60., 60.)),
+        rotation: na::rotation(std::f32::consts::PI / 5., &na::vec3(0., 1., 0.)),
+        translation: na::translation(&position),
+        pose: None,
+    }
+}
+
+fn check_golden(name: &str, scene: &Scene) {
+    let mut framebuffer = Framebuffer::new(WIDTH, HEIGHT);
+    render(scene, &mut framebuffer);
+
+    let golden_path = manifest_path(&format!("tests/golden/{}.png", name));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        framebuffer.save_png(&golden_path).unwrap();
+        return;
+    }
+
+    let golden = image::open(&golden_path)
+        .unwrap_or_else(|e| panic!("Couldn't open {}: {}", golden_path.display(), e))
+        .to_rgba8();
+    assert_eq!((golden.width(), golden.height()), (WIDTH, HEIGHT));
+
+    let mismatched = golden
+        .as_raw()
+        .chunks_exact(4)
+        .zip(framebuffer.pixels().chunks_exact(4))
+        .filter(|(expected, actual)| {
+            expected
+                .iter()
+                .zip(actual.iter())
+                .any(|(e, a)| (*e as i16 - *a as i16).abs() > TOLERANCE as i16)
+        })
+        .count();
+
+    if mismatched as f32 > MAX_MISMATCHED * (WIDTH * HEIGHT) as f32 {
+        let actual_path = manifest_path(&format!("target/golden/{}.png", name));
+        std::fs::create_dir_all(actual_path.parent().unwrap()).unwrap();
+        framebuffer.save_png(&actual_path).unwrap();
+        panic!(
+            "{} pixels differ from {}, render saved to {}",
+            mismatched,
+            golden_path.display(),
+            actual_path.display()
+        );
+    }
+}
+
+#[test]
+fn ring_front() {
+    let camera = Camera::new(na::vec3(0., 0., 0.), na::vec3(0., 0., -1.));
+    check_golden("ring_front", &ring_scene(camera));
+}
+
+#[test]
+fn ring_diagonal() {
+    let camera = Camera::new(na::vec3(0., 0., 0.), na::vec3(0.71, 0., -0.71));
+    check_golden("ring_diagonal", &ring_scene(camera));
+}
+
+#[test]
+fn alpha_modes() {
+    let mut objects = block_ring(std::f32::consts::PI / 6.);
+
+    // Checkerboard with transparent squares, alpha tested
+    let checkerboard = (0..16)
+        .flat_map(|i| {
+            if (i % 4 + i / 4) % 2 == 0 {
+                vec![40, 160, 220, 255]
+            } else {
+                vec![40, 160, 220, 0]
+            }
+        })
+        .collect();
+    objects.push(plain_block(
+        Texture::new(4, 4, checkerboard),
+        AlphaMode::Mask(0.5),
+        na::vec3(-120., -30., -380.),
+    ));
+
+    // Two overlapping translucent blocks in front of the ring
+    objects.push(plain_block(
+        Texture::new(1, 1, vec![255, 255, 255, 90]),
+        AlphaMode::Blend,
+        na::vec3(40., 10., -300.),
+    ));
+    objects.push(plain_block(
+        Texture::new(1, 1, vec![60, 220, 60, 140]),
+        AlphaMode::Blend,
+        na::vec3(110., 30., -420.),
+    ));
+
+    let scene = Scene {
+        camera: Camera::new(na::vec3(0., 0., 0.), na::vec3(0., 0., -1.)),
+        lights: vec![default_light()],
+        objects,
+        clear_color: na::vec4(0.8, 0.8, 0.8, 1.),
+        fog: None,
+    };
+    check_golden("alpha_modes", &scene);
+}
+
+#[test]
+fn ring_overview() {
+    let position = na::vec3(0., 900., 900.);
+    let camera = Camera::new(position, -position);
+    check_golden("ring_overview", &ring_scene(camera));
+}
+
+#[test]
+fn fog() {
+    let position = na::vec3(0., 900., 900.);
+    let scene = Scene {
+        fog: Some(Fog {
+            color: na::vec3(0.8, 0.8, 0.8),
+            near: 900.,
+            far: 1800.,
+        }),
+        ..ring_scene(Camera::new(position, -position))
+    };
+    check_golden("fog", &scene);
+}
+
+#[test]
+fn primitives() {
+    // Checkerboard so the texture mapping shows
+    let checkerboard = (0..64)
+        .flat_map(|i| {
+            if (i % 8 + i / 8) % 2 == 0 {
+                vec![230, 120, 40, 255]
+            } else {
+                vec![250, 240, 220, 255]
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let meshes = vec![
+        primitives::plane(1.6, 1.6, 2, 2),
+        primitives::cuboid(na::vec3(1.2, 1.2, 1.2), 1),
+        primitives::uv_sphere(0.8, 24, 12),
+        primitives::icosphere(0.8, 2),
+        primitives::cylinder(0.6, 1.4, 24, 1),
+        primitives::cone(0.7, 1.4, 24, 1),
+        primitives::torus(0.6, 0.2, 24, 12),
+        primitives::capsule(0.4, 0.8, 24, 6),
+    ];
+    let objects = meshes
+        .into_iter()
+        .enumerate()
+        .map(|(i, mesh)| Object {
+            model: Rc::new(Model {
+                mesh: Rc::new(mesh),
+                color_map: Some(Texture::new(8, 8, checkerboard.clone())),
+                specular_map: Some(Texture::new(1, 1, vec![0, 0, 0, 255])),
+                normal_map: Some(Texture::new(1, 1, vec![128, 128, 255, 255])),
+                factors: MaterialFactors::default(),
+                alpha_mode: AlphaMode::Opaque,
+                double_sided: false,
+                skin: None,
+                rig: None,
+            }),
+            scale: na::scaling(&na::vec3(50., 50., 50.)),
+            rotation: na::rotation(0.5, &na::vec3(1., 0., 0.)),
+            translation: na::translation(&na::vec3(
+                -270. + 180. * (i % 4) as f32,
+                if i < 4 { 85. } else { -85. },
+                -350.,
+            )),
+            pose: None,
+        })
+        .collect();
+
+    let scene = Scene {
+        camera: Camera::new(na::vec3(0., 0., 0.), na::vec3(0., 0., -1.)),
+        lights: vec![Light {
+            position: na::vec3(0., 300., 0.),
+            ..default_light()
+        }],
+        objects,
+        clear_color: na::vec4(0.8, 0.8, 0.8, 1.),
+        fog: None,
+    };
+    check_golden("primitives", &scene);
+}
+
+#[test]
+fn vertex_colors() {
+    // Cube with a different color at every corner, as an ASCII PLY
+    let mut ply = "ply\nformat ascii 1.0\n\
+        element vertex 8\n\
+        property float x\nproperty float y\nproperty float z\n\
+        property uchar red\nproperty uchar green\nproperty uchar blue\n\
+        element face 6\nproperty list uchar int vertex_indices\n\
+        end_header\n"
+        .to_string();
+    for i in 0..8 {
+        let (x, y, z) = (i & 1, (i >> 1) & 1, (i >> 2) & 1);
+        ply += &format!(
+            "{} {} {} {} {} {}\n",
+            x as f32 - 0.5,
+            y as f32 - 0.5,
+            z as f32 - 0.5,
+            x * 255,
+            y * 255,
+            z * 255
+        );
+    }
+    ply += "4 0 2 3 1\n4 4 5 7 6\n4 0 1 5 4\n4 2 6 7 3\n4 0 4 6 2\n4 1 3 7 5\n";
+
+    let objects = [(Shading::Flat, -80.), (Shading::Smooth, 80.)]
+        .iter()
+        .map(|&(shading, x)| Object {
+            model: Rc::new(Model {
+                mesh: Rc::new(parse_ply(ply.as_bytes(), shading).unwrap()),
+                color_map: Some(Texture::new(1, 1, vec![255, 255, 255, 255])),
+                specular_map: Some(Texture::new(1, 1, vec![0, 0, 0, 255])),
+                normal_map: Some(Texture::new(1, 1, vec![128, 128, 255, 255])),
+                factors: MaterialFactors::default(),
+                alpha_mode: AlphaMode::Opaque,
+                double_sided: false,
+                skin: None,
+                rig: None,
+            }),
+            scale: na::scaling(&na::vec3(90., 90., 90.)),
+            rotation: na::rotation(0.6, &na::vec3(1., 1., 0.)),
+            translation: na::translation(&na::vec3(x, 0., -300.)),
+            pose: None,
+        })
+        .collect();
+
+    let scene = Scene {
+        camera: Camera::new(na::vec3(0., 0., 0.), na::vec3(0., 0., -1.)),
+        lights: vec![Light {
+            position: na::vec3(0., 0., 1.),
+            directional: true,
+            ..default_light()
+        }],
+        objects,
+        clear_color: na::vec4(0.8, 0.8, 0.8, 1.),
+        fog: None,
+    };
+    check_golden("vertex_colors", &scene);
+}
+
+#[test]
+fn solid_colors() {
+    // No maps, just the factors: matte, shiny, glossy, emissive and translucent
+    let sphere = Rc::new(primitives::uv_sphere(0.8, 32, 16));
+    let factors = [
+        MaterialFactors {
+            base_color: na::vec4(0.8, 0.2, 0.2, 1.),
+            specular_strength: 0.,
+            ..MaterialFactors::default()
+        },
+        MaterialFactors {
+            base_color: na::vec4(0.2, 0.7, 0.3, 1.),
+            shininess: 10.,
+            ..MaterialFactors::default()
+        },
+        MaterialFactors {
+            base_color: na::vec4(0.2, 0.3, 0.8, 1.),
+            specular_strength: 0.6,
+            shininess: 200.,
+            ..MaterialFactors::default()
+        },
+        MaterialFactors {
+            base_color: na::vec4(0.1, 0.1, 0.1, 1.),
+            specular_strength: 0.,
+            emissive: na::vec3(0.9, 0.7, 0.2),
+            ..MaterialFactors::default()
+        },
+        MaterialFactors {
+            base_color: na::vec4(0.9, 0.9, 0.9, 0.4),
+            ..MaterialFactors::default()
+        },
+    ];
+    let objects = factors
+        .iter()
+        .enumerate()
+        .map(|(i, &factors)| Object {
+            model: Rc::new(Model {
+                mesh: Rc::clone(&sphere),
+                color_map: None,
+                specular_map: None,
+                normal_map: None,
+                factors,
+                alpha_mode: factors.alpha_mode(),
+                double_sided: false,
+                skin: None,
+                rig: None,
+            }),
+            scale: na::scaling(&na::vec3(50., 50., 50.)),
+            rotation: na::identity(),
+            translation: na::translation(&na::vec3(-240. + 120. * i as f32, 0., -350.)),
+            pose: None,
+        })
+        .collect();
+
+    let scene = Scene {
+        camera: Camera::new(na::vec3(0., 0., 0.), na::vec3(0., 0., -1.)),
+        lights: vec![Light {
+            position: na::vec3(-200., 200., 0.),
+            ..default_light()
+        }],
+        objects,
+        clear_color: na::vec4(0.8, 0.8, 0.8, 1.),
+        fog: None,
+    };
+    check_golden("solid_colors", &scene);
+}
+
+#[test]
+fn skinning() {
+    // Tall cylinder with a joint at the bottom and one halfway up, blended
+    // around the middle. Drawn in the rest pose and bent.
+    let mut cylinder = primitives::cylinder(0.2, 2., 32, 16);
+    cylinder.joints = [0., 1., 0., 0.].repeat(cylinder.num_vertices());
+    cylinder.weights = cylinder
+        .positions
+        .chunks_exact(3)
+        .flat_map(|p| {
+            let t = ((p[1] + 0.25) / 0.5).clamp(0., 1.);
+            vec![1. - t, t, 0., 0.]
+        })
+        .collect();
+    let joint = |parent, translation| Node {
+        parent,
+        translation,
+        rotation: na::quat_identity(),
+        scale: na::vec3(1., 1., 1.),
+        weights: Vec::new(),
+    };
+    let rest_pose = Skeleton {
+        nodes: vec![
+            joint(None, na::vec3(0., -1., 0.)),
+            joint(Some(0), na::vec3(0., 1., 0.)),
+        ],
+    };
+    let mut bent = rest_pose.clone();
+    bent.nodes[1].rotation = na::quat_angle_axis(1., &na::vec3(0., 0., 1.));
+
+    let model = Rc::new(Model {
+        mesh: Rc::new(cylinder),
+        color_map: None,
+        specular_map: None,
+        normal_map: None,
+        factors: MaterialFactors {
+            base_color: na::vec4(0.9, 0.6, 0.3, 1.),
+            ..MaterialFactors::default()
+        },
+        alpha_mode: AlphaMode::Opaque,
+        double_sided: false,
+        skin: Some(Rc::new(Skin {
+            joints: vec![0, 1],
+            inverse_bind_matrices: vec![na::translation(&na::vec3(0., 1., 0.)), na::identity()],
+        })),
+        // Skinned, so the mesh node's transform doesn't matter
+        rig: Some(Rc::new(Rig {
+            rest_pose,
+            mesh_node: 0,
+            clips: Vec::new(),
+        })),
+    });
+    let objects = [(None, -100.), (Some(bent), 100.)]
+        .iter()
+        .map(|(pose, x)| Object {
+            model: Rc::clone(&model),
+            scale: na::scaling(&na::vec3(80., 80., 80.)),
+            rotation: na::identity(),
+            translation: na::translation(&na::vec3(*x, 0., -350.)),
+            pose: pose.clone(),
+        })
+        .collect();
+
+    let scene = Scene {
+        camera: Camera::new(na::vec3(0., 0., 0.), na::vec3(0., 0., -1.)),
+        lights: vec![Light {
+            position: na::vec3(-200., 200., 0.),
+            ..default_light()
+        }],
+        objects,
+        clear_color: na::vec4(0.8, 0.8, 0.8, 1.),
+        fog: None,
+    };
+    check_golden("skinning", &scene);
+}
+
+#[test]
+fn morph_targets() {
+    // Sphere with a target stretching it up and one bulging its right side,
+    // drawn at rest, stretched, and half stretched with the bulge
+    let mut sphere = primitives::uv_sphere(0.8, 32, 16);
+    let vertices = sphere.num_vertices();
+    let (mut stretch, mut stretch_normals, mut bulge) = (Vec::new(), Vec::new(), Vec::new());
+    for i in 0..vertices {
+        let p = na::make_vec3(&sphere.positions[i * 3..i * 3 + 3]);
+        let n = na::make_vec3(&sphere.normals[i * 3..i * 3 + 3]);
+        stretch.extend_from_slice(&[0., p.y * 0.6, 0.]);
+        // Normals of the stretched sphere, as deltas
+        let stretched = na::normalize(&na::vec3(n.x, n.y / (1.6 * 1.6), n.z)) - n;
+        stretch_normals.extend_from_slice(stretched.as_slice());
+        let push = n * 0.3 * p.x.max(0.) / 0.8;
+        bulge.extend_from_slice(push.as_slice());
+    }
+    sphere.morph_targets = vec![
+        MorphTarget {
+            positions: stretch,
+            normals: stretch_normals,
+            tangents: Vec::new(),
+        },
+        MorphTarget {
+            positions: bulge,
+            normals: Vec::new(),
+            tangents: Vec::new(),
+        },
+    ];
+
+    let rest_pose = Skeleton {
+        nodes: vec![Node {
+            parent: None,
+            translation: na::vec3(0., 0., 0.),
+            rotation: na::quat_identity(),
+            scale: na::vec3(1., 1., 1.),
+            weights: vec![0., 0.],
+        }],
+    };
+    let model = Rc::new(Model {
+        mesh: Rc::new(sphere),
+        color_map: None,
+        specular_map: None,
+        normal_map: None,
+        factors: MaterialFactors {
+            base_color: na::vec4(0.3, 0.6, 0.9, 1.),
+            ..MaterialFactors::default()
+        },
+        alpha_mode: AlphaMode::Opaque,
+        double_sided: false,
+        skin: None,
+        rig: Some(Rc::new(Rig {
+            rest_pose: rest_pose.clone(),
+            mesh_node: 0,
+            clips: Vec::new(),
+        })),
+    });
+    let objects = [[0., 0.], [1., 0.], [0.5, 1.]]
+        .iter()
+        .enumerate()
+        .map(|(i, weights)| {
+            let mut pose = rest_pose.clone();
+            pose.nodes[0].weights = weights.to_vec();
+            Object {
+                model: Rc::clone(&model),
+                scale: na::scaling(&na::vec3(60., 60., 60.)),
+                rotation: na::identity(),
+                translation: na::translation(&na::vec3(-160. + 160. * i as f32, 0., -400.)),
+                pose: Some(pose),
+            }
+        })
+        .collect();
+
+    let scene = Scene {
+        camera: Camera::new(na::vec3(0., 0., 0.), na::vec3(0., 0., -1.)),
+        lights: vec![Light {
+            position: na::vec3(-200., 200., 0.),
+            ..default_light()
+        }],
+        objects,
+        clear_color: na::vec4(0.8, 0.8, 0.8, 1.),
+        fog: None,
+    };
+    check_golden("morph_targets", &scene);
+}
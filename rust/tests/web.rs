@@ -0,0 +1,57 @@
+// Browser tests for the JS API, which needs a WebGL context
+#![cfg(target_arch = "wasm32")]
+
+use rwgle::RustWebGLEngine;
+use wasm_bindgen::JsValue;
+use wasm_bindgen_test::*;
+
+wasm_bindgen_test_configure!(run_in_browser);
+
+const VERTEX_SOURCE: &str = "
+attribute vec3 a_position;
+void main() {
+    gl_Position = vec4(a_position, 1.);
+}
+";
+
+const FRAGMENT_SOURCE: &str = "
+precision mediump float;
+uniform sampler2D u_image;
+void main() {
+    gl_FragColor = texture2D(u_image, vec2(0.5));
+}
+";
+
+async fn engine() -> RustWebGLEngine {
+    let canvas = web_sys::window()
+        .unwrap()
+        .document()
+        .unwrap()
+        .create_element("canvas")
+        .unwrap();
+    RustWebGLEngine::new(canvas.into(), JsValue::UNDEFINED)
+        .await
+        .unwrap()
+}
+
+#[wasm_bindgen_test]
+async fn set_uniform_texture_on_a_shader_material() {
+    let engine = engine().await;
+    let material = engine
+        .create_shader_material(VERTEX_SOURCE, FRAGMENT_SOURCE)
+        .unwrap();
+    engine
+        .set_uniform_texture(material, "u_image", "textures/missing.png")
+        .unwrap();
+}
+
+#[wasm_bindgen_test]
+async fn set_uniform_texture_on_an_unknown_uniform() {
+    let engine = engine().await;
+    let material = engine
+        .create_shader_material(VERTEX_SOURCE, FRAGMENT_SOURCE)
+        .unwrap();
+    assert!(engine
+        .set_uniform_texture(material, "u_missing", "textures/missing.png")
+        .is_err());
+}